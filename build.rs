@@ -0,0 +1,4 @@
+fn main() {
+    // embed_migrations! doesn't track the migration files itself
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS music_files;
+DROP TABLE IF EXISTS file_source;
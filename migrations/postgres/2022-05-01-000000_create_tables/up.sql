@@ -0,0 +1,15 @@
+-- Databases set up with the old sql/create_tables.sql script already have
+-- these tables, so only create them when they are missing.
+CREATE TABLE IF NOT EXISTS file_source (
+    id SERIAL PRIMARY KEY,
+    url TEXT NOT NULL,
+    compressed_file_name TEXT NOT NULL,
+    time_inserted TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS music_files (
+    id SERIAL PRIMARY KEY,
+    compressed_file_name TEXT NOT NULL,
+    individual_file_name TEXT NOT NULL,
+    instrument TEXT NOT NULL
+);
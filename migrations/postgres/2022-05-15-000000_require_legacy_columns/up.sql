@@ -0,0 +1,22 @@
+-- Tables created by the old sql/create_tables.sql script left these columns
+-- nullable, which create_tables skips over. Fill in the rows written without
+-- them and make the columns NOT NULL, as the schema expects.
+UPDATE file_source SET url = '' WHERE url IS NULL;
+UPDATE file_source SET compressed_file_name = '' WHERE compressed_file_name IS NULL;
+-- rows without a time go before everything that has one
+UPDATE file_source SET time_inserted = TIMESTAMP 'epoch' WHERE time_inserted IS NULL;
+
+ALTER TABLE file_source
+    ALTER COLUMN url SET NOT NULL,
+    ALTER COLUMN compressed_file_name SET NOT NULL,
+    ALTER COLUMN time_inserted SET NOT NULL;
+
+UPDATE music_files SET compressed_file_name = '' WHERE compressed_file_name IS NULL;
+UPDATE music_files SET individual_file_name = '' WHERE individual_file_name IS NULL;
+-- what samples without an instrument keyword in their name are labelled
+UPDATE music_files SET instrument = 'unspecified' WHERE instrument IS NULL;
+
+ALTER TABLE music_files
+    ALTER COLUMN compressed_file_name SET NOT NULL,
+    ALTER COLUMN individual_file_name SET NOT NULL,
+    ALTER COLUMN instrument SET NOT NULL;
@@ -0,0 +1,3 @@
+-- The file name normalisation done by up.sql is not reverted.
+DROP INDEX IF EXISTS music_files_file_source_id_idx;
+ALTER TABLE music_files DROP COLUMN file_source_id;
@@ -0,0 +1,32 @@
+-- Link every sample to the archive it was extracted from. The last version
+-- of sql/create_tables.sql already created the column, so databases set up
+-- with it only get the backfill.
+ALTER TABLE music_files ADD COLUMN IF NOT EXISTS file_source_id INTEGER REFERENCES file_source (id);
+
+-- `upload` used to store the full path of the archive; keep only the file name.
+UPDATE music_files
+SET compressed_file_name = regexp_replace(compressed_file_name, '^.*/', '');
+
+-- Providers used to store the archive name without its extension (Drive the
+-- file id, Dropbox the sanitised title, Mediafire the stripped file name), so
+-- match on the archive stem and take the most recent download of that name.
+UPDATE music_files m
+SET file_source_id = f.id
+FROM (
+    SELECT DISTINCT ON (compressed_file_name) id, compressed_file_name
+    FROM file_source
+    ORDER BY compressed_file_name, id DESC
+) f
+WHERE m.file_source_id IS NULL
+  -- rows of the old script written without a name can't be matched
+  AND m.compressed_file_name <> ''
+  AND (f.compressed_file_name = m.compressed_file_name
+       OR f.compressed_file_name = regexp_replace(m.compressed_file_name, '\.(zip|rar)$', ''));
+
+-- Bring the matched sources in line with the on-disk file name used from now on.
+UPDATE file_source f
+SET compressed_file_name = m.compressed_file_name
+FROM music_files m
+WHERE m.file_source_id = f.id;
+
+CREATE INDEX IF NOT EXISTS music_files_file_source_id_idx ON music_files (file_source_id);
@@ -0,0 +1,13 @@
+DROP INDEX IF EXISTS music_files_instrument_idx;
+
+ALTER TABLE music_files
+    DROP COLUMN format,
+    DROP COLUMN duration_seconds,
+    DROP COLUMN sample_rate,
+    DROP COLUMN channels,
+    DROP COLUMN bit_depth;
+
+ALTER TABLE file_source
+    DROP COLUMN provider,
+    DROP COLUMN subreddit,
+    DROP COLUMN posted_at;
@@ -0,0 +1,34 @@
+ALTER TABLE file_source
+    ADD COLUMN provider TEXT NOT NULL DEFAULT 'unknown',
+    ADD COLUMN subreddit TEXT,
+    ADD COLUMN posted_at TIMESTAMP;
+
+UPDATE file_source
+SET provider = CASE
+        WHEN url LIKE '%drive.google.com%' THEN 'google_drive'
+        WHEN url LIKE '%dropbox.com%' THEN 'dropbox'
+        WHEN url LIKE '%mediafire.com%' THEN 'mediafire'
+        WHEN url LIKE 'file://%' THEN 'local'
+        ELSE 'unknown'
+    END,
+    -- every crawl so far has been of r/drumkits
+    subreddit = CASE WHEN url LIKE 'file://%' THEN NULL ELSE 'drumkits' END,
+    posted_at = time_inserted;
+
+ALTER TABLE file_source
+    ALTER COLUMN provider DROP DEFAULT,
+    ALTER COLUMN posted_at SET NOT NULL;
+
+ALTER TABLE music_files
+    ADD COLUMN format TEXT NOT NULL DEFAULT '',
+    ADD COLUMN duration_seconds DOUBLE PRECISION,
+    ADD COLUMN sample_rate INTEGER,
+    ADD COLUMN channels INTEGER,
+    ADD COLUMN bit_depth INTEGER;
+
+UPDATE music_files
+SET format = COALESCE(lower(substring(individual_file_name FROM '\.([^./]+)$')), '');
+
+ALTER TABLE music_files ALTER COLUMN format DROP DEFAULT;
+
+CREATE INDEX music_files_instrument_idx ON music_files (instrument);
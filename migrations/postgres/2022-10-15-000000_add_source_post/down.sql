@@ -0,0 +1,3 @@
+ALTER TABLE file_source
+    DROP COLUMN post_url,
+    DROP COLUMN post_title;
@@ -0,0 +1,5 @@
+-- Reddit post an archive was linked from, so that dataset records can point
+-- back at their source. Rows downloaded before this have no post recorded.
+ALTER TABLE file_source
+    ADD COLUMN post_url TEXT,
+    ADD COLUMN post_title TEXT;
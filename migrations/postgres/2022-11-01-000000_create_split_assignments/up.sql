@@ -0,0 +1,10 @@
+-- Which split each kit was put in, per named split, so that assignments stay
+-- the same as new kits are added.
+CREATE TABLE split_assignments (
+    id SERIAL PRIMARY KEY,
+    split_name TEXT NOT NULL,
+    compressed_file_name TEXT NOT NULL,
+    split TEXT NOT NULL,
+    time_inserted TIMESTAMP NOT NULL,
+    UNIQUE (split_name, compressed_file_name)
+);
@@ -0,0 +1,5 @@
+-- Near-duplicate samples found by `dedupe --mark` point at the copy that is
+-- kept; NULL for unique samples and for the kept copy itself.
+ALTER TABLE music_files ADD COLUMN duplicate_of INTEGER REFERENCES music_files (id) ON DELETE SET NULL;
+
+CREATE INDEX music_files_duplicate_of_idx ON music_files (duplicate_of);
@@ -0,0 +1 @@
+ALTER TABLE file_source DROP COLUMN original_url;
@@ -0,0 +1,4 @@
+-- Link as it was posted, before shorteners and redirects were followed to
+-- the `url` the archive was downloaded from. Rows from before this, and
+-- local archives, have none recorded.
+ALTER TABLE file_source ADD COLUMN original_url TEXT;
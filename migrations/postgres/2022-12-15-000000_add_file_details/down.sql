@@ -0,0 +1,5 @@
+ALTER TABLE file_source
+    DROP COLUMN size_bytes,
+    DROP COLUMN mime_type,
+    DROP COLUMN md5_checksum,
+    DROP COLUMN source_modified_at;
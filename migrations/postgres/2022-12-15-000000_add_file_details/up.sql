@@ -0,0 +1,8 @@
+-- Details of the archive as the host reports them, where it does. Google
+-- Drive gives all of these; the checksum is of the downloaded bytes, which
+-- are checked against Drive's before the row is written.
+ALTER TABLE file_source
+    ADD COLUMN size_bytes BIGINT,
+    ADD COLUMN mime_type TEXT,
+    ADD COLUMN md5_checksum TEXT,
+    ADD COLUMN source_modified_at TIMESTAMP;
@@ -0,0 +1,10 @@
+-- Kits sharing a name are back to sharing one assignment, the first one made.
+ALTER TABLE split_assignments DROP CONSTRAINT split_assignments_split_name_file_source_id_key;
+DELETE FROM split_assignments s
+USING split_assignments t
+WHERE s.split_name = t.split_name
+  AND s.compressed_file_name = t.compressed_file_name
+  AND s.id > t.id;
+ALTER TABLE split_assignments DROP COLUMN file_source_id;
+ALTER TABLE split_assignments ADD CONSTRAINT split_assignments_split_name_compressed_file_name_key
+    UNIQUE (split_name, compressed_file_name);
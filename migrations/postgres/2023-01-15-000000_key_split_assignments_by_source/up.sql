@@ -0,0 +1,20 @@
+-- Kits are told apart by their file_source row rather than their archive
+-- name, as different downloads can share a name.
+ALTER TABLE split_assignments DROP CONSTRAINT split_assignments_split_name_compressed_file_name_key;
+ALTER TABLE split_assignments ADD COLUMN file_source_id INTEGER REFERENCES file_source (id);
+
+-- An assignment made by name covered every kit of that name, so each of them
+-- keeps the split it was in.
+INSERT INTO split_assignments (split_name, compressed_file_name, split, time_inserted, file_source_id)
+SELECT s.split_name, s.compressed_file_name, s.split, s.time_inserted, f.id
+FROM split_assignments s
+JOIN file_source f ON f.compressed_file_name = s.compressed_file_name
+WHERE s.file_source_id IS NULL
+ORDER BY s.id, f.id;
+
+-- The rows by name, and any kit that is gone from file_source
+DELETE FROM split_assignments WHERE file_source_id IS NULL;
+
+ALTER TABLE split_assignments ALTER COLUMN file_source_id SET NOT NULL;
+ALTER TABLE split_assignments ADD CONSTRAINT split_assignments_split_name_file_source_id_key
+    UNIQUE (split_name, file_source_id);
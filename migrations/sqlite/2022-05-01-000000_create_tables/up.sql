@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS file_source (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    url TEXT NOT NULL,
+    compressed_file_name TEXT NOT NULL,
+    time_inserted TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS music_files (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    compressed_file_name TEXT NOT NULL,
+    individual_file_name TEXT NOT NULL,
+    instrument TEXT NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Only Postgres databases were ever set up with the old
+-- sql/create_tables.sql script. SQLite ones have only been created by
+-- create_tables, whose columns are already NOT NULL.
+SELECT 1;
@@ -0,0 +1,8 @@
+-- SQLite stores postdate the free-text archive names, so there is nothing to
+-- backfill here (see the postgres migration of the same name). They also
+-- postdate sql/create_tables.sql, which was only ever run against Postgres,
+-- so the column can't be there already; SQLite has no ADD COLUMN IF NOT
+-- EXISTS to guard it with anyway.
+ALTER TABLE music_files ADD COLUMN file_source_id INTEGER REFERENCES file_source (id);
+
+CREATE INDEX music_files_file_source_id_idx ON music_files (file_source_id);
@@ -0,0 +1,11 @@
+DROP INDEX IF EXISTS music_files_instrument_idx;
+
+ALTER TABLE music_files DROP COLUMN format;
+ALTER TABLE music_files DROP COLUMN duration_seconds;
+ALTER TABLE music_files DROP COLUMN sample_rate;
+ALTER TABLE music_files DROP COLUMN channels;
+ALTER TABLE music_files DROP COLUMN bit_depth;
+
+ALTER TABLE file_source DROP COLUMN provider;
+ALTER TABLE file_source DROP COLUMN subreddit;
+ALTER TABLE file_source DROP COLUMN posted_at;
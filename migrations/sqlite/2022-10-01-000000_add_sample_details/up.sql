@@ -0,0 +1,14 @@
+-- sqlite can only add NOT NULL columns with a constant default
+ALTER TABLE file_source ADD COLUMN provider TEXT NOT NULL DEFAULT 'unknown';
+ALTER TABLE file_source ADD COLUMN subreddit TEXT;
+ALTER TABLE file_source ADD COLUMN posted_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+
+UPDATE file_source SET posted_at = time_inserted;
+
+ALTER TABLE music_files ADD COLUMN format TEXT NOT NULL DEFAULT '';
+ALTER TABLE music_files ADD COLUMN duration_seconds DOUBLE;
+ALTER TABLE music_files ADD COLUMN sample_rate INTEGER;
+ALTER TABLE music_files ADD COLUMN channels INTEGER;
+ALTER TABLE music_files ADD COLUMN bit_depth INTEGER;
+
+CREATE INDEX music_files_instrument_idx ON music_files (instrument);
@@ -0,0 +1,2 @@
+ALTER TABLE file_source DROP COLUMN post_url;
+ALTER TABLE file_source DROP COLUMN post_title;
@@ -0,0 +1,2 @@
+ALTER TABLE file_source ADD COLUMN post_url TEXT;
+ALTER TABLE file_source ADD COLUMN post_title TEXT;
@@ -0,0 +1,8 @@
+CREATE TABLE split_assignments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    split_name TEXT NOT NULL,
+    compressed_file_name TEXT NOT NULL,
+    split TEXT NOT NULL,
+    time_inserted TIMESTAMP NOT NULL,
+    UNIQUE (split_name, compressed_file_name)
+);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS music_files_duplicate_of_idx;
+ALTER TABLE music_files DROP COLUMN duplicate_of;
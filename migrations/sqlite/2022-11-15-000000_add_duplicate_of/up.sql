@@ -0,0 +1,3 @@
+ALTER TABLE music_files ADD COLUMN duplicate_of INTEGER REFERENCES music_files (id) ON DELETE SET NULL;
+
+CREATE INDEX music_files_duplicate_of_idx ON music_files (duplicate_of);
@@ -0,0 +1,4 @@
+ALTER TABLE file_source DROP COLUMN size_bytes;
+ALTER TABLE file_source DROP COLUMN mime_type;
+ALTER TABLE file_source DROP COLUMN md5_checksum;
+ALTER TABLE file_source DROP COLUMN source_modified_at;
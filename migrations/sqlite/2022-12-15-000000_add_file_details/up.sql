@@ -0,0 +1,4 @@
+ALTER TABLE file_source ADD COLUMN size_bytes BIGINT;
+ALTER TABLE file_source ADD COLUMN mime_type TEXT;
+ALTER TABLE file_source ADD COLUMN md5_checksum TEXT;
+ALTER TABLE file_source ADD COLUMN source_modified_at TIMESTAMP;
@@ -0,0 +1,21 @@
+CREATE TABLE download_jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    post_id TEXT NOT NULL,
+    url TEXT NOT NULL,
+    provider TEXT,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    error_kind TEXT,
+    url_domain TEXT NOT NULL,
+    subreddit TEXT NOT NULL,
+    post_title TEXT NOT NULL,
+    post_url TEXT NOT NULL,
+    posted_at TIMESTAMP NOT NULL,
+    score DOUBLE NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    UNIQUE (post_id, url)
+);
+
+CREATE INDEX download_jobs_status_idx ON download_jobs (status, id);
@@ -0,0 +1,17 @@
+-- Kits sharing a name are back to sharing one assignment, the first one made.
+CREATE TABLE split_assignments_by_name (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    split_name TEXT NOT NULL,
+    compressed_file_name TEXT NOT NULL,
+    split TEXT NOT NULL,
+    time_inserted TIMESTAMP NOT NULL,
+    UNIQUE (split_name, compressed_file_name)
+);
+
+INSERT OR IGNORE INTO split_assignments_by_name (split_name, compressed_file_name, split, time_inserted)
+SELECT split_name, compressed_file_name, split, time_inserted
+FROM split_assignments
+ORDER BY id;
+
+DROP TABLE split_assignments;
+ALTER TABLE split_assignments_by_name RENAME TO split_assignments;
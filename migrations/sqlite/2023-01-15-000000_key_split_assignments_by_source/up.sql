@@ -0,0 +1,23 @@
+-- Kits are told apart by their file_source row rather than their archive
+-- name (see the postgres migration of the same name). SQLite can't drop the
+-- old unique constraint, so the table is rebuilt.
+CREATE TABLE split_assignments_by_source (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    split_name TEXT NOT NULL,
+    compressed_file_name TEXT NOT NULL,
+    split TEXT NOT NULL,
+    time_inserted TIMESTAMP NOT NULL,
+    file_source_id INTEGER NOT NULL REFERENCES file_source (id),
+    UNIQUE (split_name, file_source_id)
+);
+
+-- An assignment made by name covered every kit of that name, so each of them
+-- keeps the split it was in. Kits that are gone from file_source are dropped.
+INSERT INTO split_assignments_by_source (split_name, compressed_file_name, split, time_inserted, file_source_id)
+SELECT s.split_name, s.compressed_file_name, s.split, s.time_inserted, f.id
+FROM split_assignments s
+JOIN file_source f ON f.compressed_file_name = s.compressed_file_name
+ORDER BY s.id, f.id;
+
+DROP TABLE split_assignments;
+ALTER TABLE split_assignments_by_source RENAME TO split_assignments;
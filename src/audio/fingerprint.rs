@@ -0,0 +1,299 @@
+use super::decode_mono;
+
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use std::path::Path;
+
+/// Number of time steps a fingerprint is pooled down to, whatever the length
+/// of the sample.
+pub const NUM_FRAMES: usize = 32;
+/// Number of mel bands per time step.
+pub const NUM_BANDS: usize = 24;
+
+/// Only the start of long loops is fingerprinted.
+const MAX_SECONDS: f64 = 10.0;
+/// Anything this far below the peak at either end counts as silence.
+const SILENCE_DB: f32 = -40.0;
+/// Band energies this far below the loudest one are clamped, so that dither
+/// and quantisation noise don't change the fingerprint.
+const DYNAMIC_RANGE_DB: f32 = 80.0;
+const FRAME_SECONDS: f64 = 0.046;
+const MIN_HZ: f64 = 40.0;
+const MAX_HZ: f64 = 8000.0;
+
+/// Downsampled log-mel spectrogram of a sample. Leading and trailing silence
+/// is trimmed and the values are mean-centred and scaled to unit length, so
+/// fingerprints of the same sound at a different gain, bit depth or sample
+/// rate end up close together.
+#[derive(Debug, Clone)]
+pub struct Fingerprint {
+    /// Length of the sample once silence is trimmed
+    pub duration_seconds: f64,
+    values: Vec<f32>,
+}
+
+impl Fingerprint {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let (samples, sample_rate) = decode_mono(path, MAX_SECONDS)?;
+        Self::from_samples(&samples, sample_rate)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    }
+
+    pub fn from_samples(samples: &[f32], sample_rate: u32) -> anyhow::Result<Self> {
+        let samples = trim_silence(samples);
+        if samples.is_empty() || sample_rate == 0 {
+            anyhow::bail!("sample is silent");
+        }
+
+        let frames = log_mel_frames(samples, sample_rate);
+        let mut values = pool_frames(&frames);
+
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        values.iter_mut().for_each(|val| *val -= mean);
+        let norm = values.iter().map(|val| val * val).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            values.iter_mut().for_each(|val| *val /= norm);
+        }
+
+        Ok(Self {
+            duration_seconds: samples.len() as f64 / sample_rate as f64,
+            values,
+        })
+    }
+
+    /// `NUM_FRAMES * NUM_BANDS` values, one time step after another.
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    /// Cosine similarity in `[-1, 1]`, where 1 is an identical spectrogram.
+    pub fn similarity(&self, other: &Fingerprint) -> f32 {
+        self.values
+            .iter()
+            .zip(&other.values)
+            .map(|(a, b)| a * b)
+            .sum()
+    }
+}
+
+fn trim_silence(samples: &[f32]) -> &[f32] {
+    let peak = samples.iter().fold(0.0f32, |acc, val| acc.max(val.abs()));
+    if peak == 0.0 {
+        return &[];
+    }
+
+    let threshold = peak * 10f32.powf(SILENCE_DB / 20.0);
+    let start = samples.iter().position(|val| val.abs() >= threshold);
+    let end = samples.iter().rposition(|val| val.abs() >= threshold);
+    match (start, end) {
+        (Some(start), Some(end)) => &samples[start..=end],
+        _ => &[],
+    }
+}
+
+fn hz_to_mel(hz: f64) -> f64 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f64) -> f64 {
+    700.0 * (10f64.powf(mel / 2595.0) - 1.0)
+}
+
+/// Triangular mel filters over the bins of an `fft_len` point FFT, as
+/// `(bin, weight)` pairs per band. Bands are spread between `MIN_HZ` and
+/// `MAX_HZ` whatever the sample rate, so that the same sound at 44.1 and
+/// 48 kHz lands in the same bands.
+fn mel_filters(fft_len: usize, sample_rate: u32) -> Vec<Vec<(usize, f32)>> {
+    let min_mel = hz_to_mel(MIN_HZ);
+    let max_mel = hz_to_mel(MAX_HZ);
+    let edges: Vec<f64> = (0..NUM_BANDS + 2)
+        .map(|i| mel_to_hz(min_mel + (max_mel - min_mel) * i as f64 / (NUM_BANDS + 1) as f64))
+        .collect();
+    let bin_hz = sample_rate as f64 / fft_len as f64;
+
+    edges
+        .windows(3)
+        .map(|edge| {
+            (0..=fft_len / 2)
+                .filter_map(|bin| {
+                    let hz = bin as f64 * bin_hz;
+                    let weight = if hz > edge[0] && hz <= edge[1] {
+                        (hz - edge[0]) / (edge[1] - edge[0])
+                    } else if hz > edge[1] && hz < edge[2] {
+                        (edge[2] - hz) / (edge[2] - edge[1])
+                    } else {
+                        0.0
+                    };
+                    (weight > 0.0).then_some((bin, weight as f32))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Band energies in dB for each half overlapping, Hann windowed frame.
+fn log_mel_frames(samples: &[f32], sample_rate: u32) -> Vec<[f32; NUM_BANDS]> {
+    let frame_len = ((sample_rate as f64 * FRAME_SECONDS) as usize).max(16);
+    let hop = frame_len / 2;
+    let fft_len = frame_len.next_power_of_two();
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(fft_len);
+    let filters = mel_filters(fft_len, sample_rate);
+    let window: Vec<f32> = (0..frame_len)
+        .map(|i| {
+            let phase = 2.0 * std::f32::consts::PI * i as f32 / frame_len as f32;
+            0.5 - 0.5 * phase.cos()
+        })
+        .collect();
+
+    let num_frames = if samples.len() > frame_len {
+        (samples.len() - frame_len) / hop + 1
+    } else {
+        1
+    };
+
+    let mut frames = Vec::with_capacity(num_frames);
+    let mut buffer = vec![Complex::new(0.0, 0.0); fft_len];
+    for frame in 0..num_frames {
+        let start = frame * hop;
+        buffer
+            .iter_mut()
+            .for_each(|val| *val = Complex::new(0.0, 0.0));
+        for (i, sample) in samples[start..].iter().take(frame_len).enumerate() {
+            buffer[i].re = sample * window[i];
+        }
+        fft.process(&mut buffer);
+
+        let mut energies = [0.0f32; NUM_BANDS];
+        for (energy, filter) in energies.iter_mut().zip(&filters) {
+            *energy = filter
+                .iter()
+                .map(|(bin, weight)| buffer[*bin].norm_sqr() * weight)
+                .sum();
+        }
+        frames.push(energies);
+    }
+
+    let max_energy = frames
+        .iter()
+        .flat_map(|val| val.iter())
+        .fold(0.0f32, |acc, val| acc.max(*val));
+    let floor_db = 10.0 * max_energy.max(f32::MIN_POSITIVE).log10() - DYNAMIC_RANGE_DB;
+    for energies in frames.iter_mut() {
+        for energy in energies.iter_mut() {
+            *energy = (10.0 * energy.max(f32::MIN_POSITIVE).log10()).max(floor_db);
+        }
+    }
+
+    frames
+}
+
+/// Averages (or, for very short samples, repeats) frames down to exactly
+/// `NUM_FRAMES` time steps.
+fn pool_frames(frames: &[[f32; NUM_BANDS]]) -> Vec<f32> {
+    let mut values = Vec::with_capacity(NUM_FRAMES * NUM_BANDS);
+    for step in 0..NUM_FRAMES {
+        let start = step * frames.len() / NUM_FRAMES;
+        let end = ((step + 1) * frames.len() / NUM_FRAMES).max(start + 1);
+        for band in 0..NUM_BANDS {
+            let total: f32 = frames[start..end].iter().map(|val| val[band]).sum();
+            values.push(total / (end - start) as f32);
+        }
+    }
+
+    values
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::audio::tests::write_pcm_wav;
+    use std::fs;
+
+    /// Deterministic white noise in `[-1, 1]`.
+    fn noise(n: usize, seed: u64) -> Vec<f32> {
+        let mut state = seed;
+        (0..n)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+            })
+            .collect()
+    }
+
+    /// A decaying pitch drop with a click, roughly what a kick drum looks like.
+    pub fn test_kick(sample_rate: u32) -> Vec<f32> {
+        let n = (sample_rate as f64 * 0.4) as usize;
+        let click = noise(n, 1);
+        let mut phase = 0.0f64;
+        (0..n)
+            .map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                phase += 2.0 * std::f64::consts::PI * (50.0 + 100.0 * (-t * 30.0).exp())
+                    / sample_rate as f64;
+                let body = phase.sin() * (-t * 8.0).exp();
+                let click = click[i] as f64 * (-t * 200.0).exp() * 0.3;
+                (0.8 * (body + click)) as f32
+            })
+            .collect()
+    }
+
+    /// A tone with a noise burst, roughly what a snare looks like.
+    pub fn test_snare(sample_rate: u32) -> Vec<f32> {
+        let n = (sample_rate as f64 * 0.3) as usize;
+        let rattle = noise(n, 2);
+        (0..n)
+            .map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                let tone = (2.0 * std::f64::consts::PI * 200.0 * t).sin() * (-t * 20.0).exp();
+                let rattle = rattle[i] as f64 * (-t * 10.0).exp();
+                (0.5 * (tone + rattle)) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_gain_and_silence() {
+        let original = Fingerprint::from_samples(&test_kick(44100), 44100).unwrap();
+
+        // quieter, resampled to 48 kHz and with 10 ms of silence in front
+        let mut padded = vec![0.0; 480];
+        padded.extend(test_kick(48000).iter().map(|val| val * 0.5));
+        let copy = Fingerprint::from_samples(&padded, 48000).unwrap();
+
+        assert_eq!(NUM_FRAMES * NUM_BANDS, original.values().len());
+        assert!(
+            original.similarity(&copy) > 0.95,
+            "{}",
+            original.similarity(&copy)
+        );
+        assert!((original.duration_seconds - copy.duration_seconds).abs() < 0.01);
+        assert!((original.similarity(&original) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_fingerprint_tells_sounds_apart() {
+        let kick = Fingerprint::from_samples(&test_kick(44100), 44100).unwrap();
+        let snare = Fingerprint::from_samples(&test_snare(44100), 44100).unwrap();
+
+        assert!(kick.similarity(&snare) < 0.8, "{}", kick.similarity(&snare));
+        assert!(Fingerprint::from_samples(&[0.0; 1000], 44100).is_err());
+    }
+
+    #[test]
+    fn test_fingerprint_from_file() {
+        let dir = std::env::temp_dir();
+        let path_16 = dir.join("chimecho_test_fingerprint_16.wav");
+        let path_24 = dir.join("chimecho_test_fingerprint_24.wav");
+        write_pcm_wav(&path_16, 44100, 1, 16, &test_kick(44100));
+        write_pcm_wav(&path_24, 44100, 1, 24, &test_kick(44100));
+
+        let fingerprint_16 = Fingerprint::from_file(&path_16).unwrap();
+        let fingerprint_24 = Fingerprint::from_file(&path_24).unwrap();
+        fs::remove_file(&path_16).unwrap();
+        fs::remove_file(&path_24).unwrap();
+
+        assert!(fingerprint_16.similarity(&fingerprint_24) > 0.99);
+    }
+}
@@ -0,0 +1,230 @@
+pub mod fingerprint;
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Properties read from the header of an extracted sample. Anything the
+/// container doesn't declare is left as `None`.
+#[derive(Debug, Default, PartialEq)]
+pub struct AudioProperties {
+    pub duration_seconds: Option<f64>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i32>,
+    pub bit_depth: Option<i32>,
+}
+
+/// Lower cased file extension of a sample, e.g. `wav` for `Kick (Hard).WAV`.
+pub fn file_format(file_name: &str) -> String {
+    Path::new(file_name)
+        .extension()
+        .map(|val| val.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn open_format(path: &Path) -> anyhow::Result<Box<dyn FormatReader>> {
+    let file = fs::File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    hint.with_extension(&file_format(&path.display().to_string()));
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    Ok(probed.format)
+}
+
+pub fn probe_properties(path: &Path) -> anyhow::Result<AudioProperties> {
+    let mut format = open_format(path)?;
+
+    let track = format
+        .default_track()
+        .ok_or_else(|| anyhow::anyhow!("{} has no audio track", path.display()))?;
+    let params = track.codec_params.clone();
+    let track_id = track.id;
+
+    let mut n_frames = params.n_frames;
+    if n_frames.is_none() {
+        // mp3 files without a Xing/VBRI header don't declare a length, so add
+        // up the packet durations instead
+        let mut total = 0;
+        while let Ok(packet) = format.next_packet() {
+            if packet.track_id() == track_id {
+                total += packet.dur;
+            }
+        }
+        n_frames = Some(total);
+    }
+
+    let duration_seconds = match (n_frames, params.sample_rate) {
+        (Some(frames), Some(rate)) if rate > 0 => Some(frames as f64 / rate as f64),
+        _ => None,
+    };
+
+    Ok(AudioProperties {
+        duration_seconds,
+        sample_rate: params.sample_rate.map(|val| val as i32),
+        channels: params.channels.map(|val| val.count() as i32),
+        bit_depth: params.bits_per_sample.map(|val| val as i32),
+    })
+}
+
+/// Decodes up to `max_seconds` of a sample, averaging the channels down to
+/// mono. Returns the samples along with their sample rate.
+pub fn decode_mono(path: &Path, max_seconds: f64) -> anyhow::Result<(Vec<f32>, u32)> {
+    let mut format = open_format(path)?;
+
+    let track = format
+        .default_track()
+        .ok_or_else(|| anyhow::anyhow!("{} has no audio track", path.display()))?;
+    let track_id = track.id;
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .ok_or_else(|| anyhow::anyhow!("{} has no sample rate", path.display()))?;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let max_frames = (max_seconds * sample_rate as f64) as usize;
+    let mut samples = Vec::new();
+    while samples.len() < max_frames {
+        let packet = match format.next_packet() {
+            Ok(val) => val,
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(val) => val,
+            // a corrupt frame in the middle of an mp3 shouldn't lose the whole sample
+            Err(SymphoniaError::DecodeError(e)) => {
+                debug!("skipping undecodable packet in {}: {}", path.display(), e);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let spec = *decoded.spec();
+        let channels = spec.channels.count().max(1);
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend(
+            buffer
+                .samples()
+                .chunks(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+    }
+    samples.truncate(max_frames);
+
+    Ok((samples, sample_rate))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Writes interleaved samples in `[-1, 1]` as a 16 or 24 bit PCM wav file.
+    pub fn write_pcm_wav(
+        path: &Path,
+        sample_rate: u32,
+        channels: u16,
+        bit_depth: u16,
+        samples: &[f32],
+    ) {
+        let sample_bytes = bit_depth as u32 / 8;
+        let data_len = samples.len() as u32 * sample_bytes;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * channels as u32 * sample_bytes).to_le_bytes());
+        bytes.extend_from_slice(&(channels * sample_bytes as u16).to_le_bytes());
+        bytes.extend_from_slice(&bit_depth.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            let max = ((1i64 << (bit_depth - 1)) - 1) as f32;
+            let value = (sample.clamp(-1.0, 1.0) * max).round() as i32;
+            bytes.extend_from_slice(&value.to_le_bytes()[..sample_bytes as usize]);
+        }
+
+        fs::File::create(path).unwrap().write_all(&bytes).unwrap();
+    }
+
+    /// Writes a silent 16 bit PCM wav file, for tests that need real audio.
+    pub fn write_test_wav(path: &Path, sample_rate: u32, channels: u16, n_frames: u32) {
+        let samples = vec![0.0; (n_frames * channels as u32) as usize];
+        write_pcm_wav(path, sample_rate, channels, 16, &samples);
+    }
+
+    #[test]
+    fn test_file_format() {
+        assert_eq!("wav", file_format("test/Nav_Champion (Kick).WAV"));
+        assert_eq!("mp3", file_format("loops/140 bpm.loop.mp3"));
+        assert_eq!("", file_format("README"));
+    }
+
+    #[test]
+    fn test_probe_properties() {
+        let path = std::env::temp_dir().join("chimecho_test_probe_properties.wav");
+        write_test_wav(&path, 44100, 2, 22050);
+
+        let properties = probe_properties(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            AudioProperties {
+                duration_seconds: Some(0.5),
+                sample_rate: Some(44100),
+                channels: Some(2),
+                bit_depth: Some(16),
+            },
+            properties
+        );
+    }
+
+    #[test]
+    fn test_decode_mono() {
+        let path = std::env::temp_dir().join("chimecho_test_decode_mono.wav");
+        // left and right average out to 0.25, then -0.25
+        let stereo = [0.5, 0.0, 0.5, 0.0, -0.5, 0.0, -0.5, 0.0];
+        write_pcm_wav(&path, 8000, 2, 24, &stereo);
+
+        let (samples, sample_rate) = decode_mono(&path, 1.0).unwrap();
+        let (truncated, _) = decode_mono(&path, 2.0 / 8000.0).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(8000, sample_rate);
+        assert_eq!(4, samples.len());
+        for (expected, actual) in [0.25, 0.25, -0.25, -0.25].iter().zip(&samples) {
+            assert!(
+                (expected - actual).abs() < 1e-4,
+                "{} != {}",
+                expected,
+                actual
+            );
+        }
+        assert_eq!(2, truncated.len());
+    }
+}
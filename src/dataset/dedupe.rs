@@ -0,0 +1,380 @@
+use super::split::stable_hash;
+use crate::audio::fingerprint::{Fingerprint, NUM_BANDS, NUM_FRAMES};
+use crate::postgres_orm::filter::SampleFilter;
+use crate::postgres_orm::models::MusicFiles;
+use crate::postgres_orm::MetadataStore;
+use crate::query::TableRow;
+
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+/// The index splits each signature into this many bands of `BAND_BITS` bits.
+/// Two fingerprints are compared if any band matches, which at a similarity
+/// of 0.95 misses fewer than one pair in a hundred thousand.
+const NUM_BANDS_LSH: usize = 16;
+const BAND_BITS: usize = 6;
+/// Trimmed durations of near-duplicates may differ by this fraction of the
+/// longer one, or by `DURATION_SLACK_SECONDS`, whichever is larger.
+const DURATION_TOLERANCE: f64 = 0.1;
+const DURATION_SLACK_SECONDS: f64 = 0.02;
+
+/// Locality sensitive index over fingerprints. Each fingerprint is hashed by
+/// which side of a fixed set of random hyperplanes it falls on, so that
+/// similar fingerprints share buckets and only those need comparing.
+pub struct SimilarityIndex {
+    hyperplanes: Vec<Vec<f32>>,
+    buckets: Vec<HashMap<u8, Vec<usize>>>,
+    fingerprints: Vec<Fingerprint>,
+}
+
+impl Default for SimilarityIndex {
+    fn default() -> Self {
+        // hyperplanes of random signs, seeded so that runs are reproducible
+        let hyperplanes = (0..NUM_BANDS_LSH * BAND_BITS)
+            .map(|plane| {
+                (0..NUM_FRAMES * NUM_BANDS)
+                    .map(|dim| {
+                        if stable_hash(&plane.to_string(), dim as u64) & 1 == 0 {
+                            1.0
+                        } else {
+                            -1.0
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            hyperplanes,
+            buckets: vec![HashMap::new(); NUM_BANDS_LSH],
+            fingerprints: Vec::new(),
+        }
+    }
+}
+
+impl SimilarityIndex {
+    fn band_keys(&self, fingerprint: &Fingerprint) -> Vec<u8> {
+        self.hyperplanes
+            .chunks(BAND_BITS)
+            .map(|planes| {
+                planes.iter().enumerate().fold(0u8, |key, (bit, plane)| {
+                    let side: f32 = plane
+                        .iter()
+                        .zip(fingerprint.values())
+                        .map(|(a, b)| a * b)
+                        .sum();
+                    if side >= 0.0 {
+                        key | (1 << bit)
+                    } else {
+                        key
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Adds a fingerprint and returns its position in the index.
+    pub fn insert(&mut self, fingerprint: Fingerprint) -> usize {
+        let position = self.fingerprints.len();
+        let keys = self.band_keys(&fingerprint);
+        for (bucket, key) in self.buckets.iter_mut().zip(keys) {
+            bucket.entry(key).or_default().push(position);
+        }
+        self.fingerprints.push(fingerprint);
+
+        position
+    }
+
+    /// Positions of the indexed fingerprints that are at least `threshold`
+    /// similar to `fingerprint` and of about the same length, along with
+    /// their similarity.
+    pub fn find_similar(&self, fingerprint: &Fingerprint, threshold: f32) -> Vec<(usize, f32)> {
+        let candidates: BTreeSet<usize> = self
+            .buckets
+            .iter()
+            .zip(self.band_keys(fingerprint))
+            .filter_map(|(bucket, key)| bucket.get(&key))
+            .flatten()
+            .copied()
+            .collect();
+
+        candidates
+            .into_iter()
+            .filter(|val| durations_match(fingerprint, &self.fingerprints[*val]))
+            .map(|val| (val, fingerprint.similarity(&self.fingerprints[val])))
+            .filter(|(_, similarity)| *similarity >= threshold)
+            .collect()
+    }
+}
+
+fn durations_match(a: &Fingerprint, b: &Fingerprint) -> bool {
+    let longest = a.duration_seconds.max(b.duration_seconds);
+    (a.duration_seconds - b.duration_seconds).abs()
+        <= (longest * DURATION_TOLERANCE).max(DURATION_SLACK_SECONDS)
+}
+
+fn find_root(parents: &mut [usize], mut position: usize) -> usize {
+    while parents[position] != position {
+        parents[position] = parents[parents[position]];
+        position = parents[position];
+    }
+    position
+}
+
+/// Groups fingerprints that are transitively at least `threshold` similar.
+/// Only groups of two or more are returned, each in index order.
+pub fn cluster(fingerprints: &[Fingerprint], threshold: f32) -> Vec<Vec<usize>> {
+    let mut index = SimilarityIndex::default();
+    let mut parents: Vec<usize> = (0..fingerprints.len()).collect();
+
+    for fingerprint in fingerprints {
+        let matches = index.find_similar(fingerprint, threshold);
+        let position = index.insert(fingerprint.clone());
+        for (other, _) in matches {
+            let root = find_root(&mut parents, other);
+            let own_root = find_root(&mut parents, position);
+            parents[own_root.max(root)] = own_root.min(root);
+        }
+    }
+
+    let mut clusters: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for position in 0..parents.len() {
+        let root = find_root(&mut parents, position);
+        clusters.entry(root).or_default().push(position);
+    }
+
+    clusters.into_values().filter(|val| val.len() > 1).collect()
+}
+
+/// The copy of a near-duplicate group worth keeping: the highest bit depth,
+/// then the highest sample rate, then the one stored first.
+fn keeper(samples: &[&MusicFiles]) -> usize {
+    (0..samples.len())
+        .max_by_key(|val| {
+            let sample = samples[*val];
+            (sample.bit_depth, sample.sample_rate, Reverse(sample.id))
+        })
+        .unwrap_or(0)
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DuplicateRow {
+    pub cluster: usize,
+    pub id: i32,
+    pub kit: String,
+    pub file: String,
+    pub instrument: String,
+    pub duration_seconds: f64,
+    /// Similarity to the kept copy
+    pub similarity: f32,
+    pub keep: bool,
+}
+
+impl TableRow for DuplicateRow {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "cluster",
+            "id",
+            "kit",
+            "file",
+            "instrument",
+            "duration",
+            "similarity",
+            "keep",
+        ]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.cluster.to_string(),
+            self.id.to_string(),
+            self.kit.clone(),
+            self.file.clone(),
+            self.instrument.clone(),
+            format!("{:.3}", self.duration_seconds),
+            format!("{:.3}", self.similarity),
+            self.keep.to_string(),
+        ]
+    }
+}
+
+pub struct DedupeResult {
+    pub rows: Vec<DuplicateRow>,
+    /// `(sample id, duplicate_of)` for every sample that was fingerprinted
+    pub marks: Vec<(i32, Option<i32>)>,
+}
+
+/// Fingerprints the samples matching the filter from `sample_dir` and groups
+/// near-duplicates. Samples that can't be read are logged and left out.
+pub fn find_duplicates(
+    store: &dyn MetadataStore,
+    filter: &SampleFilter,
+    sample_dir: &Path,
+    threshold: f32,
+) -> anyhow::Result<DedupeResult> {
+    let samples = store.find_samples(filter)?;
+    info!("fingerprinting {} samples", samples.len());
+
+    let mut fingerprinted = Vec::new();
+    let mut fingerprints = Vec::new();
+    for (sample, _) in samples.iter() {
+        match Fingerprint::from_file(&sample_dir.join(&sample.individual_file_name)) {
+            Ok(val) => {
+                fingerprinted.push(sample);
+                fingerprints.push(val);
+            }
+            Err(e) => warn!("skipping {}: {}", sample.individual_file_name, e),
+        }
+    }
+
+    let mut marks: HashMap<i32, Option<i32>> =
+        fingerprinted.iter().map(|val| (val.id, None)).collect();
+    let mut rows = Vec::new();
+    let clusters = cluster(&fingerprints, threshold);
+    for (number, members) in clusters.iter().enumerate() {
+        let members: Vec<&MusicFiles> = members.iter().map(|val| fingerprinted[*val]).collect();
+        let kept = keeper(&members);
+        let kept_fingerprint = &fingerprints[clusters[number][kept]];
+
+        for (position, sample) in clusters[number].iter().zip(&members) {
+            let is_kept = sample.id == members[kept].id;
+            if !is_kept {
+                marks.insert(sample.id, Some(members[kept].id));
+            }
+            rows.push(DuplicateRow {
+                cluster: number + 1,
+                id: sample.id,
+                kit: sample.compressed_file_name.clone(),
+                file: sample.individual_file_name.clone(),
+                instrument: sample.instrument.clone(),
+                duration_seconds: fingerprints[*position].duration_seconds,
+                similarity: fingerprints[*position].similarity(kept_fingerprint),
+                keep: is_kept,
+            });
+        }
+    }
+    info!(
+        "found {} near-duplicate groups covering {} samples",
+        clusters.len(),
+        rows.len()
+    );
+
+    let mut marks: Vec<(i32, Option<i32>)> = marks.into_iter().collect();
+    marks.sort();
+
+    Ok(DedupeResult { rows, marks })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::fingerprint::tests::{test_kick, test_snare};
+    use crate::audio::tests::write_pcm_wav;
+    use crate::postgres_orm::tests::{test_file_source, test_music_file, test_store};
+    use std::fs;
+
+    #[test]
+    fn test_similarity_index() {
+        let snare = Fingerprint::from_samples(&test_snare(44100), 44100).unwrap();
+        let mut index = SimilarityIndex::default();
+        let kick = index.insert(Fingerprint::from_samples(&test_kick(44100), 44100).unwrap());
+        let snare_position = index.insert(snare.clone());
+
+        let quiet_kick: Vec<f32> = test_kick(48000).iter().map(|val| val * 0.3).collect();
+        let similar = index.find_similar(
+            &Fingerprint::from_samples(&quiet_kick, 48000).unwrap(),
+            0.95,
+        );
+
+        assert_eq!(
+            vec![kick],
+            similar.iter().map(|val| val.0).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![snare_position],
+            index
+                .find_similar(&snare, 0.95)
+                .iter()
+                .map(|val| val.0)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_cluster() {
+        let kick = test_kick(44100);
+        // same sound but cut short, which is a different sample
+        let short_kick = &kick[..kick.len() / 2];
+        let fingerprints = vec![
+            Fingerprint::from_samples(&kick, 44100).unwrap(),
+            Fingerprint::from_samples(&test_snare(44100), 44100).unwrap(),
+            Fingerprint::from_samples(short_kick, 44100).unwrap(),
+            Fingerprint::from_samples(&test_snare(48000), 48000).unwrap(),
+            Fingerprint::from_samples(&test_kick(22050), 22050).unwrap(),
+        ];
+
+        assert_eq!(vec![vec![0, 4], vec![1, 3]], cluster(&fingerprints, 0.95));
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let store = test_store();
+        let dir = std::env::temp_dir().join("chimecho_test_find_duplicates");
+        fs::create_dir_all(&dir).unwrap();
+        write_pcm_wav(&dir.join("kick.wav"), 44100, 1, 16, &test_kick(44100));
+        write_pcm_wav(&dir.join("kick_24.wav"), 44100, 1, 24, &test_kick(44100));
+        write_pcm_wav(&dir.join("snare.wav"), 44100, 1, 16, &test_snare(44100));
+
+        let source = store
+            .create_file_row(&test_file_source(
+                "https://a",
+                "a.zip",
+                "dropbox",
+                "2022-01-01",
+            ))
+            .unwrap();
+        let mut samples = vec![
+            test_music_file("a.zip", "kick.wav", "kick", source.id, 0.4),
+            test_music_file("a.zip", "kick_24.wav", "kick", source.id, 0.4),
+            test_music_file("a.zip", "snare.wav", "snare", source.id, 0.4),
+            test_music_file("a.zip", "missing.wav", "snare", source.id, 0.4),
+        ];
+        samples[1].bit_depth = Some(24);
+        let inserted = store.bulk_insert_music_files(&samples).unwrap();
+
+        let result = find_duplicates(store.as_ref(), &SampleFilter::default(), &dir, 0.95).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            vec![(1, inserted[0].id, false), (1, inserted[1].id, true)],
+            result
+                .rows
+                .iter()
+                .map(|val| (val.cluster, val.id, val.keep))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                (inserted[0].id, Some(inserted[1].id)),
+                (inserted[1].id, None),
+                (inserted[2].id, None),
+            ],
+            result.marks
+        );
+
+        store.mark_duplicates(&result.marks).unwrap();
+        let filter = SampleFilter {
+            exclude_duplicates: true,
+            ..Default::default()
+        };
+        let remaining: Vec<String> = store
+            .find_samples(&filter)
+            .unwrap()
+            .into_iter()
+            .map(|(sample, _)| sample.individual_file_name)
+            .collect();
+        assert_eq!(vec!["kick_24.wav", "snare.wav", "missing.wav"], remaining);
+    }
+}
@@ -0,0 +1,527 @@
+use super::split::{assign_split, Split, SplitRatios};
+use crate::postgres_orm::filter::SampleFilter;
+use crate::postgres_orm::models::{FileSource, MusicFiles};
+use crate::postgres_orm::MetadataStore;
+use crate::storage_download::download_utils::instrument_labels;
+
+use chrono::NaiveDateTime;
+use clap::ArgEnum;
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DataType, DoubleType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::schema::parser::parse_message_type;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ManifestFormat {
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+const PARQUET_SCHEMA: &str = "
+message manifest {
+    REQUIRED BYTE_ARRAY uri (UTF8);
+    REQUIRED BYTE_ARRAY split (UTF8);
+    REQUIRED INT32 kit_id;
+    REQUIRED BYTE_ARRAY kit (UTF8);
+    REQUIRED BYTE_ARRAY file (UTF8);
+    REQUIRED BYTE_ARRAY instrument (UTF8);
+    REPEATED BYTE_ARRAY labels (UTF8);
+    REQUIRED BYTE_ARRAY format (UTF8);
+    OPTIONAL DOUBLE duration_seconds;
+    OPTIONAL INT32 sample_rate;
+    OPTIONAL INT32 channels;
+    OPTIONAL INT32 bit_depth;
+    REQUIRED BYTE_ARRAY provider (UTF8);
+    OPTIONAL BYTE_ARRAY subreddit (UTF8);
+    REQUIRED BYTE_ARRAY source_url (UTF8);
+    OPTIONAL BYTE_ARRAY post_url (UTF8);
+    OPTIONAL BYTE_ARRAY post_title (UTF8);
+    REQUIRED INT64 posted_at (TIMESTAMP_MILLIS);
+}
+";
+
+/// One training example: a single extracted sample, where to fetch it from,
+/// its labels and the kit and post it came from.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ManifestRecord {
+    pub uri: String,
+    pub split: Split,
+    pub kit_id: i32,
+    pub kit: String,
+    pub file: String,
+    pub instrument: String,
+    pub labels: Vec<String>,
+    pub format: String,
+    pub duration_seconds: Option<f64>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i32>,
+    pub bit_depth: Option<i32>,
+    pub provider: String,
+    pub subreddit: Option<String>,
+    pub source_url: String,
+    pub post_url: Option<String>,
+    pub post_title: Option<String>,
+    pub posted_at: NaiveDateTime,
+}
+
+#[derive(Debug)]
+pub struct ManifestOptions {
+    /// GCS bucket the samples were uploaded to. Without one, records point at
+    /// the local extraction folder instead.
+    pub bucket: Option<String>,
+    /// Folder the samples were extracted to
+    pub unzipped_dir: String,
+    /// Keywords the labels of a sample are picked from
+    pub instrument_keywords: Vec<String>,
+    pub ratios: SplitRatios,
+    pub seed: u64,
+    /// Split of each kit as stored in the metadata store, by file_source id.
+    /// Kits missing from it fall back to a hash of the kit name.
+    pub assignments: Option<HashMap<i32, Split>>,
+}
+
+/// Where `upload` puts a sample: `gsutil cp -r ./unzipped/ gs://<bucket>`
+/// keeps the name of the last folder, so objects live under
+/// `<bucket>/unzipped/`.
+pub fn sample_uri(bucket: Option<&str>, unzipped_dir: &str, individual_file_name: &str) -> String {
+    let unzipped_dir = unzipped_dir.trim_end_matches('/');
+
+    match bucket {
+        Some(bucket) => {
+            let folder = Path::new(unzipped_dir)
+                .file_name()
+                .map(|val| val.to_string_lossy())
+                .unwrap_or_default();
+            format!("gs://{}/{}/{}", bucket, folder, individual_file_name)
+        }
+        None => format!("{}/{}", unzipped_dir, individual_file_name),
+    }
+}
+
+impl ManifestRecord {
+    pub fn new(sample: MusicFiles, source: FileSource, options: &ManifestOptions) -> Self {
+        Self {
+            uri: sample_uri(
+                options.bucket.as_deref(),
+                &options.unzipped_dir,
+                &sample.individual_file_name,
+            ),
+            split: options
+                .assignments
+                .as_ref()
+                .and_then(|val| val.get(&source.id))
+                .copied()
+                .unwrap_or_else(|| {
+                    assign_split(&source.compressed_file_name, options.seed, &options.ratios)
+                }),
+            kit_id: source.id,
+            kit: source.compressed_file_name,
+            labels: instrument_labels(&sample.individual_file_name, &options.instrument_keywords),
+            file: sample.individual_file_name,
+            instrument: sample.instrument,
+            format: sample.format,
+            duration_seconds: sample.duration_seconds,
+            sample_rate: sample.sample_rate,
+            channels: sample.channels,
+            bit_depth: sample.bit_depth,
+            provider: source.provider,
+            subreddit: source.subreddit,
+            source_url: source.url,
+            post_url: source.post_url,
+            post_title: source.post_title,
+            posted_at: source.posted_at,
+        }
+    }
+
+    fn csv_headers() -> Vec<&'static str> {
+        vec![
+            "uri",
+            "split",
+            "kit_id",
+            "kit",
+            "file",
+            "instrument",
+            "labels",
+            "format",
+            "duration_seconds",
+            "sample_rate",
+            "channels",
+            "bit_depth",
+            "provider",
+            "subreddit",
+            "source_url",
+            "post_url",
+            "post_title",
+            "posted_at",
+        ]
+    }
+
+    /// CSV has no list type, so labels are joined with `;`.
+    fn csv_fields(&self) -> Vec<String> {
+        fn optional<T: ToString>(val: &Option<T>) -> String {
+            val.as_ref().map(|val| val.to_string()).unwrap_or_default()
+        }
+
+        vec![
+            self.uri.clone(),
+            self.split.to_string(),
+            self.kit_id.to_string(),
+            self.kit.clone(),
+            self.file.clone(),
+            self.instrument.clone(),
+            self.labels.join(";"),
+            self.format.clone(),
+            optional(&self.duration_seconds),
+            optional(&self.sample_rate),
+            optional(&self.channels),
+            optional(&self.bit_depth),
+            self.provider.clone(),
+            optional(&self.subreddit),
+            self.source_url.clone(),
+            optional(&self.post_url),
+            optional(&self.post_title),
+            self.posted_at.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        ]
+    }
+}
+
+pub fn build_manifest(
+    store: &dyn MetadataStore,
+    filter: &SampleFilter,
+    options: &ManifestOptions,
+) -> anyhow::Result<Vec<ManifestRecord>> {
+    Ok(store
+        .find_samples(filter)?
+        .into_iter()
+        .map(|(sample, source)| ManifestRecord::new(sample, source, options))
+        .collect())
+}
+
+pub fn write_manifest(
+    records: &[ManifestRecord],
+    format: ManifestFormat,
+    file_path: &str,
+) -> anyhow::Result<()> {
+    let file = fs::File::create(file_path)?;
+
+    match format {
+        ManifestFormat::Csv => write_csv(records, file),
+        ManifestFormat::Jsonl => write_jsonl(records, file),
+        ManifestFormat::Parquet => write_parquet(records, file),
+    }
+}
+
+fn write_csv<W: Write>(records: &[ManifestRecord], writer: W) -> anyhow::Result<()> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    csv_writer.write_record(ManifestRecord::csv_headers())?;
+    for record in records {
+        csv_writer.write_record(record.csv_fields())?;
+    }
+    csv_writer.flush()?;
+
+    Ok(())
+}
+
+fn write_jsonl<W: Write>(records: &[ManifestRecord], mut writer: W) -> anyhow::Result<()> {
+    for record in records {
+        serde_json::to_writer(&mut writer, record)?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes the next column of the row group. `optional` columns get definition
+/// levels so that `None` is stored as null.
+fn write_column<T, W>(
+    row_group: &mut SerializedRowGroupWriter<'_, W>,
+    values: Vec<Option<T::T>>,
+    optional: bool,
+) -> anyhow::Result<()>
+where
+    T: DataType,
+    W: Write + Send,
+{
+    let mut column = row_group
+        .next_column()?
+        .ok_or_else(|| anyhow::anyhow!("manifest schema has fewer columns than the records"))?;
+
+    let def_levels: Vec<i16> = values.iter().map(|val| val.is_some() as i16).collect();
+    let present: Vec<T::T> = values.into_iter().flatten().collect();
+    if !optional && present.len() != def_levels.len() {
+        anyhow::bail!("required manifest column is missing values");
+    }
+
+    column.typed::<T>().write_batch(
+        &present,
+        if optional { Some(&def_levels) } else { None },
+        None,
+    )?;
+    column.close()?;
+
+    Ok(())
+}
+
+/// Writes the next column as a repeated field: the first value of each row
+/// starts a new record (repetition level 0) and an empty list is a single
+/// undefined entry.
+fn write_list_column<W: Write + Send>(
+    row_group: &mut SerializedRowGroupWriter<'_, W>,
+    values: Vec<Vec<String>>,
+) -> anyhow::Result<()> {
+    let mut column = row_group
+        .next_column()?
+        .ok_or_else(|| anyhow::anyhow!("manifest schema has fewer columns than the records"))?;
+
+    let mut present = Vec::new();
+    let mut def_levels = Vec::new();
+    let mut rep_levels = Vec::new();
+    for row in values {
+        if row.is_empty() {
+            def_levels.push(0);
+            rep_levels.push(0);
+        }
+        for (index, val) in row.into_iter().enumerate() {
+            present.push(ByteArray::from(val.as_str()));
+            def_levels.push(1);
+            rep_levels.push(if index == 0 { 0 } else { 1 });
+        }
+    }
+
+    column
+        .typed::<ByteArrayType>()
+        .write_batch(&present, Some(&def_levels), Some(&rep_levels))?;
+    column.close()?;
+
+    Ok(())
+}
+
+fn write_parquet<W: Write + Send>(records: &[ManifestRecord], writer: W) -> anyhow::Result<()> {
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+    let properties = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::UNCOMPRESSED)
+            .build(),
+    );
+    let mut file_writer = SerializedFileWriter::new(writer, schema, properties)?;
+    let mut row_group = file_writer.next_row_group()?;
+
+    let text = |val: &str| Some(ByteArray::from(val));
+    let optional_text = |val: &Option<String>| val.as_deref().map(ByteArray::from);
+
+    let rg = &mut row_group;
+    write_column::<ByteArrayType, _>(rg, records.iter().map(|r| text(&r.uri)).collect(), false)?;
+    write_column::<ByteArrayType, _>(
+        rg,
+        records.iter().map(|r| text(&r.split.to_string())).collect(),
+        false,
+    )?;
+    write_column::<Int32Type, _>(rg, records.iter().map(|r| Some(r.kit_id)).collect(), false)?;
+    write_column::<ByteArrayType, _>(rg, records.iter().map(|r| text(&r.kit)).collect(), false)?;
+    write_column::<ByteArrayType, _>(rg, records.iter().map(|r| text(&r.file)).collect(), false)?;
+    write_column::<ByteArrayType, _>(
+        rg,
+        records.iter().map(|r| text(&r.instrument)).collect(),
+        false,
+    )?;
+    write_list_column(rg, records.iter().map(|r| r.labels.clone()).collect())?;
+    write_column::<ByteArrayType, _>(rg, records.iter().map(|r| text(&r.format)).collect(), false)?;
+    write_column::<DoubleType, _>(
+        rg,
+        records.iter().map(|r| r.duration_seconds).collect(),
+        true,
+    )?;
+    write_column::<Int32Type, _>(rg, records.iter().map(|r| r.sample_rate).collect(), true)?;
+    write_column::<Int32Type, _>(rg, records.iter().map(|r| r.channels).collect(), true)?;
+    write_column::<Int32Type, _>(rg, records.iter().map(|r| r.bit_depth).collect(), true)?;
+    write_column::<ByteArrayType, _>(
+        rg,
+        records.iter().map(|r| text(&r.provider)).collect(),
+        false,
+    )?;
+    write_column::<ByteArrayType, _>(
+        rg,
+        records
+            .iter()
+            .map(|r| optional_text(&r.subreddit))
+            .collect(),
+        true,
+    )?;
+    write_column::<ByteArrayType, _>(
+        rg,
+        records.iter().map(|r| text(&r.source_url)).collect(),
+        false,
+    )?;
+    write_column::<ByteArrayType, _>(
+        rg,
+        records.iter().map(|r| optional_text(&r.post_url)).collect(),
+        true,
+    )?;
+    write_column::<ByteArrayType, _>(
+        rg,
+        records
+            .iter()
+            .map(|r| optional_text(&r.post_title))
+            .collect(),
+        true,
+    )?;
+    write_column::<Int64Type, _>(
+        rg,
+        records
+            .iter()
+            .map(|r| Some(r.posted_at.and_utc().timestamp_millis()))
+            .collect(),
+        false,
+    )?;
+
+    row_group.close()?;
+    file_writer.close()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+
+    fn test_record(kit: &str, file: &str, subreddit: Option<&str>) -> ManifestRecord {
+        let config = Config::default();
+        let options = ManifestOptions {
+            bucket: Some("chimecho_bucket".to_string()),
+            unzipped_dir: config.storage.unzipped_dir,
+            instrument_keywords: config.classification.instrument_keywords,
+            ratios: SplitRatios::new(0.1, 0.1).unwrap(),
+            seed: 0,
+            assignments: None,
+        };
+        let posted_at = "2022-05-01T12:00:00".parse().unwrap();
+
+        ManifestRecord::new(
+            MusicFiles {
+                id: 1,
+                compressed_file_name: kit.to_string(),
+                individual_file_name: file.to_string(),
+                instrument: instrument_labels(file, &options.instrument_keywords)
+                    .into_iter()
+                    .next()
+                    .unwrap_or_else(|| "unspecified".to_string()),
+                file_source_id: Some(3),
+                format: "wav".to_string(),
+                duration_seconds: Some(0.5),
+                sample_rate: Some(44100),
+                channels: None,
+                bit_depth: Some(24),
+                duplicate_of: None,
+            },
+            FileSource {
+                id: 3,
+                url: "https://www.dropbox.com/s/abc/kit.zip?dl=1".to_string(),
+                compressed_file_name: kit.to_string(),
+                time_inserted: posted_at,
+                provider: "dropbox".to_string(),
+                subreddit: subreddit.map(|val| val.to_string()),
+                posted_at,
+                post_url: Some("https://www.reddit.com/r/Drumkits/comments/abc/kit/".to_string()),
+                post_title: Some("Kit, vol. 1".to_string()),
+                original_url: None,
+                size_bytes: None,
+                mime_type: None,
+                md5_checksum: None,
+                source_modified_at: None,
+            },
+            &options,
+        )
+    }
+
+    fn test_records() -> Vec<ManifestRecord> {
+        vec![
+            test_record("kit.zip", "kit/808 Kick.wav", Some("drumkits")),
+            test_record("kit.zip", "kit/Vox.wav", None),
+        ]
+    }
+
+    #[test]
+    fn test_manifest_record() {
+        let record = test_record("kit.zip", "kit/808 Kick.wav", Some("drumkits"));
+
+        assert_eq!("gs://chimecho_bucket/unzipped/kit/808 Kick.wav", record.uri);
+        assert_eq!(vec!["kick", "808"], record.labels);
+        assert_eq!(
+            "unzipped/kit/Vox.wav",
+            sample_uri(None, "unzipped", "kit/Vox.wav")
+        );
+        assert_eq!(
+            "gs://chimecho_bucket/samples/kit/Vox.wav",
+            sample_uri(Some("chimecho_bucket"), "data/samples/", "kit/Vox.wav")
+        );
+        assert_eq!(
+            "data/samples/kit/Vox.wav",
+            sample_uri(None, "data/samples/", "kit/Vox.wav")
+        );
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let mut buffer = Vec::new();
+        write_csv(&test_records(), &mut buffer).unwrap();
+        let output = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(3, lines.len());
+        assert!(lines[0].starts_with("uri,split,kit_id,kit,file,instrument,labels,format"));
+        assert!(lines[1].contains(",kick,kick;808,wav,0.5,44100,,24,dropbox,drumkits,"));
+        assert!(lines[1].ends_with(",\"Kit, vol. 1\",2022-05-01T12:00:00"));
+    }
+
+    #[test]
+    fn test_write_jsonl() {
+        let mut buffer = Vec::new();
+        write_jsonl(&test_records(), &mut buffer).unwrap();
+        let output = String::from_utf8(buffer).unwrap();
+
+        let rows: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(2, rows.len());
+        assert_eq!(serde_json::json!(["kick", "808"]), rows[0]["labels"]);
+        assert!(rows[1]["subreddit"].is_null());
+        assert_eq!(rows[0]["split"], rows[1]["split"]);
+    }
+
+    #[test]
+    fn test_write_parquet() {
+        let path = std::env::temp_dir().join("chimecho_test_manifest.parquet");
+        write_parquet(&test_records(), fs::File::create(&path).unwrap()).unwrap();
+
+        let reader = SerializedFileReader::new(fs::File::open(&path).unwrap()).unwrap();
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(2, rows.len());
+        assert_eq!(
+            "gs://chimecho_bucket/unzipped/kit/808 Kick.wav",
+            rows[0].get_string(0).unwrap()
+        );
+        assert_eq!(2, rows[0].get_list(6).unwrap().len());
+        assert_eq!(0, rows[1].get_list(6).unwrap().len());
+        assert_eq!(44100, rows[0].get_int(9).unwrap());
+        assert!(rows[1].get_string(13).is_err());
+    }
+}
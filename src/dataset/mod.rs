@@ -0,0 +1,3 @@
+pub mod dedupe;
+pub mod manifest;
+pub mod split;
@@ -0,0 +1,563 @@
+use crate::postgres_orm::filter::SampleFilter;
+use crate::postgres_orm::models::NewSplitAssignment;
+use crate::postgres_orm::MetadataStore;
+use crate::query::TableRow;
+
+use serde::Serialize;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Split {
+    Train,
+    Val,
+    Test,
+}
+
+pub const SPLITS: [Split; 3] = [Split::Train, Split::Val, Split::Test];
+
+impl Split {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Split::Train => "train",
+            Split::Val => "val",
+            Split::Test => "test",
+        }
+    }
+}
+
+impl fmt::Display for Split {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Split {
+    type Err = anyhow::Error;
+
+    fn from_str(split: &str) -> anyhow::Result<Self> {
+        SPLITS
+            .iter()
+            .find(|val| val.as_str() == split)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("{} is not one of train, val or test", split))
+    }
+}
+
+/// Fractions of kits held out for validation and test, the rest is train.
+#[derive(Debug, Clone, Copy)]
+pub struct SplitRatios {
+    pub val: f64,
+    pub test: f64,
+}
+
+impl SplitRatios {
+    pub fn new(val: f64, test: f64) -> anyhow::Result<Self> {
+        if val < 0.0 || test < 0.0 || val + test > 1.0 {
+            anyhow::bail!(
+                "split ratios must be positive and add up to at most 1, got val {} and test {}",
+                val,
+                test
+            );
+        }
+
+        Ok(Self { val, test })
+    }
+
+    pub fn ratio(&self, split: Split) -> f64 {
+        match split {
+            Split::Train => 1.0 - self.val - self.test,
+            Split::Val => self.val,
+            Split::Test => self.test,
+        }
+    }
+}
+
+/// 64 bit FNV-1a followed by the splitmix64 finalizer. Unlike the std
+/// hashers it is guaranteed to give the same value across Rust versions and
+/// platforms, which is what keeps splits reproducible.
+pub fn stable_hash(key: &str, seed: u64) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325 ^ seed;
+    for byte in key.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// Maps a key to a point in `[0, 1)` that only depends on the key and seed.
+pub fn unit_interval(key: &str, seed: u64) -> f64 {
+    (stable_hash(key, seed) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Puts a whole kit in one split, so that samples from the same archive never
+/// end up on both sides of a train/test boundary.
+pub fn assign_split(kit: &str, seed: u64, ratios: &SplitRatios) -> Split {
+    let point = unit_interval(kit, seed);
+
+    if point < ratios.test {
+        Split::Test
+    } else if point < ratios.test + ratios.val {
+        Split::Val
+    } else {
+        Split::Train
+    }
+}
+
+/// Number of samples of each instrument in one kit, the unit that gets
+/// assigned to a split.
+#[derive(Debug, Clone, Default)]
+pub struct KitInstruments {
+    /// Id of the kit's file_source row, which is what tells apart kits that
+    /// share an archive name
+    pub file_source_id: i32,
+    pub kit: String,
+    pub instruments: BTreeMap<String, usize>,
+}
+
+impl KitInstruments {
+    fn sample_count(&self) -> usize {
+        self.instruments.values().sum()
+    }
+}
+
+/// Assigns every kit that isn't in `existing` to a split, keeping whole kits
+/// together while matching the ratios per instrument as closely as possible.
+///
+/// Kits are placed greedily, largest first (ties broken by a seeded hash of
+/// the kit name, then by id), into the split that is furthest below its target share of
+/// the kit's instruments. Existing assignments are never changed but count
+/// towards the targets, so new kits fill in whatever the old ones left
+/// unbalanced.
+pub fn stratified_assignments(
+    kits: &[KitInstruments],
+    existing: &HashMap<i32, Split>,
+    seed: u64,
+    ratios: &SplitRatios,
+) -> Vec<(i32, Split)> {
+    let mut totals: BTreeMap<&str, usize> = BTreeMap::new();
+    for kit in kits {
+        for (instrument, count) in &kit.instruments {
+            *totals.entry(instrument).or_insert(0) += count;
+        }
+    }
+
+    let mut current: HashMap<Split, BTreeMap<&str, usize>> = HashMap::new();
+    let mut current_kits: HashMap<Split, usize> = HashMap::new();
+
+    let mut new_kits = Vec::new();
+    for kit in kits {
+        match existing.get(&kit.file_source_id) {
+            Some(split) => add_kit(*split, kit, &mut current, &mut current_kits),
+            None => new_kits.push(kit),
+        }
+    }
+    new_kits.sort_by_key(|kit| {
+        (
+            Reverse(kit.sample_count()),
+            stable_hash(&kit.kit, seed),
+            kit.file_source_id,
+        )
+    });
+
+    let candidates: Vec<Split> = SPLITS
+        .iter()
+        .copied()
+        .filter(|split| ratios.ratio(*split) > 0.0)
+        .collect();
+    let total_kits = kits.len() as f64;
+
+    let mut assignments = Vec::new();
+    for kit in new_kits {
+        let score = |split: Split, current: &HashMap<Split, BTreeMap<&str, usize>>| {
+            let ratio = ratios.ratio(split);
+            let split_counts = current.get(&split);
+
+            // how much of its target the split is still missing for each of
+            // the kit's instruments, weighted by the kit's share of that
+            // instrument so that rare instruments aren't drowned out by kicks
+            let instrument_deficit: f64 = kit
+                .instruments
+                .iter()
+                .map(|(instrument, count)| {
+                    let total = totals[instrument.as_str()] as f64;
+                    let have = split_counts
+                        .and_then(|val| val.get(instrument.as_str()))
+                        .copied()
+                        .unwrap_or(0) as f64;
+                    (*count as f64 / total) * (1.0 - have / (ratio * total))
+                })
+                .sum();
+            let kit_deficit =
+                ratio * total_kits - current_kits.get(&split).copied().unwrap_or(0) as f64;
+
+            (instrument_deficit, kit_deficit)
+        };
+
+        let best = candidates
+            .iter()
+            .copied()
+            .map(|split| (split, score(split, &current)))
+            .fold(
+                None,
+                |best: Option<(Split, (f64, f64))>, candidate| match best {
+                    Some(val) if val.1.partial_cmp(&candidate.1) != Some(Ordering::Less) => {
+                        Some(val)
+                    }
+                    _ => Some(candidate),
+                },
+            )
+            .map(|(split, _)| split)
+            .unwrap_or(Split::Train);
+
+        add_kit(best, kit, &mut current, &mut current_kits);
+        assignments.push((kit.file_source_id, best));
+    }
+
+    assignments
+}
+
+fn add_kit<'a>(
+    split: Split,
+    kit: &'a KitInstruments,
+    current: &mut HashMap<Split, BTreeMap<&'a str, usize>>,
+    current_kits: &mut HashMap<Split, usize>,
+) {
+    *current_kits.entry(split).or_insert(0) += 1;
+    let split_counts = current.entry(split).or_default();
+    for (instrument, count) in &kit.instruments {
+        *split_counts.entry(instrument).or_insert(0) += count;
+    }
+}
+
+/// Instrument counts for every kit in the metadata store, including kits
+/// that were downloaded but have no samples yet.
+pub fn kit_instruments(store: &dyn MetadataStore) -> anyhow::Result<Vec<KitInstruments>> {
+    let filter = SampleFilter::default();
+
+    let mut kits: BTreeMap<i32, KitInstruments> = BTreeMap::new();
+    for source in store.find_file_sources(&filter)? {
+        kits.insert(
+            source.id,
+            KitInstruments {
+                file_source_id: source.id,
+                kit: source.compressed_file_name,
+                instruments: BTreeMap::new(),
+            },
+        );
+    }
+    for (sample, source) in store.find_samples(&filter)? {
+        *kits
+            .entry(source.id)
+            .or_insert_with(|| KitInstruments {
+                file_source_id: source.id,
+                kit: source.compressed_file_name,
+                instruments: BTreeMap::new(),
+            })
+            .instruments
+            .entry(sample.instrument)
+            .or_insert(0) += 1;
+    }
+
+    Ok(kits.into_values().collect())
+}
+
+/// Loads the stored assignments for `split_name`, assigns any kits that
+/// arrived since and stores those too.
+pub fn load_or_assign(
+    store: &dyn MetadataStore,
+    kits: &[KitInstruments],
+    split_name: &str,
+    seed: u64,
+    ratios: &SplitRatios,
+) -> anyhow::Result<HashMap<i32, Split>> {
+    let mut assignments = HashMap::new();
+    for assignment in store.find_split_assignments(split_name)? {
+        let split = assignment.split.parse()?;
+        assignments.insert(assignment.file_source_id, split);
+    }
+
+    let new_assignments = stratified_assignments(kits, &assignments, seed, ratios);
+    let names: HashMap<i32, &str> = kits
+        .iter()
+        .map(|kit| (kit.file_source_id, kit.kit.as_str()))
+        .collect();
+    let time_inserted = chrono::Utc::now().naive_utc();
+    let new_rows: Vec<NewSplitAssignment> = new_assignments
+        .iter()
+        .map(|(file_source_id, split)| NewSplitAssignment {
+            split_name,
+            compressed_file_name: names[file_source_id],
+            split: split.as_str(),
+            time_inserted,
+            file_source_id: *file_source_id,
+        })
+        .collect();
+
+    if !new_rows.is_empty() {
+        let inserted = store.insert_split_assignments(&new_rows)?;
+        info!(
+            "Assigned {} new kits to splits under the name {}",
+            inserted, split_name
+        );
+    }
+
+    assignments.extend(new_assignments);
+    Ok(assignments)
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SplitSummaryRow {
+    pub split: Split,
+    pub instrument: String,
+    pub kits: usize,
+    pub samples: usize,
+    /// Fraction of all samples of this instrument that are in this split
+    pub share: f64,
+}
+
+impl TableRow for SplitSummaryRow {
+    fn headers() -> Vec<&'static str> {
+        vec!["split", "instrument", "kits", "samples", "share"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.split.to_string(),
+            self.instrument.clone(),
+            self.kits.to_string(),
+            self.samples.to_string(),
+            format!("{:.3}", self.share),
+        ]
+    }
+}
+
+/// Kit and sample counts per split, overall (`all`) and per instrument.
+pub fn summarize(
+    kits: &[KitInstruments],
+    assignments: &HashMap<i32, Split>,
+) -> Vec<SplitSummaryRow> {
+    let mut totals: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut per_split: BTreeMap<(Split, &str), (usize, usize)> = BTreeMap::new();
+    for kit in kits {
+        let split = match assignments.get(&kit.file_source_id) {
+            Some(val) => *val,
+            None => continue,
+        };
+
+        let all = per_split.entry((split, "all")).or_insert((0, 0));
+        all.0 += 1;
+        all.1 += kit.sample_count();
+        *totals.entry("all").or_insert(0) += kit.sample_count();
+
+        for (instrument, count) in &kit.instruments {
+            let entry = per_split.entry((split, instrument)).or_insert((0, 0));
+            entry.0 += 1;
+            entry.1 += count;
+            *totals.entry(instrument).or_insert(0) += count;
+        }
+    }
+
+    per_split
+        .into_iter()
+        .map(
+            |((split, instrument), (kit_count, samples))| SplitSummaryRow {
+                split,
+                instrument: instrument.to_string(),
+                kits: kit_count,
+                samples,
+                share: match totals.get(instrument) {
+                    Some(total) if *total > 0 => samples as f64 / *total as f64,
+                    _ => 0.0,
+                },
+            },
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgres_orm::tests::{test_file_source, test_music_file, test_store};
+
+    fn test_kits() -> Vec<KitInstruments> {
+        (0..200)
+            .map(|kit_num| {
+                let mut instruments = BTreeMap::new();
+                instruments.insert("kick".to_string(), 1 + kit_num % 5);
+                if kit_num % 4 == 0 {
+                    instruments.insert("snare".to_string(), 3);
+                }
+                if kit_num % 10 == 0 {
+                    instruments.insert("flute".to_string(), 1);
+                }
+                KitInstruments {
+                    file_source_id: kit_num as i32,
+                    kit: format!("kit_{}.zip", kit_num),
+                    instruments,
+                }
+            })
+            .collect()
+    }
+
+    fn instrument_share(
+        kits: &[KitInstruments],
+        assignments: &HashMap<i32, Split>,
+        split: Split,
+        instrument: &str,
+    ) -> f64 {
+        summarize(kits, assignments)
+            .into_iter()
+            .find(|row| row.split == split && row.instrument == instrument)
+            .map(|row| row.share)
+            .unwrap_or(0.0)
+    }
+
+    #[test]
+    fn test_assign_split_is_deterministic() {
+        let ratios = SplitRatios::new(0.1, 0.1).unwrap();
+
+        for kit in ["kit.zip", "1abcID.rar", "Cool Kit.zip"] {
+            assert_eq!(assign_split(kit, 7, &ratios), assign_split(kit, 7, &ratios));
+        }
+        // pinned, so that a change to the hash doesn't silently reshuffle
+        // everyone's splits
+        assert_eq!(4_301_074_974_334_778_818, stable_hash("kit.zip", 0));
+    }
+
+    #[test]
+    fn test_assign_split_ratios() {
+        let ratios = SplitRatios::new(0.2, 0.1).unwrap();
+
+        let mut counts = [0; 3];
+        for kit_num in 0..10_000 {
+            match assign_split(&format!("kit_{}.zip", kit_num), 42, &ratios) {
+                Split::Train => counts[0] += 1,
+                Split::Val => counts[1] += 1,
+                Split::Test => counts[2] += 1,
+            }
+        }
+
+        assert!((6_800..7_200).contains(&counts[0]));
+        assert!((1_800..2_200).contains(&counts[1]));
+        assert!((800..1_200).contains(&counts[2]));
+    }
+
+    #[test]
+    fn test_split_ratios_validation() {
+        assert!(SplitRatios::new(0.5, 0.6).is_err());
+        assert!(SplitRatios::new(-0.1, 0.1).is_err());
+        assert!(SplitRatios::new(0.0, 0.0).is_ok());
+    }
+
+    #[test]
+    fn test_stratified_assignments() {
+        let kits = test_kits();
+        let ratios = SplitRatios::new(0.2, 0.1).unwrap();
+
+        let assignments: HashMap<i32, Split> =
+            stratified_assignments(&kits, &HashMap::new(), 3, &ratios)
+                .into_iter()
+                .collect();
+        assert_eq!(kits.len(), assignments.len());
+
+        for instrument in ["all", "kick", "snare", "flute"] {
+            for split in SPLITS {
+                let share = instrument_share(&kits, &assignments, split, instrument);
+                assert!(
+                    (share - ratios.ratio(split)).abs() < 0.06,
+                    "{} share of {} is {}",
+                    split,
+                    instrument,
+                    share
+                );
+            }
+        }
+
+        let again: HashMap<i32, Split> = stratified_assignments(&kits, &HashMap::new(), 3, &ratios)
+            .into_iter()
+            .collect();
+        assert_eq!(assignments, again);
+    }
+
+    #[test]
+    fn test_stratified_assignments_keep_existing() {
+        let kits = test_kits();
+        let ratios = SplitRatios::new(0.2, 0.1).unwrap();
+
+        // everything from the first half went to train, so the second half
+        // has to make up the val and test shares
+        let existing: HashMap<i32, Split> = kits[..100]
+            .iter()
+            .map(|kit| (kit.file_source_id, Split::Train))
+            .collect();
+        let new_assignments = stratified_assignments(&kits, &existing, 0, &ratios);
+
+        assert_eq!(100, new_assignments.len());
+        assert!(new_assignments
+            .iter()
+            .all(|(kit, _)| !existing.contains_key(kit)));
+
+        let mut assignments = existing;
+        assignments.extend(new_assignments);
+        let val_share = instrument_share(&kits, &assignments, Split::Val, "all");
+        assert!((val_share - 0.2).abs() < 0.06, "val share is {}", val_share);
+    }
+
+    #[test]
+    fn test_load_or_assign_kits_sharing_a_name() {
+        let store = test_store();
+        let mut sources = Vec::new();
+        for url in ["https://a", "https://b"] {
+            let source = store
+                .create_file_row(&test_file_source(url, "kit.zip", "dropbox", "2022-01-01"))
+                .unwrap();
+            store
+                .bulk_insert_music_files(&[test_music_file(
+                    "kit.zip", "kick.wav", "kick", source.id, 0.4,
+                )])
+                .unwrap();
+            sources.push(source.id);
+        }
+
+        let kits = kit_instruments(store.as_ref()).unwrap();
+        assert_eq!(
+            sources,
+            kits.iter()
+                .map(|kit| kit.file_source_id)
+                .collect::<Vec<_>>()
+        );
+
+        // both kits are stored, rather than the second being dropped as a
+        // duplicate of the first
+        let ratios = SplitRatios::new(1.0, 0.0).unwrap();
+        let assignments = load_or_assign(store.as_ref(), &kits, "v1", 0, &ratios).unwrap();
+        assert_eq!(2, assignments.len());
+
+        let stored = store.find_split_assignments("v1").unwrap();
+        assert_eq!(
+            sources,
+            stored
+                .iter()
+                .map(|val| val.file_source_id)
+                .collect::<Vec<_>>()
+        );
+        assert!(stored.iter().all(|val| val.split == "val"));
+        assert_eq!(
+            assignments,
+            load_or_assign(store.as_ref(), &kits, "v1", 0, &ratios).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_split_from_str() {
+        assert_eq!(Split::Val, "val".parse::<Split>().unwrap());
+        assert!("validation".parse::<Split>().is_err());
+    }
+}
@@ -0,0 +1,530 @@
+pub mod connector;
+pub mod rate_limit;
+#[cfg(test)]
+pub mod test_server;
+
+use crate::config::HttpSettings;
+use indicatif::ProgressBar;
+use reqwest::header::RETRY_AFTER;
+use reqwest::redirect::Policy;
+use reqwest::{Client, IntoUrl, Proxy, RequestBuilder, Response, StatusCode};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use self::rate_limit::{HostLimiter, RateLimit};
+use crate::progress;
+
+/// How failed requests are tried again. Delays grow exponentially from
+/// `base_delay`, up to `max_delay`, with full jitter so that requests held
+/// back by the same error don't all come back at once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 4,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (counting from 0), picked at random
+    /// between nothing and the exponential delay for it.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .checked_mul(2u32.saturating_pow(retry))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        exponential.mul_f64(fastrand::f64())
+    }
+}
+
+/// Everything about how `HttpClient` sends requests
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    pub retry: RetryPolicy,
+    /// Rate limit for hosts without one of their own
+    pub default_rate_limit: RateLimit,
+    /// Rate limits for particular hosts, by host name. A host also matches
+    /// the limits of the domains it is a subdomain of.
+    pub host_rate_limits: Vec<(String, RateLimit)>,
+    pub connect_timeout: Duration,
+    /// Longest wait for a host to answer, and then for each chunk of the body
+    pub read_timeout: Duration,
+    pub proxy: Option<String>,
+    pub user_agent: String,
+    pub max_body_bytes: u64,
+}
+
+impl HttpConfig {
+    pub fn from_settings(settings: &HttpSettings) -> Self {
+        Self {
+            retry: RetryPolicy {
+                max_retries: settings.max_retries,
+                ..RetryPolicy::default()
+            },
+            default_rate_limit: RateLimit::new(settings.requests_per_second, 5),
+            host_rate_limits: settings
+                .host_rate_limits
+                .iter()
+                .map(|(host, limit)| {
+                    (
+                        host.clone(),
+                        RateLimit::new(limit.requests_per_second, limit.burst),
+                    )
+                })
+                .collect(),
+            connect_timeout: Duration::from_secs(settings.connect_timeout_secs),
+            read_timeout: Duration::from_secs(settings.read_timeout_secs),
+            proxy: settings.proxy.clone(),
+            user_agent: settings.user_agent.clone(),
+            max_body_bytes: settings.max_body_bytes,
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self::from_settings(&HttpSettings::default())
+    }
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    Request(reqwest::Error),
+    /// The host stopped sending for longer than the read timeout
+    ReadTimeout {
+        url: String,
+        timeout: Duration,
+    },
+    /// The body is bigger than `max_body_bytes`
+    TooLarge {
+        url: String,
+        limit: u64,
+    },
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Request(e) => write!(f, "{}", e),
+            HttpError::ReadTimeout { url, timeout } => {
+                write!(f, "{} sent nothing for {} seconds", url, timeout.as_secs())
+            }
+            HttpError::TooLarge { url, limit } => {
+                write!(f, "{} is larger than the {} byte limit", url, limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HttpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HttpError::Request(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for HttpError {
+    fn from(e: reqwest::Error) -> Self {
+        HttpError::Request(e)
+    }
+}
+
+/// The client every source and provider sends requests with. Requests wait
+/// their turn under the host's rate limit, and are tried again on connection
+/// errors, timeouts, 429s and 5xx errors. Cookies are kept, as a few hosts
+/// hand out the cookie a download needs on an earlier page.
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client,
+    config: Arc<HttpConfig>,
+    // shared by clones, so a host's limit holds however many of them are
+    // talking to it
+    limiter: Arc<HostLimiter>,
+    // bytes of every file read with `bytes`, also shared by clones
+    downloaded: Arc<AtomicU64>,
+}
+
+impl HttpClient {
+    pub fn new(config: HttpConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: build_client(&config, Policy::default())?,
+            config: Arc::new(config),
+            limiter: Arc::new(HostLimiter::new()),
+            downloaded: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub fn from_settings(settings: &HttpSettings) -> anyhow::Result<Self> {
+        Self::new(HttpConfig::from_settings(settings))
+    }
+
+    /// The same client, except that redirects are handed back rather than
+    /// followed
+    pub fn without_redirects(&self) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: build_client(&self.config, Policy::none())?,
+            config: self.config.clone(),
+            limiter: self.limiter.clone(),
+            downloaded: self.downloaded.clone(),
+        })
+    }
+
+    /// The settings this client was built with, for requests sent some
+    /// other way that should still follow them
+    pub fn config(&self) -> &HttpConfig {
+        &self.config
+    }
+
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    /// Sends a request built from this client's `get` or `post`. Once out
+    /// of retries, the last response is returned as is, error status and
+    /// all, for the caller to deal with.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, HttpError> {
+        let config = &self.config;
+        let request = request.build()?;
+        let host = request.url().host_str().unwrap_or_default().to_lowercase();
+        let rate_limit = rate_limit::limit_for(config, &host);
+
+        let mut retry = 0;
+        loop {
+            self.limiter.acquire(&host, &rate_limit).await;
+
+            // requests with a streamed body can't be sent a second time
+            let attempt = match request.try_clone() {
+                Some(val) => val,
+                None => return self.execute(request).await,
+            };
+            let result = self.execute(attempt).await;
+
+            let delay = match &result {
+                Ok(response) if is_retryable_status(response.status()) => {
+                    // a host asking for a longer wait than `max_delay` is
+                    // tried again after `max_delay` all the same, rather than
+                    // given up on while there are retries left
+                    match retry_after(response) {
+                        Some(val) => val.min(config.retry.max_delay),
+                        None => config.retry.backoff(retry),
+                    }
+                }
+                Err(HttpError::Request(e)) if e.is_timeout() || e.is_connect() => {
+                    config.retry.backoff(retry)
+                }
+                Err(HttpError::ReadTimeout { .. }) => config.retry.backoff(retry),
+                _ => return result,
+            };
+            if retry >= config.retry.max_retries {
+                return result;
+            }
+
+            warn!(
+                "Request to {} failed ({}), retrying in {:.1}s ({} of {})",
+                request.url(),
+                match &result {
+                    Ok(response) => response.status().to_string(),
+                    Err(e) => e.to_string(),
+                },
+                delay.as_secs_f64(),
+                retry + 1,
+                config.retry.max_retries
+            );
+            tokio::time::sleep(delay).await;
+            retry += 1;
+        }
+    }
+
+    async fn execute(&self, request: reqwest::Request) -> Result<Response, HttpError> {
+        let url = request.url().to_string();
+        match tokio::time::timeout(self.config.read_timeout, self.client.execute(request)).await {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(HttpError::ReadTimeout {
+                url,
+                timeout: self.config.read_timeout,
+            }),
+        }
+    }
+
+    /// Total bytes of the files downloaded with `bytes` so far
+    pub fn downloaded_bytes(&self) -> u64 {
+        self.downloaded.load(Ordering::Relaxed)
+    }
+
+    /// Reads the whole body of a file being downloaded, showing how much of
+    /// it has come in. Gives up once it goes past `max_body_bytes` or the
+    /// host stops sending for longer than the read timeout.
+    pub async fn bytes(&self, response: Response) -> Result<Vec<u8>, HttpError> {
+        let bar = progress::download(response.content_length(), &file_name(&response));
+        let result = self.read_body(response, Some(&bar)).await;
+        bar.finish_and_clear();
+
+        let body = result?;
+        self.downloaded
+            .fetch_add(body.len() as u64, Ordering::Relaxed);
+        Ok(body)
+    }
+
+    async fn read_body(
+        &self,
+        mut response: Response,
+        bar: Option<&ProgressBar>,
+    ) -> Result<Vec<u8>, HttpError> {
+        let url = response.url().to_string();
+        let limit = self.config.max_body_bytes;
+        let too_large = || HttpError::TooLarge {
+            url: url.clone(),
+            limit,
+        };
+        if response.content_length().unwrap_or_default() > limit {
+            return Err(too_large());
+        }
+
+        let mut body = Vec::new();
+        loop {
+            let chunk = tokio::time::timeout(self.config.read_timeout, response.chunk())
+                .await
+                .map_err(|_| HttpError::ReadTimeout {
+                    url: url.clone(),
+                    timeout: self.config.read_timeout,
+                })??;
+            match chunk {
+                Some(val) if body.len() as u64 + val.len() as u64 > limit => {
+                    return Err(too_large())
+                }
+                Some(val) => {
+                    body.extend_from_slice(&val);
+                    if let Some(bar) = bar {
+                        bar.inc(val.len() as u64);
+                    }
+                }
+                None => return Ok(body),
+            }
+        }
+    }
+
+    /// Reads the whole body as text, under the same limits as `bytes`
+    pub async fn text(&self, response: Response) -> Result<String, HttpError> {
+        let body = self.read_body(response, None).await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+}
+
+/// What a download's progress bar is labelled with: the last segment of the
+/// url, which is usually the file's name, or the host when there's none
+fn file_name(response: &Response) -> String {
+    let url = response.url();
+    url.path_segments()
+        .and_then(|mut val| val.rfind(|segment| !segment.is_empty()))
+        .and_then(|val| percent_encoding::percent_decode_str(val).decode_utf8().ok())
+        .map(|val| val.into_owned())
+        .unwrap_or_else(|| url.host_str().unwrap_or_default().to_string())
+}
+
+fn build_client(config: &HttpConfig, redirect: Policy) -> anyhow::Result<Client> {
+    let mut builder = Client::builder()
+        .cookie_store(true)
+        .redirect(redirect)
+        .connect_timeout(config.connect_timeout)
+        .user_agent(config.user_agent.as_str());
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(
+            Proxy::all(proxy.as_str())
+                .map_err(|e| anyhow::anyhow!("Invalid proxy {}: {}", proxy, e))?,
+        );
+    }
+
+    Ok(builder.build()?)
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// How long a `Retry-After` header asks to wait, given either as a number of
+/// seconds or as a date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, SystemTime::now())
+}
+
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    // a date that has already passed means no wait at all
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(8),
+        };
+
+        for retry in 0..10 {
+            let cap = Duration::from_millis(500 * 2u64.pow(retry)).min(policy.max_delay);
+            for _ in 0..20 {
+                assert!(policy.backoff(retry) <= cap, "retry {}", retry);
+            }
+        }
+        // far past the point where the delay would overflow
+        assert!(policy.backoff(200) <= policy.max_delay);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+
+        let cases = [
+            ("120", Some(Duration::from_secs(120))),
+            (" 0 ", Some(Duration::from_secs(0))),
+            (
+                "Wed, 21 Oct 2015 07:29:30 GMT",
+                Some(Duration::from_secs(90)),
+            ),
+            (
+                "Wed, 21 Oct 2015 07:00:00 GMT",
+                Some(Duration::from_secs(0)),
+            ),
+            ("soon", None),
+            ("-5", None),
+        ];
+
+        for (value, expected) in cases.iter() {
+            assert_eq!(*expected, parse_retry_after(value, now), "{}", value);
+        }
+    }
+
+    /// Answers each request with the next response in `responses`, with
+    /// `ok` as the body
+    fn serve(responses: &'static [&'static str]) -> String {
+        let mut responses = responses.iter();
+        test_server::serve(move |_| responses.next().map(|head| (*head, "ok")))
+    }
+
+    fn test_client(max_body_bytes: u64) -> HttpClient {
+        HttpClient::new(HttpConfig {
+            retry: RetryPolicy {
+                max_retries: 2,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(50),
+            },
+            default_rate_limit: RateLimit::new(100.0, 10),
+            max_body_bytes,
+            ..HttpConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let client = test_client(1024);
+
+        let address = serve(&[
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0",
+            "HTTP/1.1 503 Service Unavailable",
+            "HTTP/1.1 200 OK",
+        ]);
+        let response = client.send(client.get(&address)).await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        // out of retries, the last answer is handed back
+        let address = serve(&[
+            "HTTP/1.1 502 Bad Gateway",
+            "HTTP/1.1 502 Bad Gateway",
+            "HTTP/1.1 502 Bad Gateway",
+        ]);
+        let response = client.send(client.get(&address)).await.unwrap();
+        assert_eq!(StatusCode::BAD_GATEWAY, response.status());
+
+        // a wait longer than the longest delay is cut down to it, and the
+        // request still tried again
+        let address = serve(&[
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 3600",
+            "HTTP/1.1 200 OK",
+        ]);
+        let response = client.send(client.get(&address)).await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        // other errors aren't retried
+        let address = serve(&["HTTP/1.1 404 Not Found"]);
+        let response = client.send(client.get(&address)).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+    }
+
+    #[tokio::test]
+    async fn test_bytes() {
+        let address = serve(&["HTTP/1.1 200 OK", "HTTP/1.1 200 OK"]);
+
+        let client = test_client(2);
+        let response = client.send(client.get(&address)).await.unwrap();
+        assert_eq!(b"ok".to_vec(), client.bytes(response).await.unwrap());
+
+        let client = test_client(1);
+        let response = client.send(client.get(&address)).await.unwrap();
+        assert_eq!(
+            format!("{}/ is larger than the 1 byte limit", address),
+            client.bytes(response).await.unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_proxy() {
+        let config = HttpConfig {
+            proxy: Some("http://localhost:3128".to_string()),
+            ..HttpConfig::default()
+        };
+        assert!(HttpClient::new(config).is_ok());
+
+        let config = HttpConfig {
+            proxy: Some("not a proxy".to_string()),
+            ..HttpConfig::default()
+        };
+        assert!(HttpClient::new(config).is_err());
+    }
+
+    #[test]
+    fn test_is_retryable_status() {
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_retryable_status(StatusCode::NOT_FOUND));
+        assert!(!is_retryable_status(StatusCode::FORBIDDEN));
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::HttpConfig;
+
+/// A token bucket: up to `burst` requests can go out at once, after which
+/// they're let through at `per_second`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        Self { per_second, burst }
+    }
+}
+
+/// Rate limit for a host: the one set for it, or for the closest domain it
+/// is a subdomain of, or the default.
+pub fn limit_for(config: &HttpConfig, host: &str) -> RateLimit {
+    config
+        .host_rate_limits
+        .iter()
+        .filter(|(domain, _)| {
+            host == domain
+                || host
+                    .strip_suffix(domain.as_str())
+                    .is_some_and(|rest| rest.ends_with('.'))
+        })
+        .max_by_key(|(domain, _)| domain.len())
+        .map(|(_, limit)| *limit)
+        .unwrap_or(config.default_rate_limit)
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Goes below zero when requests are waiting on tokens that haven't
+    /// come in yet
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    /// Takes a token and returns how long to wait for it to be there
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> Duration {
+        let burst = limit.burst.max(1) as f64;
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(burst);
+        self.updated_at = now;
+
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 || limit.per_second <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / limit.per_second)
+        }
+    }
+}
+
+/// A bucket per host, shared by every request
+#[derive(Debug, Default)]
+pub struct HostLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl HostLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Waits until the host's rate limit lets another request through
+    pub async fn acquire(&self, host: &str, limit: &RateLimit) {
+        let delay = self.reserve(host, limit, Instant::now());
+        if !delay.is_zero() {
+            debug!("Waiting {:.1}s for {}", delay.as_secs_f64(), host);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    fn reserve(&self, host: &str, limit: &RateLimit, now: Instant) -> Duration {
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets
+            .entry(host.to_string())
+            .or_insert_with(|| Bucket {
+                tokens: limit.burst.max(1) as f64,
+                updated_at: now,
+            })
+            .take(limit, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve() {
+        let limiter = HostLimiter::new();
+        let limit = RateLimit::new(2.0, 2);
+        let start = Instant::now();
+
+        // the burst goes straight through, then requests are spaced out
+        assert_eq!(Duration::ZERO, limiter.reserve("a.com", &limit, start));
+        assert_eq!(Duration::ZERO, limiter.reserve("a.com", &limit, start));
+        assert_eq!(
+            Duration::from_millis(500),
+            limiter.reserve("a.com", &limit, start)
+        );
+        assert_eq!(
+            Duration::from_millis(1000),
+            limiter.reserve("a.com", &limit, start)
+        );
+        // other hosts have their own bucket
+        assert_eq!(Duration::ZERO, limiter.reserve("b.com", &limit, start));
+
+        // tokens come back with time, up to the burst
+        let later = start + Duration::from_secs(10);
+        assert_eq!(Duration::ZERO, limiter.reserve("a.com", &limit, later));
+        assert_eq!(Duration::ZERO, limiter.reserve("a.com", &limit, later));
+        assert_eq!(
+            Duration::from_millis(500),
+            limiter.reserve("a.com", &limit, later)
+        );
+    }
+
+    #[test]
+    fn test_limit_for() {
+        let config = HttpConfig {
+            default_rate_limit: RateLimit::new(5.0, 5),
+            host_rate_limits: vec![
+                ("mediafire.com".to_string(), RateLimit::new(1.0, 2)),
+                ("download.mediafire.com".to_string(), RateLimit::new(0.5, 1)),
+            ],
+            ..HttpConfig::default()
+        };
+
+        let cases = [
+            ("mediafire.com", RateLimit::new(1.0, 2)),
+            ("www.mediafire.com", RateLimit::new(1.0, 2)),
+            ("download.mediafire.com", RateLimit::new(0.5, 1)),
+            ("download1.download.mediafire.com", RateLimit::new(0.5, 1)),
+            ("notmediafire.com", RateLimit::new(5.0, 5)),
+        ];
+
+        for (host, expected) in cases.iter() {
+            assert_eq!(*expected, limit_for(&config, host), "{}", host);
+        }
+    }
+}
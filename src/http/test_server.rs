@@ -0,0 +1,46 @@
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+/// Serves requests on a local port and returns its address. Each request is
+/// answered with what `respond` gives for its path: the status line and
+/// headers, and the body. The server stops once `respond` gives `None`, and
+/// otherwise runs for as long as the tests do.
+pub fn serve<F>(mut respond: F) -> String
+where
+    F: FnMut(&str) -> Option<(&'static str, &'static str)> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.windows(4).any(|val| val == b"\r\n\r\n") {
+                let read = stream.read(&mut buf).unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..read]);
+            }
+
+            let request = String::from_utf8_lossy(&request);
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+            let (head, body) = match respond(path) {
+                Some(val) => val,
+                None => return,
+            };
+            let response = format!(
+                "{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                head,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    address
+}
@@ -1,23 +1,42 @@
 #[macro_use]
 extern crate diesel;
+#[macro_use]
+extern crate diesel_migrations;
+mod audio;
+mod config;
+mod dataset;
+mod http;
 mod postgres_orm;
+mod progress;
+mod query;
 mod source;
 mod storage_download;
 
+use audio::AudioProperties;
+use config::{Config, ProviderSettings, SourceSettings};
+use dataset::manifest::{ManifestFormat, ManifestOptions};
+use dataset::split::SplitRatios;
+use http::HttpClient;
+use postgres_orm::models::DownloadJobUpdate;
 use source::reddit;
-use source::reddit::{RedditPost, RequestSubmissionResponse};
+use source::reddit::{RedditPost, RequestSubmissionResponse, SubmissionPost};
 
-use storage_download::download_utils;
-use storage_download::dropbox::DropboxMetadata;
-use storage_download::google_drive::get_google_drive_connector;
-use storage_download::google_drive::GoogleDriveMetadata;
-use storage_download::google_drive::GoogleFileType;
-use storage_download::mediafire::MediaFireMetadata;
-use storage_download::{AssocDataForDownload, DownloadFiles, DownloadOptions};
+use storage_download::download_utils::{self, SampleRules};
+use storage_download::error::DownloadError;
+use storage_download::google_drive::{get_google_drive_connector, DriveApi};
+use storage_download::jobs::{self, JobStatus};
+use storage_download::plan::{PlannedDownload, PlannedUpload};
+use storage_download::registry::{provider_for, ProviderRegistry};
+use storage_download::resolver::{LinkResolver, ResolvedLink};
+use storage_download::summary::RunSummary;
+use storage_download::{DownloadFiles, DownloadOptions};
 
-use anyhow;
 use clap::{Parser, Subcommand};
 use itertools::izip;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Instant;
 #[macro_use]
 extern crate log;
 
@@ -29,6 +48,9 @@ extern crate log;
 )]
 /// Program used to get music sample data from Reddit
 struct Cli {
+    /// Config file to read instead of chimecho.toml (or CHIMECHO_CONFIG)
+    #[clap(long, global = true)]
+    config: Option<String>,
     #[clap(subcommand)]
     cmd: SubCommand,
 }
@@ -44,144 +66,603 @@ enum SubCommand {
         /// Example: "after=1586604030&before=1605097230"
         #[clap(short, long)]
         time_period: Option<String>,
+        /// Subreddit to get posts from [default: drumkits]
+        #[clap(long)]
+        subreddit: Option<String>,
         /// Number of steps to iterate over posts list
         #[clap(short, long)]
         step_size: Option<usize>,
-        /// File path folder for the music data to live in
+        /// File path folder for the music data to live in. Defaults to download_dir in chimecho.toml
         #[clap(short, long)]
-        file_path: String,
+        file_path: Option<String>,
+        /// Also look for download links in the top-level comments the author
+        /// left on their own post. Costs one extra request per post
+        #[clap(long)]
+        op_comments: bool,
+        /// Only download the links queued by earlier crawls, without getting new posts from Reddit
+        #[clap(long)]
+        resume: bool,
+        /// Put the links that failed in earlier crawls back in the queue, with their attempts reset
+        #[clap(long)]
+        retry_failed: bool,
+        /// Times a request is tried again after a connection error, timeout, 429 or 5xx answer
+        #[clap(long)]
+        max_retries: Option<u32>,
+        /// Requests per second sent to each host, for hosts without a limit of their own
+        #[clap(long)]
+        requests_per_second: Option<f64>,
+        /// List the links a crawl would find, their provider and file name and whether they are
+        /// already downloaded, without downloading anything or touching the metadata store
+        #[clap(long, conflicts_with_all = &["resume", "retry-failed"])]
+        dry_run: bool,
+        /// Output format of the --dry-run listing and the crawl summary
+        #[clap(short, long, arg_enum, default_value = "table")]
+        output: query::OutputFormat,
     },
     // Upload downloaded sample data to GCS
     Upload {
-        /// File path folder that contains zip and rar files
+        /// File path folder that contains zip and rar files. Defaults to download_dir in chimecho.toml
+        #[clap(short, long)]
+        file_path: Option<String>,
+        /// bucket name for google cloud storage upload. Defaults to bucket in chimecho.toml
+        #[clap(short, long)]
+        bucket: Option<String>,
+        /// List the archives found, the samples that would be stored and the objects they would
+        /// be uploaded to, without extracting anything or touching the metadata store or bucket
+        #[clap(long)]
+        dry_run: bool,
+        /// Output format of the --dry-run listing
+        #[clap(short, long, arg_enum, default_value = "table")]
+        output: query::OutputFormat,
+    },
+    /// List the kits and samples collected in the metadata store
+    Query {
+        #[clap(subcommand)]
+        target: QueryTarget,
+    },
+    /// Export a dataset manifest with one record per sample, for model training
+    Manifest {
+        #[clap(flatten)]
+        filter: query::FilterArgs,
+        /// File path the manifest is written to
         #[clap(short, long)]
         file_path: String,
-        /// bucket name for google cloud storage upload
+        /// Manifest format
+        #[clap(long, arg_enum, default_value = "jsonl")]
+        format: ManifestFormat,
+        /// bucket name the samples were uploaded to. Records point at the local unzipped folder without one
         #[clap(short, long)]
-        bucket: String,
+        bucket: Option<String>,
+        /// Fraction of kits held out for validation
+        #[clap(long, default_value = "0.1")]
+        val_ratio: f64,
+        /// Fraction of kits held out for test
+        #[clap(long, default_value = "0.1")]
+        test_ratio: f64,
+        /// Seed for assigning kits to splits. The same seed always gives the same splits
+        #[clap(long, default_value = "0")]
+        seed: u64,
+        /// Use the stratified split stored under this name (see the split subcommand) instead of hashing kit names
+        #[clap(long)]
+        split_name: Option<String>,
+    },
+    /// Assign kits to train/val/test splits, stratified by instrument, and store the assignments
+    Split {
+        /// Name the assignments are stored under. Kits that already have a split under this name keep it
+        #[clap(short, long, default_value = "default")]
+        name: String,
+        /// Fraction of kits held out for validation
+        #[clap(long, default_value = "0.1")]
+        val_ratio: f64,
+        /// Fraction of kits held out for test
+        #[clap(long, default_value = "0.1")]
+        test_ratio: f64,
+        /// Seed for the order new kits are assigned in
+        #[clap(long, default_value = "0")]
+        seed: u64,
+        /// Output format of the per split summary
+        #[clap(short, long, arg_enum, default_value = "table")]
+        output: query::OutputFormat,
+    },
+    /// Find perceptual near-duplicate samples, such as the same kick re-exported at a different bit depth
+    Dedupe {
+        #[clap(flatten)]
+        filter: query::FilterArgs,
+        /// Folder the samples were extracted to. Defaults to unzipped_dir in chimecho.toml
+        #[clap(short, long)]
+        dir: Option<String>,
+        /// Minimum fingerprint similarity, between 0 and 1, for two samples to count as near-duplicates
+        #[clap(short, long, default_value = "0.95")]
+        threshold: f32,
+        /// Store the near-duplicates found in music_files.duplicate_of, replacing earlier marks of the samples checked
+        #[clap(long)]
+        mark: bool,
+        /// Output format of the near-duplicate report
+        #[clap(short, long, arg_enum, default_value = "table")]
+        output: query::OutputFormat,
+    },
+    /// Manage the metadata store
+    Db {
+        #[clap(subcommand)]
+        cmd: DbCommand,
+    },
+    /// Inspect the settings read from chimecho.toml and the environment
+    Config {
+        #[clap(subcommand)]
+        cmd: ConfigCommand,
     },
 }
 
-fn get_zip_music(
+#[derive(Subcommand, Debug)]
+enum QueryTarget {
+    /// List downloaded kits, with the number of their samples that match
+    Kits {
+        #[clap(flatten)]
+        filter: query::FilterArgs,
+        /// Output format
+        #[clap(short, long, arg_enum, default_value = "table")]
+        output: query::OutputFormat,
+    },
+    /// List individual samples along with the kit they came from
+    Samples {
+        #[clap(flatten)]
+        filter: query::FilterArgs,
+        /// Output format
+        #[clap(short, long, arg_enum, default_value = "table")]
+        output: query::OutputFormat,
+    },
+    /// List the links queued by the download subcommand, with how each one went
+    Jobs {
+        /// Only include jobs with this status
+        #[clap(long, arg_enum)]
+        status: Option<JobStatus>,
+        /// Output format
+        #[clap(short, long, arg_enum, default_value = "table")]
+        output: query::OutputFormat,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum DbCommand {
+    /// Apply any pending schema migrations to the configured database (postgres:// or sqlite://)
+    Migrate,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the settings in effect, after the config file and environment are applied
+    Show,
+}
+
+/// Options for where a crawl picks its links up from
+struct CrawlOptions {
     q: Option<String>,
     time_period: Option<String>,
-    step_size: Option<usize>,
-    file_path: String,
+    /// Only work the jobs already queued, without asking Reddit for more
+    resume: bool,
+    /// Put failed jobs back in the queue before starting
+    retry_failed: bool,
+}
+
+fn get_zip_music(
+    config: &Config,
+    options: CrawlOptions,
+    output: query::OutputFormat,
 ) -> anyhow::Result<()> {
-    let posts = reddit::get_posts(q, time_period);
-    let response = match posts {
-        Ok(val) => val,
-        Err(e) => panic!(
-            "There was an issue reading data from reddit with {}. Quitting program.",
-            e
-        ),
-    };
+    let started = Instant::now();
+    let file_path = config.storage.download_dir()?;
+    info!("The file path that was passed from the CLI: {}", file_path);
 
-    let step_size = step_size.unwrap_or(1);
+    let client = HttpClient::from_settings(&config.http)?;
+    let store = postgres_orm::establish_connection(&config.database)?;
 
-    info!("The file path that was passed from the CLI: {}", &file_path);
+    if options.retry_failed {
+        let requeued = store.requeue_failed_jobs()?;
+        info!("Put {} failed jobs back in the queue", requeued);
+    }
+    if !options.resume {
+        queue_posts(&client, store.as_ref(), &config.source, &options)?;
+    }
+
+    let summary = download_queued(
+        &client,
+        store.as_ref(),
+        &config.providers,
+        file_path.to_string(),
+    )?;
+
+    query::write_rows(
+        &summary.rows(started.elapsed()),
+        output,
+        std::io::stdout().lock(),
+    )
+}
+
+/// Gets posts from Reddit and queues a download job for every link in them.
+/// Links queued by an earlier crawl keep the job they have.
+fn queue_posts(
+    client: &HttpClient,
+    store: &dyn postgres_orm::MetadataStore,
+    source: &SourceSettings,
+    options: &CrawlOptions,
+) -> anyhow::Result<()> {
+    let submissions = get_submissions(client, source, options)?;
+    let posts = post_links(client, source, &submissions);
+
+    let new_jobs: Vec<_> = posts
+        .iter()
+        .map(|(post_id, post)| post.new_download_job(post_id))
+        .collect();
+    let queued = store.enqueue_download_jobs(&new_jobs)?;
+    info!(
+        "Queued {} new links out of the {} found in {} posts",
+        queued,
+        new_jobs.len(),
+        submissions.len()
+    );
+
+    Ok(())
+}
+
+fn get_submissions(
+    client: &HttpClient,
+    source: &SourceSettings,
+    options: &CrawlOptions,
+) -> anyhow::Result<Vec<SubmissionPost>> {
+    let response = reddit::get_posts(
+        client,
+        source,
+        options.q.clone(),
+        options.time_period.clone(),
+    )
+    .map_err(|e| anyhow::anyhow!("There was an issue reading data from reddit: {}", e))?;
 
     let vec_basic_list: RequestSubmissionResponse = serde_json::from_str(&response)?;
-    let vec_basic_list = vec_basic_list.items;
-    let submission_data_vec = vec_basic_list.iter().step_by(step_size).filter_map(|sub| {
-        sub.url.as_ref().map(|url| {
-            RedditPost::new(
-                sub.domain.as_str(),
-                url.clone(),
-                sub.subreddit.clone(),
-                sub.score,
-                sub.title.clone(),
+    Ok(vec_basic_list.items)
+}
+
+/// Every link to download from the posts, with the id of the post it's in.
+/// A post can link to more than one kit, each of which is downloaded as its
+/// own post.
+fn post_links<'a>(
+    client: &HttpClient,
+    source: &SourceSettings,
+    submissions: &'a [SubmissionPost],
+) -> Vec<(&'a str, RedditPost)> {
+    let scanned: Vec<&SubmissionPost> = submissions
+        .iter()
+        .step_by(source.step_size.max(1))
+        .collect();
+    let bar = progress::counter(scanned.len() as u64, "posts scanned");
+
+    let links = scanned
+        .into_iter()
+        .flat_map(|sub| {
+            bar.inc(1);
+            let comments = if source.op_comments {
+                reddit::get_op_comments(client, sub).unwrap_or_else(|e| {
+                    warn!("Couldn't get the comments on {}: {}", &sub.full_link, e);
+                    Vec::new()
+                })
+            } else {
+                Vec::new()
+            };
+            // older results can lack an id, but never a permalink
+            let post_id = if sub.id.is_empty() {
+                sub.full_link.as_str()
+            } else {
+                sub.id.as_str()
+            };
+
+            sub.download_links(&comments).into_iter().map(move |url| {
+                (
+                    post_id,
+                    RedditPost::new(
+                        sub.link_domain(&url),
+                        url,
+                        sub.subreddit.to_lowercase(),
+                        sub.score,
+                        sub.title.clone(),
+                        sub.created_utc,
+                        sub.full_link.clone(),
+                    ),
+                )
+            })
+        })
+        .collect();
+    bar.finish_and_clear();
+
+    links
+}
+
+/// Lists the links a crawl would find and what it would do with each,
+/// without queueing them, downloading anything or opening the metadata
+/// store. Only Reddit and the shorteners links go through are asked.
+fn plan_crawl(
+    config: &Config,
+    options: &CrawlOptions,
+    output: query::OutputFormat,
+) -> anyhow::Result<()> {
+    let file_path = config.storage.download_dir()?;
+    let client = HttpClient::from_settings(&config.http)?;
+
+    let submissions = get_submissions(&client, &config.source, options)?;
+    let posts = post_links(&client, &config.source, &submissions);
+
+    let registry = ProviderRegistry::new(file_path.to_string());
+    let resolver = LinkResolver::new(&client, config.providers.max_hops)?;
+    let rows: Vec<PlannedDownload> = posts
+        .iter()
+        .map(|(post_id, post)| {
+            PlannedDownload::new(
+                post_id,
+                &post.get_title(),
+                &post.get_full_url(),
+                resolver.resolve(&post.get_full_url()),
+                &registry,
+                file_path,
             )
         })
-    });
-
-    let google_drive_hub = get_google_drive_connector()?;
-
-    let metadata_and_download_vec =
-        submission_data_vec
-            .into_iter()
-            .filter_map(|post| match post.url_domain {
-                "drive.google.com" => Some(AssocDataForDownload {
-                    download: DownloadOptions::GoogleDrive(GoogleDriveMetadata::new(
-                        post.get_full_url().as_str(),
-                        post.get_title(),
-                        file_path.clone(),
-                    )),
-                    website_metadata: post,
-                }),
-                "mediafire.com" => Some(AssocDataForDownload {
-                    download: DownloadOptions::Mediafire(MediaFireMetadata::new(
-                        post.get_full_url(),
-                        file_path.clone(),
-                    )),
-                    website_metadata: post,
-                }),
-                "dropbox.com" => Some(AssocDataForDownload {
-                    download: DownloadOptions::Dropbox(DropboxMetadata::new(
-                        post.get_full_url(),
-                        post.get_title(),
-                        file_path.clone(),
-                    )),
-                    website_metadata: post,
-                }),
-                _ => None,
+        .collect();
+    info!("Found {} links in {} posts", rows.len(), submissions.len());
+
+    query::write_rows(&rows, output, std::io::stdout().lock())
+}
+
+/// Works through the queued jobs until none are left, recording how each one
+/// went. A job that fails with an error that can clear up by itself goes back
+/// in the queue, after the jobs that haven't been tried yet.
+fn download_queued(
+    client: &HttpClient,
+    store: &dyn postgres_orm::MetadataStore,
+    providers: &ProviderSettings,
+    file_path: String,
+) -> anyhow::Result<RunSummary> {
+    // without a working service account, Drive files are still downloaded
+    // through their public links
+    let google_drive_hub =
+        get_google_drive_connector(providers.google_credentials.as_deref(), client.config())
+            .unwrap_or_else(|e| {
+                warn!("{}", e);
+                None
             });
 
-    let postgres_conn = postgres_orm::establish_connection();
+    let registry = ProviderRegistry::new(file_path);
+    let resolver = LinkResolver::new(client, providers.max_hops)?;
     info!("Downloading music samples from various sources....");
 
-    for assoc_data in metadata_and_download_vec {
-        match assoc_data.download {
-            DownloadOptions::GoogleDrive(val) => match val.file_metadata {
-                Some(GoogleFileType::GoogleFile(_)) => {
-                    val.download(Some(&google_drive_hub), &postgres_conn)?;
+    let mut summary = RunSummary::default();
+    let mut failures: Vec<(String, DownloadError)> = Vec::new();
+    loop {
+        let stale_before =
+            chrono::Utc::now().naive_utc() - chrono::Duration::minutes(jobs::STALE_AFTER_MINUTES);
+        let job = match store.claim_download_job(stale_before)? {
+            Some(val) => val,
+            None => break,
+        };
+        let post = RedditPost::from_job(&job);
+        let started = Instant::now();
+        let downloaded_before = client.downloaded_bytes();
+
+        // shortened links are only routable once they've been followed
+        let link = resolver.resolve(&post.get_full_url());
+        let provider = link
+            .as_ref()
+            .ok()
+            .and_then(|val| provider_for(&val.final_url))
+            .map(|val| val.name())
+            .or(job.provider.as_deref());
+        let result = link.and_then(|link| {
+            download_link(
+                client,
+                &link,
+                &registry,
+                google_drive_hub.as_ref(),
+                store,
+                &post,
+            )
+        });
+
+        // public Drive files hit download quotas, and newer providers fail
+        // more often (expired transfers, folder links they can't zip,
+        // removed Mediafire files), so a link that fails is recorded against
+        // its job rather than ending the whole crawl
+        let (status, last_error, error) = match result {
+            Ok(None) => (JobStatus::Done, None, None),
+            Ok(Some(reason)) => {
+                debug!("Skipping {}: {}", &job.url, &reason);
+                (JobStatus::Skipped, Some(reason), None)
+            }
+            Err(e) => {
+                let error = DownloadError::from(e);
+                let status = JobStatus::after_failure(&error, job.attempts, providers.max_attempts);
+                warn!(
+                    "{} kit {} on attempt {} ({}): {}",
+                    if status == JobStatus::Failed {
+                        "Skipping"
+                    } else {
+                        "Will retry"
+                    },
+                    &job.url,
+                    job.attempts,
+                    error.kind(),
+                    &error
+                );
+                if status == JobStatus::Failed {
+                    failures.push((job.url.clone(), error.clone()));
                 }
-                _ => (),
+                (status, Some(error.to_string()), Some(error))
+            }
+        };
+        summary.record(
+            provider,
+            status,
+            error.as_ref(),
+            client.downloaded_bytes() - downloaded_before,
+            started.elapsed(),
+        );
+
+        store.update_download_job(
+            job.id,
+            &DownloadJobUpdate {
+                status: status.as_str(),
+                provider,
+                last_error: last_error.as_deref(),
+                error_kind: error.as_ref().map(|val| val.kind()),
+                updated_at: chrono::Utc::now().naive_utc(),
             },
-            DownloadOptions::Dropbox(val) => val.download(None, &postgres_conn)?,
-            DownloadOptions::Mediafire(val) => val.download(None, &postgres_conn)?,
+        )?;
+    }
+
+    log_failures(&failures);
+
+    Ok(summary)
+}
+
+/// Downloads the kit a followed link points at. Returns why the link was
+/// skipped when there's nothing to download from it.
+fn download_link(
+    client: &HttpClient,
+    link: &ResolvedLink,
+    registry: &ProviderRegistry,
+    google_drive_hub: Option<&DriveApi>,
+    store: &dyn postgres_orm::MetadataStore,
+    post: &RedditPost,
+) -> anyhow::Result<Option<String>> {
+    if link.hops > 0 {
+        info!(
+            "Followed {} to {} in {} hops",
+            &link.original_url, &link.final_url, link.hops
+        );
+    }
+
+    let download = match registry.resolve(&link.final_url, &post.get_title()) {
+        Some(val) => val,
+        None => {
+            return Ok(Some(format!(
+                "No provider for {} link {}",
+                post.url_domain, &link.final_url
+            )))
         }
+    };
+
+    if let Some(reason) = download.skip_reason() {
+        return Ok(Some(reason.to_string()));
     }
 
-    Ok(())
+    // errors go back to the caller, which records them against the job
+    match download {
+        DownloadOptions::GoogleDrive(val) => val.download(client, google_drive_hub, store, post),
+        DownloadOptions::Dropbox(val) => val.download(client, None, store, post),
+        DownloadOptions::Mediafire(val) => val.download(client, None, store, post),
+        DownloadOptions::Mega(val) => val.download(client, None, store, post),
+        DownloadOptions::WeTransfer(val) => val.download(client, None, store, post),
+        DownloadOptions::OneDrive(val) => val.download(client, None, store, post),
+        DownloadOptions::Box(val) => val.download(client, None, store, post),
+        DownloadOptions::PCloud(val) => val.download(client, None, store, post),
+    }
+}
+
+/// Lists the kits that were given up on, as the summary only counts them
+fn log_failures(failures: &[(String, DownloadError)]) {
+    for (url, error) in failures {
+        warn!("Couldn't download [{}] {}: {}", error.kind(), url, error);
+    }
 }
 
-fn upload_to_gcs(file_path: &str, bucket_name: &str) -> anyhow::Result<()> {
-    let get_all_sample_path = download_utils::get_files(file_path)?;
+fn sample_rules(config: &Config) -> SampleRules<'_> {
+    SampleRules {
+        accepted_extensions: &config.extraction.accepted_extensions,
+        instrument_keywords: &config.classification.instrument_keywords,
+    }
+}
+
+/// Lists what `upload` would store and upload, reading the archives'
+/// contents without extracting them
+fn plan_upload(config: &Config, output: query::OutputFormat) -> anyhow::Result<()> {
+    let file_path = config.storage.download_dir()?;
+    let bucket_name = config.storage.bucket()?;
+
+    let archives = download_utils::get_files(file_path, &sample_rules(config))?;
+    let rows = PlannedUpload::from_archives(&archives, bucket_name, &config.storage.unzipped_dir);
+    info!(
+        "Found {} samples in {} archives",
+        rows.len(),
+        archives.len()
+    );
+
+    query::write_rows(&rows, output, std::io::stdout().lock())
+}
+
+fn upload_to_gcs(config: &Config) -> anyhow::Result<()> {
+    let file_path = config.storage.download_dir()?;
+    let bucket_name = config.storage.bucket()?;
+    let unzipped_dir = &config.storage.unzipped_dir;
+    let get_all_sample_path = download_utils::get_files(file_path, &sample_rules(config))?;
 
     info!(
         "Got all of the uncompressed files from data file path: {}",
         &file_path
     );
 
-    let postgres_conn = postgres_orm::establish_connection();
+    let store = postgres_orm::establish_connection(&config.database)?;
+
+    // extract first, so that the audio properties can be read from the samples
+    download_utils::unzip_files(file_path, unzipped_dir)?;
 
     for file_obj in get_all_sample_path {
-        let temp_file = &file_obj.compressed_file_root;
+        let temp_file = file_obj.archive_name();
+        let file_source =
+            store.get_or_create_file_source(&temp_file, &file_obj.compressed_file_root)?;
 
         let mut music_file_vec = Vec::new();
         // duplicate the file root so that it is the same
         // size as file list for izip op
         let mut compressed_list = Vec::new();
         for _ in &file_obj.file_name_list {
-            compressed_list.push(temp_file);
+            compressed_list.push(&temp_file);
         }
 
-        for (compressed_file_name, individual_file_name, instruments) in izip!(
+        let formats: Vec<String> = file_obj
+            .file_name_list
+            .iter()
+            .map(|val| audio::file_format(val))
+            .collect();
+        let properties = file_obj.file_name_list.iter().map(|val| {
+            let sample_path = Path::new(unzipped_dir).join(val);
+            audio::probe_properties(&sample_path).unwrap_or_else(|e| {
+                warn!(
+                    "Couldn't read the audio properties of {}: {}",
+                    sample_path.display(),
+                    e
+                );
+                AudioProperties::default()
+            })
+        });
+
+        for (compressed_file_name, individual_file_name, instruments, format, properties) in izip!(
             compressed_list,
             &file_obj.file_name_list,
-            &file_obj.instrument
+            &file_obj.instrument,
+            &formats,
+            properties
         ) {
             let new_music_files = postgres_orm::models::NewMusicFiles {
                 compressed_file_name,
                 individual_file_name,
                 instrument: instruments,
+                file_source_id: file_source.id,
+                format,
+                duration_seconds: properties.duration_seconds,
+                sample_rate: properties.sample_rate,
+                channels: properties.channels,
+                bit_depth: properties.bit_depth,
             };
             music_file_vec.push(new_music_files);
         }
 
         debug!(
-            "Inserting uncompressed files from file root as a row {} into postgres",
+            "Inserting uncompressed files from file root as a row {} into the metadata store",
             &temp_file
         );
 
@@ -190,43 +671,307 @@ fn upload_to_gcs(file_path: &str, bucket_name: &str) -> anyhow::Result<()> {
             &music_file_vec
         );
         if !music_file_vec.is_empty() {
-            postgres_orm::bulk_insert_music_files(&postgres_conn, &music_file_vec)?;
+            store.bulk_insert_music_files(&music_file_vec)?;
         }
     }
 
-    download_utils::unzip_files(&file_path)?;
     // upload to gcs
     info!("Uploading uncompressed music sample files to GCS.....");
-    let _new_command = std::process::Command::new("gsutil")
+    upload_objects(unzipped_dir, bucket_name)
+}
+
+/// Copies the extracted samples to the bucket with gsutil, counting the
+/// objects it copies or finds already there from what it prints on stderr
+fn upload_objects(unzipped_dir: &str, bucket_name: &str) -> anyhow::Result<()> {
+    let bar = progress::counter(
+        download_utils::count_files(unzipped_dir)? as u64,
+        "objects uploaded",
+    );
+    let mut child = Command::new("gsutil")
         .arg("-m")
         .arg("cp")
         .arg("-r")
         .arg("-n")
-        .arg("./unzipped/")
+        .arg(format!("{}/", unzipped_dir.trim_end_matches('/')))
         .arg(format!("gs://{}", bucket_name).as_str())
-        .output()
-        .expect("failed to list files in rar.");
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| DownloadError::Io(format!("Couldn't run gsutil: {}", e)))?;
+
+    if let Some(stderr) = child.stderr.take() {
+        for line in BufReader::new(stderr).lines() {
+            // gsutil -m redraws its own progress line with carriage returns
+            let line = line?;
+            let objects = line
+                .split('\r')
+                .filter(|val| {
+                    val.starts_with("Copying ") || val.starts_with("Skipping existing item")
+                })
+                .count();
+            bar.inc(objects as u64);
+        }
+    }
+    let status = child.wait()?;
+    bar.finish_and_clear();
+
+    if !status.success() {
+        return Err(DownloadError::Io(format!("gsutil exited with {}", status)).into());
+    }
+
+    Ok(())
+}
+
+fn query_metadata(config: &Config, target: QueryTarget) -> anyhow::Result<()> {
+    let store = postgres_orm::establish_connection(&config.database)?;
+    let stdout = std::io::stdout();
+
+    match target {
+        QueryTarget::Kits { filter, output } => {
+            let kits = query::find_kits(store.as_ref(), &filter.to_filter())?;
+            query::write_rows(&kits, output, stdout.lock())
+        }
+        QueryTarget::Samples { filter, output } => {
+            let samples = query::find_samples(store.as_ref(), &filter.to_filter())?;
+            query::write_rows(&samples, output, stdout.lock())
+        }
+        QueryTarget::Jobs { status, output } => {
+            let jobs = query::find_jobs(store.as_ref(), status)?;
+            query::write_rows(&jobs, output, stdout.lock())
+        }
+    }
+}
+
+fn export_manifest(
+    config: &Config,
+    filter: &query::FilterArgs,
+    file_path: &str,
+    format: ManifestFormat,
+    mut options: ManifestOptions,
+    split_name: Option<String>,
+) -> anyhow::Result<()> {
+    let store = postgres_orm::establish_connection(&config.database)?;
+
+    if let Some(split_name) = split_name {
+        let kits = dataset::split::kit_instruments(store.as_ref())?;
+        options.assignments = Some(dataset::split::load_or_assign(
+            store.as_ref(),
+            &kits,
+            &split_name,
+            options.seed,
+            &options.ratios,
+        )?);
+    }
+
+    let records = dataset::manifest::build_manifest(store.as_ref(), &filter.to_filter(), &options)?;
+    dataset::manifest::write_manifest(&records, format, file_path)?;
+
+    info!("Wrote {} manifest records to {}", records.len(), &file_path);
 
     Ok(())
 }
 
+fn split_kits(
+    config: &Config,
+    split_name: &str,
+    ratios: &SplitRatios,
+    seed: u64,
+    output: query::OutputFormat,
+) -> anyhow::Result<()> {
+    let store = postgres_orm::establish_connection(&config.database)?;
+
+    let kits = dataset::split::kit_instruments(store.as_ref())?;
+    let assignments =
+        dataset::split::load_or_assign(store.as_ref(), &kits, split_name, seed, ratios)?;
+
+    let summary = dataset::split::summarize(&kits, &assignments);
+    query::write_rows(&summary, output, std::io::stdout().lock())
+}
+
+fn dedupe_samples(
+    config: &Config,
+    filter: &query::FilterArgs,
+    threshold: f32,
+    mark: bool,
+    output: query::OutputFormat,
+) -> anyhow::Result<()> {
+    let store = postgres_orm::establish_connection(&config.database)?;
+
+    let result = dataset::dedupe::find_duplicates(
+        store.as_ref(),
+        &filter.to_filter(),
+        Path::new(&config.storage.unzipped_dir),
+        threshold,
+    )?;
+    if mark {
+        let updated = store.mark_duplicates(&result.marks)?;
+        info!("Updated duplicate marks of {} samples", updated);
+    }
+
+    query::write_rows(&result.rows, output, std::io::stdout().lock())
+}
+
 fn main() {
     env_logger::init();
     let args = Cli::parse();
 
+    // file < environment < the flags of each subcommand
+    let mut config = match Config::load(args.config.as_deref()) {
+        Ok(val) => val,
+        Err(e) => {
+            error!("error in reading the config: {}", e);
+            return;
+        }
+    };
+
     match args.cmd {
         SubCommand::Download {
             q,
             time_period,
+            subreddit,
             step_size,
             file_path,
-        } => match get_zip_music(q, time_period, step_size, file_path) {
+            op_comments,
+            resume,
+            retry_failed,
+            max_retries,
+            requests_per_second,
+            dry_run,
+            output,
+        } => {
+            if let Some(val) = subreddit {
+                config.source.subreddit = val;
+            }
+            if let Some(val) = step_size {
+                config.source.step_size = val;
+            }
+            if op_comments {
+                config.source.op_comments = true;
+            }
+            if let Some(val) = file_path {
+                config.storage.download_dir = Some(val);
+            }
+            if let Some(val) = max_retries {
+                config.http.max_retries = val;
+            }
+            if let Some(val) = requests_per_second {
+                config.http.requests_per_second = val;
+            }
+
+            let options = CrawlOptions {
+                q,
+                time_period,
+                resume,
+                retry_failed,
+            };
+            let result = if dry_run {
+                plan_crawl(&config, &options, output)
+            } else {
+                get_zip_music(&config, options, output)
+            };
+            match result {
+                Ok(_) => {}
+                Err(e) => error!("error with downloading zip files: {}", e),
+            }
+        }
+        SubCommand::Upload {
+            file_path,
+            bucket,
+            dry_run,
+            output,
+        } => {
+            if let Some(val) = file_path {
+                config.storage.download_dir = Some(val);
+            }
+            if let Some(val) = bucket {
+                config.storage.bucket = Some(val);
+            }
+
+            let result = if dry_run {
+                plan_upload(&config, output)
+            } else {
+                upload_to_gcs(&config)
+            };
+            match result {
+                Ok(_) => {}
+                Err(e) => error!("error in uploading to gcs: {}", e),
+            }
+        }
+        SubCommand::Query { target } => match query_metadata(&config, target) {
             Ok(_) => {}
-            Err(e) => error!("error with downloading zip files: {}", e),
+            Err(e) => error!("error in querying the metadata store: {}", e),
         },
-        SubCommand::Upload { file_path, bucket } => match upload_to_gcs(&file_path, &bucket) {
-            Ok(_) => {}
-            Err(e) => error!("error in uploading to gcs: {}", e),
+        SubCommand::Manifest {
+            filter,
+            file_path,
+            format,
+            bucket,
+            val_ratio,
+            test_ratio,
+            seed,
+            split_name,
+        } => {
+            let result = SplitRatios::new(val_ratio, test_ratio).and_then(|ratios| {
+                let options = ManifestOptions {
+                    bucket: bucket.or_else(|| config.storage.bucket.clone()),
+                    unzipped_dir: config.storage.unzipped_dir.clone(),
+                    instrument_keywords: config.classification.instrument_keywords.clone(),
+                    ratios,
+                    seed,
+                    assignments: None,
+                };
+                export_manifest(&config, &filter, &file_path, format, options, split_name)
+            });
+            match result {
+                Ok(_) => {}
+                Err(e) => error!("error in exporting the dataset manifest: {}", e),
+            }
+        }
+        SubCommand::Split {
+            name,
+            val_ratio,
+            test_ratio,
+            seed,
+            output,
+        } => {
+            let result = SplitRatios::new(val_ratio, test_ratio)
+                .and_then(|ratios| split_kits(&config, &name, &ratios, seed, output));
+            match result {
+                Ok(_) => {}
+                Err(e) => error!("error in splitting kits: {}", e),
+            }
+        }
+        SubCommand::Dedupe {
+            filter,
+            dir,
+            threshold,
+            mark,
+            output,
+        } => {
+            if let Some(val) = dir {
+                config.storage.unzipped_dir = val;
+            }
+
+            match dedupe_samples(&config, &filter, threshold, mark, output) {
+                Ok(_) => {}
+                Err(e) => error!("error in finding near-duplicate samples: {}", e),
+            }
+        }
+        SubCommand::Db { cmd } => match cmd {
+            DbCommand::Migrate => {
+                match postgres_orm::establish_connection(&config.database)
+                    .and_then(|store| store.run_migrations())
+                {
+                    Ok(_) => info!("Database migrations are up to date"),
+                    Err(e) => error!("error in running database migrations: {}", e),
+                }
+            }
+        },
+        SubCommand::Config { cmd } => match cmd {
+            ConfigCommand::Show => match config.to_toml() {
+                Ok(val) => print!("{}", val),
+                Err(e) => error!("error in showing the config: {}", e),
+            },
         },
     }
 }
@@ -0,0 +1,83 @@
+use chrono::NaiveDateTime;
+
+/// Filters shared by everything that reads samples back out of the metadata
+/// store. Empty lists and `None` mean "don't filter on this".
+#[derive(Debug, Default, Clone)]
+pub struct SampleFilter {
+    pub instruments: Vec<String>,
+    pub subreddits: Vec<String>,
+    pub providers: Vec<String>,
+    pub formats: Vec<String>,
+    /// Inclusive lower bound on when the source post was made
+    pub posted_after: Option<NaiveDateTime>,
+    /// Exclusive upper bound on when the source post was made
+    pub posted_before: Option<NaiveDateTime>,
+    pub min_duration: Option<f64>,
+    pub max_duration: Option<f64>,
+    /// Leave out samples marked as a near-duplicate of another sample
+    pub exclude_duplicates: bool,
+}
+
+impl SampleFilter {
+    /// Whether any of the filters apply to `music_files` rather than to the
+    /// archive they came from.
+    pub fn has_sample_filters(&self) -> bool {
+        !self.instruments.is_empty()
+            || !self.formats.is_empty()
+            || self.min_duration.is_some()
+            || self.max_duration.is_some()
+            || self.exclude_duplicates
+    }
+}
+
+/// Applies the archive level parts of a `SampleFilter` to a boxed query that
+/// includes `file_source`. A macro rather than a function, as the boxed query
+/// type differs per backend.
+macro_rules! filter_file_sources {
+    ($query:expr, $filter:expr) => {{
+        use $crate::postgres_orm::schema::file_source::dsl as fs;
+
+        let filter: &$crate::postgres_orm::filter::SampleFilter = $filter;
+        let mut query = $query;
+        if !filter.subreddits.is_empty() {
+            query = query.filter(fs::subreddit.eq_any(filter.subreddits.clone()));
+        }
+        if !filter.providers.is_empty() {
+            query = query.filter(fs::provider.eq_any(filter.providers.clone()));
+        }
+        if let Some(posted_after) = filter.posted_after {
+            query = query.filter(fs::posted_at.ge(posted_after));
+        }
+        if let Some(posted_before) = filter.posted_before {
+            query = query.filter(fs::posted_at.lt(posted_before));
+        }
+        query
+    }};
+}
+
+/// Applies the sample level parts of a `SampleFilter` to a boxed query that
+/// includes `music_files`.
+macro_rules! filter_music_files {
+    ($query:expr, $filter:expr) => {{
+        use $crate::postgres_orm::schema::music_files::dsl as mf;
+
+        let filter: &$crate::postgres_orm::filter::SampleFilter = $filter;
+        let mut query = $query;
+        if !filter.instruments.is_empty() {
+            query = query.filter(mf::instrument.eq_any(filter.instruments.clone()));
+        }
+        if !filter.formats.is_empty() {
+            query = query.filter(mf::format.eq_any(filter.formats.clone()));
+        }
+        if let Some(min_duration) = filter.min_duration {
+            query = query.filter(mf::duration_seconds.ge(min_duration));
+        }
+        if let Some(max_duration) = filter.max_duration {
+            query = query.filter(mf::duration_seconds.le(max_duration));
+        }
+        if filter.exclude_duplicates {
+            query = query.filter(mf::duplicate_of.is_null());
+        }
+        query
+    }};
+}
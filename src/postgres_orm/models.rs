@@ -1,20 +1,41 @@
-use super::schema::{file_source, music_files};
-use std::time::SystemTime;
+use super::schema::{download_jobs, file_source, music_files, split_assignments};
+use chrono::NaiveDateTime;
 
 #[derive(Insertable)]
 #[table_name = "file_source"]
 pub struct NewFileSource<'a> {
     pub url: &'a str,
     pub compressed_file_name: &'a str,
-    pub time_inserted: &'a SystemTime,
+    pub time_inserted: NaiveDateTime,
+    pub provider: &'a str,
+    pub subreddit: Option<&'a str>,
+    pub posted_at: NaiveDateTime,
+    pub post_url: Option<&'a str>,
+    pub post_title: Option<&'a str>,
+    pub original_url: Option<&'a str>,
+    pub size_bytes: Option<i64>,
+    pub mime_type: Option<&'a str>,
+    pub md5_checksum: Option<&'a str>,
+    pub source_modified_at: Option<NaiveDateTime>,
 }
 
-#[derive(Queryable)]
+#[derive(Queryable, Identifiable, Debug, serde::Serialize)]
+#[table_name = "file_source"]
 pub struct FileSource {
     pub id: i32,
     pub url: String,
     pub compressed_file_name: String,
-    pub time_inserted: SystemTime,
+    pub time_inserted: NaiveDateTime,
+    pub provider: String,
+    pub subreddit: Option<String>,
+    pub posted_at: NaiveDateTime,
+    pub post_url: Option<String>,
+    pub post_title: Option<String>,
+    pub original_url: Option<String>,
+    pub size_bytes: Option<i64>,
+    pub mime_type: Option<String>,
+    pub md5_checksum: Option<String>,
+    pub source_modified_at: Option<NaiveDateTime>,
 }
 
 #[derive(Insertable, Debug)]
@@ -23,12 +44,103 @@ pub struct NewMusicFiles<'a> {
     pub compressed_file_name: &'a str,
     pub individual_file_name: &'a str,
     pub instrument: &'a str,
+    pub file_source_id: i32,
+    pub format: &'a str,
+    pub duration_seconds: Option<f64>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i32>,
+    pub bit_depth: Option<i32>,
 }
 
-#[derive(Queryable)]
+#[derive(Queryable, Identifiable, Associations, Debug, serde::Serialize)]
+#[belongs_to(FileSource)]
+#[table_name = "music_files"]
 pub struct MusicFiles {
     pub id: i32,
     pub compressed_file_name: String,
     pub individual_file_name: String,
     pub instrument: String,
+    pub file_source_id: Option<i32>,
+    pub format: String,
+    pub duration_seconds: Option<f64>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i32>,
+    pub bit_depth: Option<i32>,
+    /// Id of the sample this one is a near-duplicate of, set by `dedupe --mark`
+    pub duplicate_of: Option<i32>,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "split_assignments"]
+pub struct NewSplitAssignment<'a> {
+    pub split_name: &'a str,
+    pub compressed_file_name: &'a str,
+    pub split: &'a str,
+    pub time_inserted: NaiveDateTime,
+    pub file_source_id: i32,
+}
+
+#[derive(Queryable, Identifiable, Debug)]
+#[table_name = "split_assignments"]
+pub struct SplitAssignment {
+    pub id: i32,
+    pub split_name: String,
+    pub compressed_file_name: String,
+    pub split: String,
+    pub time_inserted: NaiveDateTime,
+    /// The kit the assignment is for. `compressed_file_name` is only kept
+    /// for reading the table, as kits can share a name.
+    pub file_source_id: i32,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "download_jobs"]
+pub struct NewDownloadJob<'a> {
+    pub post_id: &'a str,
+    pub url: &'a str,
+    pub provider: Option<&'a str>,
+    pub status: &'a str,
+    pub url_domain: &'a str,
+    pub subreddit: &'a str,
+    pub post_title: &'a str,
+    pub post_url: &'a str,
+    pub posted_at: NaiveDateTime,
+    pub score: f64,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Identifiable, Debug, serde::Serialize)]
+#[table_name = "download_jobs"]
+pub struct DownloadJob {
+    pub id: i32,
+    pub post_id: String,
+    /// Link as it was posted, before any redirects were followed
+    pub url: String,
+    pub provider: Option<String>,
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub error_kind: Option<String>,
+    pub url_domain: String,
+    pub subreddit: String,
+    pub post_title: String,
+    pub post_url: String,
+    pub posted_at: NaiveDateTime,
+    pub score: f64,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// Outcome of working a job. `None`s are written as NULL, so a job that
+/// succeeds on a retry no longer shows the error from before.
+#[derive(AsChangeset, Debug)]
+#[table_name = "download_jobs"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct DownloadJobUpdate<'a> {
+    pub status: &'a str,
+    pub provider: Option<&'a str>,
+    pub last_error: Option<&'a str>,
+    pub error_kind: Option<&'a str>,
+    pub updated_at: NaiveDateTime,
 }
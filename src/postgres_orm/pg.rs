@@ -0,0 +1,264 @@
+use super::{filter, models, schema, with_retries, with_write_retries, MetadataStore};
+use crate::storage_download::jobs::JobStatus;
+
+use chrono::NaiveDateTime;
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use std::io;
+
+embed_migrations!("migrations/postgres");
+
+pub struct PgStore {
+    pool: Pool<ConnectionManager<PgConnection>>,
+}
+
+impl PgStore {
+    pub fn connect(database_url: &str) -> anyhow::Result<Self> {
+        let pool = Pool::builder()
+            .build(ConnectionManager::<PgConnection>::new(database_url))
+            .map_err(|e| anyhow::anyhow!("Error connecting to {}: {}", database_url, e))?;
+
+        Ok(Self { pool })
+    }
+
+    fn conn(&self) -> anyhow::Result<PooledConnection<ConnectionManager<PgConnection>>> {
+        Ok(self.pool.get()?)
+    }
+}
+
+impl MetadataStore for PgStore {
+    fn run_migrations(&self) -> anyhow::Result<()> {
+        embedded_migrations::run_with_output(&*self.conn()?, &mut io::stdout())?;
+
+        Ok(())
+    }
+
+    fn create_file_row(
+        &self,
+        new_file_source: &models::NewFileSource,
+    ) -> anyhow::Result<models::FileSource> {
+        use schema::file_source;
+
+        with_write_retries(|| {
+            Ok(diesel::insert_into(file_source::table)
+                .values(new_file_source)
+                .get_result(&*self.conn()?)?)
+        })
+    }
+
+    fn find_file_source(
+        &self,
+        compressed_file_name: &str,
+    ) -> anyhow::Result<Option<models::FileSource>> {
+        use schema::file_source::dsl;
+
+        with_retries(|| {
+            Ok(dsl::file_source
+                .filter(dsl::compressed_file_name.eq(compressed_file_name))
+                .order(dsl::id.desc())
+                .first(&*self.conn()?)
+                .optional()?)
+        })
+    }
+
+    fn find_file_source_by_url(&self, url: &str) -> anyhow::Result<Option<models::FileSource>> {
+        use schema::file_source::dsl;
+
+        with_retries(|| {
+            Ok(dsl::file_source
+                .filter(dsl::url.eq(url))
+                .order(dsl::id.desc())
+                .first(&*self.conn()?)
+                .optional()?)
+        })
+    }
+
+    fn bulk_insert_music_files(
+        &self,
+        new_music_files: &[models::NewMusicFiles],
+    ) -> anyhow::Result<Vec<models::MusicFiles>> {
+        use schema::music_files;
+
+        // a single multi-row INSERT ... RETURNING, so the batch either lands
+        // as a whole or not at all
+        with_write_retries(|| {
+            Ok(diesel::insert_into(music_files::table)
+                .values(new_music_files)
+                .get_results(&*self.conn()?)?)
+        })
+    }
+
+    fn find_file_sources(
+        &self,
+        filter: &filter::SampleFilter,
+    ) -> anyhow::Result<Vec<models::FileSource>> {
+        use schema::file_source::dsl;
+
+        with_retries(|| {
+            let query =
+                filter_file_sources!(dsl::file_source.into_boxed::<diesel::pg::Pg>(), filter);
+            Ok(query.order(dsl::id.asc()).load(&*self.conn()?)?)
+        })
+    }
+
+    fn find_samples(
+        &self,
+        filter: &filter::SampleFilter,
+    ) -> anyhow::Result<Vec<(models::MusicFiles, models::FileSource)>> {
+        use schema::{file_source, music_files};
+
+        with_retries(|| {
+            let query = music_files::table
+                .inner_join(file_source::table)
+                .into_boxed::<diesel::pg::Pg>();
+            let query = filter_file_sources!(query, filter);
+            let query = filter_music_files!(query, filter);
+            Ok(query
+                .order((file_source::id.asc(), music_files::id.asc()))
+                .load(&*self.conn()?)?)
+        })
+    }
+
+    fn find_split_assignments(
+        &self,
+        split_name: &str,
+    ) -> anyhow::Result<Vec<models::SplitAssignment>> {
+        use schema::split_assignments::dsl;
+
+        with_retries(|| {
+            Ok(dsl::split_assignments
+                .filter(dsl::split_name.eq(split_name))
+                .order(dsl::id.asc())
+                .load(&*self.conn()?)?)
+        })
+    }
+
+    fn insert_split_assignments(
+        &self,
+        new_assignments: &[models::NewSplitAssignment],
+    ) -> anyhow::Result<usize> {
+        use schema::split_assignments;
+
+        with_retries(|| {
+            Ok(diesel::insert_into(split_assignments::table)
+                .values(new_assignments)
+                .on_conflict_do_nothing()
+                .execute(&*self.conn()?)?)
+        })
+    }
+
+    fn mark_duplicates(&self, marks: &[(i32, Option<i32>)]) -> anyhow::Result<usize> {
+        use schema::music_files::dsl;
+
+        with_retries(|| {
+            let conn = self.conn()?;
+            conn.transaction(|| {
+                let mut updated = 0;
+                for (id, duplicate_of) in marks {
+                    updated += diesel::update(dsl::music_files.find(*id))
+                        .set(dsl::duplicate_of.eq(*duplicate_of))
+                        .execute(&*conn)?;
+                }
+                Ok(updated)
+            })
+        })
+    }
+
+    fn enqueue_download_jobs(&self, new_jobs: &[models::NewDownloadJob]) -> anyhow::Result<usize> {
+        use schema::download_jobs;
+
+        // a job is unique by post and url, so a retry after an insert that
+        // went through adds nothing
+        with_retries(|| {
+            Ok(diesel::insert_into(download_jobs::table)
+                .values(new_jobs)
+                .on_conflict_do_nothing()
+                .execute(&*self.conn()?)?)
+        })
+    }
+
+    fn claim_download_job(
+        &self,
+        stale_before: NaiveDateTime,
+    ) -> anyhow::Result<Option<models::DownloadJob>> {
+        use schema::download_jobs::dsl;
+
+        // SKIP LOCKED lets crawls running side by side each take a different
+        // job instead of waiting on one another
+        with_write_retries(|| {
+            let conn = self.conn()?;
+            conn.transaction(|| {
+                let id: Option<i32> = dsl::download_jobs
+                    .select(dsl::id)
+                    .filter(
+                        dsl::status.eq(JobStatus::Pending.as_str()).or(dsl::status
+                            .eq(JobStatus::Running.as_str())
+                            .and(dsl::updated_at.lt(stale_before))),
+                    )
+                    .order((dsl::attempts.asc(), dsl::id.asc()))
+                    .for_update()
+                    .skip_locked()
+                    .first(&*conn)
+                    .optional()?;
+
+                match id {
+                    Some(id) => Ok(Some(
+                        diesel::update(dsl::download_jobs.find(id))
+                            .set((
+                                dsl::status.eq(JobStatus::Running.as_str()),
+                                dsl::attempts.eq(dsl::attempts + 1),
+                                dsl::updated_at.eq(chrono::Utc::now().naive_utc()),
+                            ))
+                            .get_result(&*conn)?,
+                    )),
+                    None => Ok(None),
+                }
+            })
+        })
+    }
+
+    fn update_download_job(
+        &self,
+        id: i32,
+        update: &models::DownloadJobUpdate,
+    ) -> anyhow::Result<()> {
+        use schema::download_jobs::dsl;
+
+        with_retries(|| {
+            diesel::update(dsl::download_jobs.find(id))
+                .set(update)
+                .execute(&*self.conn()?)?;
+            Ok(())
+        })
+    }
+
+    fn requeue_failed_jobs(&self) -> anyhow::Result<usize> {
+        use schema::download_jobs::dsl;
+
+        with_retries(|| {
+            Ok(diesel::update(
+                dsl::download_jobs.filter(dsl::status.eq(JobStatus::Failed.as_str())),
+            )
+            .set((
+                dsl::status.eq(JobStatus::Pending.as_str()),
+                dsl::attempts.eq(0),
+                dsl::updated_at.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(&*self.conn()?)?)
+        })
+    }
+
+    fn find_download_jobs(&self, status: Option<&str>) -> anyhow::Result<Vec<models::DownloadJob>> {
+        use schema::download_jobs::dsl;
+
+        with_retries(|| {
+            let mut query = dsl::download_jobs.into_boxed::<diesel::pg::Pg>();
+            if let Some(status) = status {
+                query = query.filter(dsl::status.eq(status));
+            }
+            Ok(query.order(dsl::id.asc()).load(&*self.conn()?)?)
+        })
+    }
+}
@@ -1,9 +1,40 @@
+table! {
+    download_jobs (id) {
+        id -> Integer,
+        post_id -> Text,
+        url -> Text,
+        provider -> Nullable<Text>,
+        status -> Text,
+        attempts -> Integer,
+        last_error -> Nullable<Text>,
+        error_kind -> Nullable<Text>,
+        url_domain -> Text,
+        subreddit -> Text,
+        post_title -> Text,
+        post_url -> Text,
+        posted_at -> Timestamp,
+        score -> Double,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 table! {
     file_source (id) {
         id -> Integer,
         url -> Text,
         compressed_file_name -> Text,
         time_inserted -> Timestamp,
+        provider -> Text,
+        subreddit -> Nullable<Text>,
+        posted_at -> Timestamp,
+        post_url -> Nullable<Text>,
+        post_title -> Nullable<Text>,
+        original_url -> Nullable<Text>,
+        size_bytes -> Nullable<BigInt>,
+        mime_type -> Nullable<Text>,
+        md5_checksum -> Nullable<Text>,
+        source_modified_at -> Nullable<Timestamp>,
     }
 }
 
@@ -13,5 +44,28 @@ table! {
         compressed_file_name -> Text,
         individual_file_name -> Text,
         instrument -> Text,
+        file_source_id -> Nullable<Integer>,
+        format -> Text,
+        duration_seconds -> Nullable<Double>,
+        sample_rate -> Nullable<Integer>,
+        channels -> Nullable<Integer>,
+        bit_depth -> Nullable<Integer>,
+        duplicate_of -> Nullable<Integer>,
     }
 }
+
+table! {
+    split_assignments (id) {
+        id -> Integer,
+        split_name -> Text,
+        compressed_file_name -> Text,
+        split -> Text,
+        time_inserted -> Timestamp,
+        file_source_id -> Integer,
+    }
+}
+
+joinable!(music_files -> file_source (file_source_id));
+joinable!(split_assignments -> file_source (file_source_id));
+
+allow_tables_to_appear_in_same_query!(download_jobs, file_source, music_files, split_assignments,);
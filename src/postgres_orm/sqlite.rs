@@ -0,0 +1,302 @@
+use super::{filter, models, schema, with_retries, with_write_retries, MetadataStore};
+use crate::storage_download::jobs::JobStatus;
+
+use chrono::NaiveDateTime;
+
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager, CustomizeConnection, Pool, PooledConnection};
+use diesel::sqlite::SqliteConnection;
+use std::io;
+
+embed_migrations!("migrations/sqlite");
+
+#[derive(Debug)]
+struct SqlitePragmas;
+
+impl CustomizeConnection<SqliteConnection, r2d2::Error> for SqlitePragmas {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), r2d2::Error> {
+        // sqlite only enforces foreign keys when asked to, per connection, and
+        // by default fails straight away when another connection holds the lock
+        conn.execute("PRAGMA foreign_keys = ON; PRAGMA busy_timeout = 5000;")
+            .map(|_| ())
+            .map_err(r2d2::Error::QueryError)
+    }
+}
+
+/// File backed metadata store for local crawls and tests, so that no
+/// Postgres instance is needed.
+pub struct SqliteStore {
+    pool: Pool<ConnectionManager<SqliteConnection>>,
+}
+
+impl SqliteStore {
+    pub fn connect(path: &str) -> anyhow::Result<Self> {
+        // every connection to :memory: opens its own empty database
+        let max_size = if path == ":memory:" { 1 } else { 10 };
+
+        let pool = Pool::builder()
+            .max_size(max_size)
+            .connection_customizer(Box::new(SqlitePragmas))
+            .build(ConnectionManager::<SqliteConnection>::new(path))
+            .map_err(|e| anyhow::anyhow!("Error opening sqlite database {}: {}", path, e))?;
+
+        Ok(Self { pool })
+    }
+
+    fn conn(&self) -> anyhow::Result<PooledConnection<ConnectionManager<SqliteConnection>>> {
+        Ok(self.pool.get()?)
+    }
+}
+
+impl MetadataStore for SqliteStore {
+    fn run_migrations(&self) -> anyhow::Result<()> {
+        embedded_migrations::run_with_output(&*self.conn()?, &mut io::stdout())?;
+
+        Ok(())
+    }
+
+    fn create_file_row(
+        &self,
+        new_file_source: &models::NewFileSource,
+    ) -> anyhow::Result<models::FileSource> {
+        use schema::file_source::{self, dsl};
+
+        // diesel doesn't support RETURNING on sqlite, so read the row back
+        // inside the same transaction
+        with_write_retries(|| {
+            let conn = self.conn()?;
+            conn.immediate_transaction(|| {
+                diesel::insert_into(file_source::table)
+                    .values(new_file_source)
+                    .execute(&*conn)?;
+
+                Ok(dsl::file_source.order(dsl::id.desc()).first(&*conn)?)
+            })
+        })
+    }
+
+    fn find_file_source(
+        &self,
+        compressed_file_name: &str,
+    ) -> anyhow::Result<Option<models::FileSource>> {
+        use schema::file_source::dsl;
+
+        with_retries(|| {
+            Ok(dsl::file_source
+                .filter(dsl::compressed_file_name.eq(compressed_file_name))
+                .order(dsl::id.desc())
+                .first(&*self.conn()?)
+                .optional()?)
+        })
+    }
+
+    fn find_file_source_by_url(&self, url: &str) -> anyhow::Result<Option<models::FileSource>> {
+        use schema::file_source::dsl;
+
+        with_retries(|| {
+            Ok(dsl::file_source
+                .filter(dsl::url.eq(url))
+                .order(dsl::id.desc())
+                .first(&*self.conn()?)
+                .optional()?)
+        })
+    }
+
+    fn bulk_insert_music_files(
+        &self,
+        new_music_files: &[models::NewMusicFiles],
+    ) -> anyhow::Result<Vec<models::MusicFiles>> {
+        use schema::music_files::{self, dsl};
+
+        // sqlite writes are serialised, so every row with an id above the
+        // largest one seen at the start of the transaction is from this batch
+        with_write_retries(|| {
+            let conn = self.conn()?;
+            conn.immediate_transaction(|| {
+                let last_id: Option<i32> = dsl::music_files
+                    .select(diesel::dsl::max(dsl::id))
+                    .first(&*conn)?;
+
+                diesel::insert_into(music_files::table)
+                    .values(new_music_files)
+                    .execute(&*conn)?;
+
+                Ok(dsl::music_files
+                    .filter(dsl::id.gt(last_id.unwrap_or(0)))
+                    .order(dsl::id.asc())
+                    .load(&*conn)?)
+            })
+        })
+    }
+
+    fn find_file_sources(
+        &self,
+        filter: &filter::SampleFilter,
+    ) -> anyhow::Result<Vec<models::FileSource>> {
+        use schema::file_source::dsl;
+
+        with_retries(|| {
+            let query = filter_file_sources!(
+                dsl::file_source.into_boxed::<diesel::sqlite::Sqlite>(),
+                filter
+            );
+            Ok(query.order(dsl::id.asc()).load(&*self.conn()?)?)
+        })
+    }
+
+    fn find_samples(
+        &self,
+        filter: &filter::SampleFilter,
+    ) -> anyhow::Result<Vec<(models::MusicFiles, models::FileSource)>> {
+        use schema::{file_source, music_files};
+
+        with_retries(|| {
+            let query = music_files::table
+                .inner_join(file_source::table)
+                .into_boxed::<diesel::sqlite::Sqlite>();
+            let query = filter_file_sources!(query, filter);
+            let query = filter_music_files!(query, filter);
+            Ok(query
+                .order((file_source::id.asc(), music_files::id.asc()))
+                .load(&*self.conn()?)?)
+        })
+    }
+
+    fn find_split_assignments(
+        &self,
+        split_name: &str,
+    ) -> anyhow::Result<Vec<models::SplitAssignment>> {
+        use schema::split_assignments::dsl;
+
+        with_retries(|| {
+            Ok(dsl::split_assignments
+                .filter(dsl::split_name.eq(split_name))
+                .order(dsl::id.asc())
+                .load(&*self.conn()?)?)
+        })
+    }
+
+    fn insert_split_assignments(
+        &self,
+        new_assignments: &[models::NewSplitAssignment],
+    ) -> anyhow::Result<usize> {
+        use schema::split_assignments;
+
+        with_retries(|| {
+            Ok(diesel::insert_or_ignore_into(split_assignments::table)
+                .values(new_assignments)
+                .execute(&*self.conn()?)?)
+        })
+    }
+
+    fn mark_duplicates(&self, marks: &[(i32, Option<i32>)]) -> anyhow::Result<usize> {
+        use schema::music_files::dsl;
+
+        with_retries(|| {
+            let conn = self.conn()?;
+            conn.immediate_transaction(|| {
+                let mut updated = 0;
+                for (id, duplicate_of) in marks {
+                    updated += diesel::update(dsl::music_files.find(*id))
+                        .set(dsl::duplicate_of.eq(*duplicate_of))
+                        .execute(&*conn)?;
+                }
+                Ok(updated)
+            })
+        })
+    }
+
+    fn enqueue_download_jobs(&self, new_jobs: &[models::NewDownloadJob]) -> anyhow::Result<usize> {
+        use schema::download_jobs;
+
+        // a job is unique by post and url, so a retry after an insert that
+        // went through adds nothing
+        with_retries(|| {
+            Ok(diesel::insert_or_ignore_into(download_jobs::table)
+                .values(new_jobs)
+                .execute(&*self.conn()?)?)
+        })
+    }
+
+    fn claim_download_job(
+        &self,
+        stale_before: NaiveDateTime,
+    ) -> anyhow::Result<Option<models::DownloadJob>> {
+        use schema::download_jobs::dsl;
+
+        // the immediate transaction takes the write lock before the job is
+        // picked, so two crawls can't claim the same one
+        with_write_retries(|| {
+            let conn = self.conn()?;
+            conn.immediate_transaction(|| {
+                let id: Option<i32> = dsl::download_jobs
+                    .select(dsl::id)
+                    .filter(
+                        dsl::status.eq(JobStatus::Pending.as_str()).or(dsl::status
+                            .eq(JobStatus::Running.as_str())
+                            .and(dsl::updated_at.lt(stale_before))),
+                    )
+                    .order((dsl::attempts.asc(), dsl::id.asc()))
+                    .first(&*conn)
+                    .optional()?;
+
+                match id {
+                    Some(id) => {
+                        diesel::update(dsl::download_jobs.find(id))
+                            .set((
+                                dsl::status.eq(JobStatus::Running.as_str()),
+                                dsl::attempts.eq(dsl::attempts + 1),
+                                dsl::updated_at.eq(chrono::Utc::now().naive_utc()),
+                            ))
+                            .execute(&*conn)?;
+                        Ok(Some(dsl::download_jobs.find(id).first(&*conn)?))
+                    }
+                    None => Ok(None),
+                }
+            })
+        })
+    }
+
+    fn update_download_job(
+        &self,
+        id: i32,
+        update: &models::DownloadJobUpdate,
+    ) -> anyhow::Result<()> {
+        use schema::download_jobs::dsl;
+
+        with_retries(|| {
+            diesel::update(dsl::download_jobs.find(id))
+                .set(update)
+                .execute(&*self.conn()?)?;
+            Ok(())
+        })
+    }
+
+    fn requeue_failed_jobs(&self) -> anyhow::Result<usize> {
+        use schema::download_jobs::dsl;
+
+        with_retries(|| {
+            Ok(diesel::update(
+                dsl::download_jobs.filter(dsl::status.eq(JobStatus::Failed.as_str())),
+            )
+            .set((
+                dsl::status.eq(JobStatus::Pending.as_str()),
+                dsl::attempts.eq(0),
+                dsl::updated_at.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(&*self.conn()?)?)
+        })
+    }
+
+    fn find_download_jobs(&self, status: Option<&str>) -> anyhow::Result<Vec<models::DownloadJob>> {
+        use schema::download_jobs::dsl;
+
+        with_retries(|| {
+            let mut query = dsl::download_jobs.into_boxed::<diesel::sqlite::Sqlite>();
+            if let Some(status) = status {
+                query = query.filter(dsl::status.eq(status));
+            }
+            Ok(query.order(dsl::id.asc()).load(&*self.conn()?)?)
+        })
+    }
+}
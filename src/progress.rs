@@ -0,0 +1,48 @@
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// A bar counting `len` things done so far, such as posts scanned or
+/// archives extracted. Bars are drawn on stderr, and only when it's a
+/// terminal, so piped output and log files don't fill up with them.
+pub fn counter(len: u64, message: &str) -> ProgressBar {
+    let bar = ProgressBar::new(len);
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{msg:>18} [{bar:40}] {pos}/{len} ({elapsed}, eta {eta})")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    bar.set_message(message.to_string());
+
+    bar
+}
+
+/// A bar for the body of one file as it comes in. Hosts that don't say how
+/// large the file is get a spinner with the bytes read so far.
+pub fn download(len: Option<u64>, name: &str) -> ProgressBar {
+    let bar = match len {
+        Some(val) => {
+            let bar = ProgressBar::new(val);
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template(
+                        "{wide_msg} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} eta {eta}",
+                    )
+                    .unwrap()
+                    .progress_chars("=> "),
+            );
+            bar
+        }
+        None => {
+            let bar = ProgressBar::new_spinner();
+            bar.set_style(
+                ProgressStyle::default_spinner()
+                    .template("{spinner} {wide_msg} {bytes} {bytes_per_sec}")
+                    .unwrap(),
+            );
+            bar
+        }
+    };
+    bar.set_message(name.to_string());
+
+    bar
+}
@@ -0,0 +1,418 @@
+use crate::postgres_orm::filter::SampleFilter;
+use crate::postgres_orm::models::{DownloadJob, FileSource, MusicFiles};
+use crate::postgres_orm::MetadataStore;
+use crate::storage_download::jobs::JobStatus;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use clap::{ArgEnum, Args};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+/// Filters shared by the subcommands that read from the metadata store
+#[derive(Args, Debug, Default)]
+pub struct FilterArgs {
+    /// Only include samples of this instrument (e.g. kick). Can be repeated
+    #[clap(long)]
+    pub instrument: Vec<String>,
+    /// Only include kits posted to this subreddit. Can be repeated
+    #[clap(long)]
+    pub subreddit: Vec<String>,
+    /// Only include kits downloaded from this provider (google_drive, dropbox, mediafire, mega, wetransfer, onedrive, box, pcloud, local). Can be repeated
+    #[clap(long)]
+    pub provider: Vec<String>,
+    /// Only include samples with this file extension (e.g. wav). Can be repeated
+    #[clap(long)]
+    pub file_format: Vec<String>,
+    /// Only include kits posted on or after this date (YYYY-MM-DD)
+    #[clap(long, parse(try_from_str = parse_date))]
+    pub after: Option<NaiveDateTime>,
+    /// Only include kits posted before this date (YYYY-MM-DD)
+    #[clap(long, parse(try_from_str = parse_date))]
+    pub before: Option<NaiveDateTime>,
+    /// Minimum sample duration in seconds
+    #[clap(long)]
+    pub min_duration: Option<f64>,
+    /// Maximum sample duration in seconds
+    #[clap(long)]
+    pub max_duration: Option<f64>,
+    /// Leave out samples marked as near-duplicates by `dedupe --mark`
+    #[clap(long)]
+    pub exclude_duplicates: bool,
+}
+
+impl FilterArgs {
+    pub fn to_filter(&self) -> SampleFilter {
+        let lowercase = |values: &[String]| values.iter().map(|val| val.to_lowercase()).collect();
+
+        SampleFilter {
+            instruments: lowercase(&self.instrument),
+            subreddits: lowercase(&self.subreddit),
+            providers: lowercase(&self.provider),
+            formats: lowercase(&self.file_format),
+            posted_after: self.after,
+            posted_before: self.before,
+            min_duration: self.min_duration,
+            max_duration: self.max_duration,
+            exclude_duplicates: self.exclude_duplicates,
+        }
+    }
+}
+
+fn parse_date(date: &str) -> Result<NaiveDateTime, String> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|val| val.and_hms_opt(0, 0, 0).unwrap())
+        .map_err(|e| format!("{} is not a YYYY-MM-DD date: {}", date, e))
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct KitRow {
+    pub id: i32,
+    pub kit: String,
+    pub provider: String,
+    pub subreddit: Option<String>,
+    pub posted_at: NaiveDateTime,
+    pub samples: usize,
+    pub url: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SampleRow {
+    pub id: i32,
+    pub kit: String,
+    pub file: String,
+    pub instrument: String,
+    pub format: String,
+    pub duration_seconds: Option<f64>,
+    pub sample_rate: Option<i32>,
+    pub provider: String,
+    pub subreddit: Option<String>,
+    pub posted_at: NaiveDateTime,
+}
+
+impl From<(MusicFiles, FileSource)> for SampleRow {
+    fn from((sample, source): (MusicFiles, FileSource)) -> Self {
+        Self {
+            id: sample.id,
+            kit: source.compressed_file_name,
+            file: sample.individual_file_name,
+            instrument: sample.instrument,
+            format: sample.format,
+            duration_seconds: sample.duration_seconds,
+            sample_rate: sample.sample_rate,
+            provider: source.provider,
+            subreddit: source.subreddit,
+            posted_at: source.posted_at,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct JobRow {
+    pub id: i32,
+    pub status: String,
+    pub attempts: i32,
+    pub provider: Option<String>,
+    pub error_kind: Option<String>,
+    pub updated_at: NaiveDateTime,
+    pub url: String,
+    pub last_error: Option<String>,
+}
+
+impl From<DownloadJob> for JobRow {
+    fn from(job: DownloadJob) -> Self {
+        Self {
+            id: job.id,
+            status: job.status,
+            attempts: job.attempts,
+            provider: job.provider,
+            error_kind: job.error_kind,
+            updated_at: job.updated_at,
+            url: job.url,
+            last_error: job.last_error,
+        }
+    }
+}
+
+/// Rows that can be printed as an aligned table as well as serialized.
+pub trait TableRow {
+    fn headers() -> Vec<&'static str>;
+    fn cells(&self) -> Vec<String>;
+}
+
+pub fn optional_cell<T: ToString>(val: &Option<T>) -> String {
+    val.as_ref().map(|val| val.to_string()).unwrap_or_default()
+}
+
+impl TableRow for KitRow {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "id",
+            "kit",
+            "provider",
+            "subreddit",
+            "posted_at",
+            "samples",
+            "url",
+        ]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.kit.clone(),
+            self.provider.clone(),
+            optional_cell(&self.subreddit),
+            self.posted_at.to_string(),
+            self.samples.to_string(),
+            self.url.clone(),
+        ]
+    }
+}
+
+impl TableRow for SampleRow {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "id",
+            "kit",
+            "file",
+            "instrument",
+            "format",
+            "duration_seconds",
+            "sample_rate",
+            "provider",
+            "subreddit",
+            "posted_at",
+        ]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.kit.clone(),
+            self.file.clone(),
+            self.instrument.clone(),
+            self.format.clone(),
+            self.duration_seconds
+                .map(|val| format!("{:.3}", val))
+                .unwrap_or_default(),
+            optional_cell(&self.sample_rate),
+            self.provider.clone(),
+            optional_cell(&self.subreddit),
+            self.posted_at.to_string(),
+        ]
+    }
+}
+
+impl TableRow for JobRow {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "id",
+            "status",
+            "attempts",
+            "provider",
+            "error_kind",
+            "updated_at",
+            "url",
+            "last_error",
+        ]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.status.clone(),
+            self.attempts.to_string(),
+            optional_cell(&self.provider),
+            optional_cell(&self.error_kind),
+            self.updated_at.to_string(),
+            self.url.clone(),
+            optional_cell(&self.last_error),
+        ]
+    }
+}
+
+/// Kits matching the filter, with the number of their samples that match.
+/// Kits without any matching sample are only left out when the filter has
+/// sample level conditions, so that downloaded but not yet uploaded kits
+/// still show up.
+pub fn find_kits(store: &dyn MetadataStore, filter: &SampleFilter) -> anyhow::Result<Vec<KitRow>> {
+    let mut sample_counts: HashMap<i32, usize> = HashMap::new();
+    for (_, source) in store.find_samples(filter)? {
+        *sample_counts.entry(source.id).or_insert(0) += 1;
+    }
+
+    Ok(store
+        .find_file_sources(filter)?
+        .into_iter()
+        .map(|source| KitRow {
+            samples: sample_counts.get(&source.id).copied().unwrap_or(0),
+            id: source.id,
+            kit: source.compressed_file_name,
+            provider: source.provider,
+            subreddit: source.subreddit,
+            posted_at: source.posted_at,
+            url: source.url,
+        })
+        .filter(|kit| kit.samples > 0 || !filter.has_sample_filters())
+        .collect())
+}
+
+pub fn find_samples(
+    store: &dyn MetadataStore,
+    filter: &SampleFilter,
+) -> anyhow::Result<Vec<SampleRow>> {
+    Ok(store
+        .find_samples(filter)?
+        .into_iter()
+        .map(SampleRow::from)
+        .collect())
+}
+
+pub fn find_jobs(
+    store: &dyn MetadataStore,
+    status: Option<JobStatus>,
+) -> anyhow::Result<Vec<JobRow>> {
+    Ok(store
+        .find_download_jobs(status.map(|val| val.as_str()))?
+        .into_iter()
+        .map(JobRow::from)
+        .collect())
+}
+
+pub fn write_rows<R, W>(rows: &[R], output: OutputFormat, mut writer: W) -> anyhow::Result<()>
+where
+    R: TableRow + Serialize,
+    W: Write,
+{
+    match output {
+        OutputFormat::Table => {
+            let headers = R::headers();
+            let cells: Vec<Vec<String>> = rows.iter().map(|row| row.cells()).collect();
+
+            let mut widths: Vec<usize> = headers.iter().map(|val| val.len()).collect();
+            for row in &cells {
+                for (width, cell) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(cell.chars().count());
+                }
+            }
+
+            let write_line = |writer: &mut W, line: &[String]| -> anyhow::Result<()> {
+                let padded: Vec<String> = line
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                    .collect();
+                writeln!(writer, "{}", padded.join("  ").trim_end())?;
+                Ok(())
+            };
+
+            let header_line: Vec<String> = headers.iter().map(|val| val.to_string()).collect();
+            let divider_line: Vec<String> = widths.iter().map(|val| "-".repeat(*val)).collect();
+            write_line(&mut writer, &header_line)?;
+            write_line(&mut writer, &divider_line)?;
+            for row in &cells {
+                write_line(&mut writer, row)?;
+            }
+        }
+        OutputFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            for row in rows {
+                csv_writer.serialize(row)?;
+            }
+            csv_writer.flush()?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, rows)?;
+            writeln!(writer)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_rows() -> Vec<KitRow> {
+        vec![
+            KitRow {
+                id: 1,
+                kit: "kit.zip".to_string(),
+                provider: "dropbox".to_string(),
+                subreddit: Some("drumkits".to_string()),
+                posted_at: parse_date("2022-05-01").unwrap(),
+                samples: 12,
+                url: "https://www.dropbox.com/s/abc/kit.zip?dl=1".to_string(),
+            },
+            KitRow {
+                id: 2,
+                kit: "local.rar".to_string(),
+                provider: "local".to_string(),
+                subreddit: None,
+                posted_at: parse_date("2022-06-01").unwrap(),
+                samples: 3,
+                url: "file://data/local.rar".to_string(),
+            },
+        ]
+    }
+
+    fn render(output: OutputFormat) -> String {
+        let mut buffer = Vec::new();
+        write_rows(&test_rows(), output, &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn test_write_table() {
+        let lines: Vec<String> = render(OutputFormat::Table)
+            .lines()
+            .map(|val| val.to_string())
+            .collect();
+
+        assert_eq!(4, lines.len());
+        assert!(lines[0].starts_with("id  kit        provider  subreddit  posted_at"));
+        assert!(lines[3].starts_with("2   local.rar  local                2022-06-01 00:00:00"));
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let csv_output = render(OutputFormat::Csv);
+        let mut lines = csv_output.lines();
+
+        assert_eq!(
+            Some("id,kit,provider,subreddit,posted_at,samples,url"),
+            lines.next()
+        );
+        assert_eq!(
+            Some("2,local.rar,local,,2022-06-01T00:00:00,3,file://data/local.rar"),
+            lines.nth(1)
+        );
+    }
+
+    #[test]
+    fn test_write_json() {
+        let json: serde_json::Value = serde_json::from_str(&render(OutputFormat::Json)).unwrap();
+
+        assert_eq!(2, json.as_array().unwrap().len());
+        assert_eq!("dropbox", json[0]["provider"]);
+        assert!(json[1]["subreddit"].is_null());
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(
+            "2022-05-01 00:00:00",
+            parse_date("2022-05-01").unwrap().to_string()
+        );
+        assert!(parse_date("05/01/2022").is_err());
+    }
+}
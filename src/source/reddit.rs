@@ -1,23 +1,37 @@
-use reqwest;
+use crate::config::SourceSettings;
+use crate::http::{HttpClient, HttpError};
+use crate::postgres_orm::models::{DownloadJob, NewDownloadJob, NewFileSource};
+use crate::storage_download::jobs::JobStatus;
+use crate::storage_download::registry::provider_for;
+use chrono::{DateTime, NaiveDateTime};
+use lazy_static::lazy_static;
+use regex::Regex;
+use url::Url;
+
+const SUBMISSION_SEARCH_URL: &str = "https://api.pushshift.io/reddit/search/submission/";
+
+const COMMENT_SEARCH_URL: &str = "https://api.pushshift.io/reddit/search/comment/";
 
 #[derive(Debug, Clone)]
-pub struct RedditPost<'a> {
-    pub url_domain: &'a str,
+pub struct RedditPost {
+    pub url_domain: String,
     full_url: String,
-    #[allow(unused)]
     subreddit: String,
-    #[allow(unused)]
     score: f64,
     title: String,
+    created_utc: u32,
+    permalink: String,
 }
 
-impl RedditPost<'_> {
+impl RedditPost {
     pub fn new(
-        url_domain: &str,
+        url_domain: String,
         full_url: String,
         subreddit: String,
         score: f64,
         title: String,
+        created_utc: u32,
+        permalink: String,
     ) -> RedditPost {
         RedditPost {
             url_domain,
@@ -25,6 +39,8 @@ impl RedditPost<'_> {
             subreddit,
             score,
             title,
+            created_utc,
+            permalink,
         }
     }
 
@@ -35,18 +51,147 @@ impl RedditPost<'_> {
     pub fn get_title(&self) -> String {
         self.title.clone()
     }
+
+    /// Builds the `file_source` row for an archive downloaded from this post.
+    /// `url` is where the archive was downloaded from, which can differ from
+    /// the link as posted once shorteners and redirects are followed.
+    pub fn new_file_source<'b>(
+        &'b self,
+        url: &'b str,
+        compressed_file_name: &'b str,
+        provider: &'b str,
+    ) -> NewFileSource<'b> {
+        let time_inserted = chrono::Utc::now().naive_utc();
+
+        NewFileSource {
+            url,
+            compressed_file_name,
+            time_inserted,
+            provider,
+            subreddit: Some(&self.subreddit),
+            posted_at: self.posted_at().unwrap_or(time_inserted),
+            post_url: Some(&self.permalink),
+            post_title: Some(&self.title),
+            original_url: Some(&self.full_url),
+            size_bytes: None,
+            mime_type: None,
+            md5_checksum: None,
+            source_modified_at: None,
+        }
+    }
+
+    /// Builds the queued job for this post's link. The provider is filled in
+    /// when the link already points at one; shortened links only get theirs
+    /// once followed.
+    pub fn new_download_job<'b>(&'b self, post_id: &'b str) -> NewDownloadJob<'b> {
+        let created_at = chrono::Utc::now().naive_utc();
+
+        NewDownloadJob {
+            post_id,
+            url: &self.full_url,
+            provider: provider_for(&self.full_url).map(|val| val.name()),
+            status: JobStatus::Pending.as_str(),
+            url_domain: &self.url_domain,
+            subreddit: &self.subreddit,
+            post_title: &self.title,
+            post_url: &self.permalink,
+            posted_at: self.posted_at().unwrap_or(created_at),
+            score: self.score,
+            created_at,
+            updated_at: created_at,
+        }
+    }
+
+    fn posted_at(&self) -> Option<NaiveDateTime> {
+        DateTime::from_timestamp(self.created_utc as i64, 0).map(|val| val.naive_utc())
+    }
+
+    /// The post a queued job was found in, so that it can be downloaded
+    /// without going back to Reddit.
+    pub fn from_job(job: &DownloadJob) -> Self {
+        RedditPost::new(
+            job.url_domain.clone(),
+            job.url.clone(),
+            job.subreddit.clone(),
+            job.score,
+            job.post_title.clone(),
+            job.posted_at.and_utc().timestamp() as u32,
+            job.post_url.clone(),
+        )
+    }
 }
 
 #[derive(serde::Deserialize)]
 pub struct SubmissionPost {
     pub domain: String,
-    pub link_flair_text: Option<String>,
     pub url: Option<String>,
     pub created_utc: u32,
     pub full_link: String,
     pub score: f64,
     pub title: String,
     pub subreddit: String,
+    #[serde(default)]
+    pub id: String,
+    pub author: Option<String>,
+    #[serde(default)]
+    pub is_self: bool,
+    pub selftext: Option<String>,
+}
+
+impl SubmissionPost {
+    /// Every link to download for this post: the link it was posted with,
+    /// followed by provider links in its body and in `op_comments`, without
+    /// repeats. Links in text only count when a provider handles them, so
+    /// shortened links are only followed when they are the post's own link.
+    pub fn download_links(&self, op_comments: &[String]) -> Vec<String> {
+        let post_url = self.url.iter().filter(|_| !self.is_self).cloned();
+        let text_links = self
+            .selftext
+            .iter()
+            .chain(op_comments)
+            .flat_map(|val| extract_links(val))
+            .filter(|val| provider_for(val).is_some());
+
+        let mut links: Vec<String> = Vec::new();
+        for link in post_url.chain(text_links) {
+            if !links.contains(&link) {
+                links.push(link);
+            }
+        }
+
+        links
+    }
+
+    /// Domain recorded for one of `download_links`: the one Reddit gives for
+    /// the link the post was made with, and the link's own host for links
+    /// found in text.
+    pub fn link_domain(&self, link: &str) -> String {
+        if !self.is_self && self.url.as_deref() == Some(link) {
+            return self.domain.clone();
+        }
+
+        Url::parse(link)
+            .ok()
+            .and_then(|val| val.host_str().map(str::to_string))
+            .unwrap_or_else(|| self.domain.clone())
+    }
+}
+
+/// Urls in the markdown of a post or comment, with Reddit's escaping undone.
+pub fn extract_links(text: &str) -> Vec<String> {
+    lazy_static! {
+        static ref URL: Regex = Regex::new(r#"https?://[^\s<>()\[\]"'|]+"#).unwrap();
+    }
+
+    URL.find_iter(text)
+        .map(|val| {
+            val.as_str()
+                .replace("\\_", "_")
+                .replace("&amp;", "&")
+                .trim_end_matches(|c| ".,;:!?*\\".contains(c))
+                .to_string()
+        })
+        .collect()
 }
 
 #[derive(serde::Deserialize)]
@@ -55,22 +200,223 @@ pub struct RequestSubmissionResponse {
     pub items: Vec<SubmissionPost>,
 }
 
+/// The Pushshift search for the newest submissions. `time_period` is given
+/// as query parameters already (`after=7d&before=1d`), `q` as the text
+/// searched for, and both are encoded here.
+fn submission_search_url(
+    source: &SourceSettings,
+    q: Option<&str>,
+    time_period: Option<&str>,
+) -> Url {
+    let mut url = Url::parse(SUBMISSION_SEARCH_URL).unwrap();
+    {
+        let mut query = url.query_pairs_mut();
+        query
+            .append_pair("subreddit", &source.subreddit)
+            .append_pair("sort", "desc")
+            .append_pair("sort_type", "created_utc")
+            .append_pair("size", &source.page_size.to_string());
+        if let Some(time_period) = time_period {
+            query.extend_pairs(url::form_urlencoded::parse(time_period.as_bytes()));
+        }
+        if let Some(q) = q {
+            query.append_pair("q", q);
+        }
+    }
+
+    url
+}
+
 #[tokio::main]
 pub async fn get_posts(
+    client: &HttpClient,
+    source: &SourceSettings,
     q: Option<String>,
     time_period: Option<String>,
-) -> anyhow::Result<String, reqwest::Error> {
-    let base_url = String::from("https://api.pushshift.io/reddit/search/submission/?subreddit=drumkits&sort=desc&sort_type=created_utc&size=1000");
+) -> anyhow::Result<String, HttpError> {
+    let url = submission_search_url(source, q.as_deref(), time_period.as_deref());
 
-    let base_url = match time_period {
-        Some(val) => format!("{}&{}", base_url, val),
-        None => base_url,
-    };
+    client
+        .text(client.send(client.get(url)).await?.error_for_status()?)
+        .await
+}
+
+#[derive(serde::Deserialize)]
+struct CommentPost {
+    body: String,
+    parent_id: String,
+}
+
+#[derive(serde::Deserialize)]
+struct RequestCommentResponse {
+    #[serde(rename = "data")]
+    items: Vec<CommentPost>,
+}
+
+/// Bodies of the comments that reply to the post directly, rather than to
+/// another comment.
+fn parse_top_level_comments(response: &str, post_id: &str) -> anyhow::Result<Vec<String>> {
+    let response: RequestCommentResponse = serde_json::from_str(response)?;
+    let link_id = format!("t3_{}", post_id);
 
-    let base_url = match q {
-        Some(val) => format!("{}&q={}", base_url, val),
-        None => base_url,
+    Ok(response
+        .items
+        .into_iter()
+        .filter(|val| val.parent_id == link_id)
+        .map(|val| val.body)
+        .collect())
+}
+
+/// Top-level comments the author left on their own post, which is where kit
+/// links often end up when the post itself is an image or a preview.
+#[tokio::main]
+pub async fn get_op_comments(
+    client: &HttpClient,
+    post: &SubmissionPost,
+) -> anyhow::Result<Vec<String>> {
+    let author = match &post.author {
+        Some(val) if !post.id.is_empty() && val != "[deleted]" => val,
+        _ => return Ok(Vec::new()),
     };
 
-    reqwest::get(base_url).await?.text().await
+    let request = client.get(COMMENT_SEARCH_URL).query(&[
+        ("link_id", post.id.as_str()),
+        ("author", author.as_str()),
+        ("size", "100"),
+    ]);
+    let response = client
+        .text(client.send(request).await?.error_for_status()?)
+        .await?;
+
+    parse_top_level_comments(&response, &post.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_submission(url: &str, is_self: bool, selftext: &str) -> SubmissionPost {
+        SubmissionPost {
+            domain: "self.Drumkits".to_string(),
+            url: Some(url.to_string()),
+            created_utc: 1667260800,
+            full_link: "https://www.reddit.com/r/Drumkits/comments/yj3k2d/kit/".to_string(),
+            score: 1.0,
+            title: "Kit".to_string(),
+            subreddit: "Drumkits".to_string(),
+            id: "yj3k2d".to_string(),
+            author: Some("producer".to_string()),
+            is_self,
+            selftext: Some(selftext.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_extract_links() {
+        let text = "Free kit! [Download](https://drive.google.com/file/d/1-cgL6\\_YlB8g/view?usp=sharing)\n\n\
+                    Mirror: https://www.mediafire.com/file/k2lq0bm5/Kit.zip/file.\n\
+                    ig: <https://instagram.com/producer> | https://www.dropbox.com/s/abc/kit.zip?dl=0&amp;x=1";
+
+        assert_eq!(
+            vec![
+                "https://drive.google.com/file/d/1-cgL6_YlB8g/view?usp=sharing",
+                "https://www.mediafire.com/file/k2lq0bm5/Kit.zip/file",
+                "https://instagram.com/producer",
+                "https://www.dropbox.com/s/abc/kit.zip?dl=0&x=1",
+            ],
+            extract_links(text)
+        );
+        assert!(extract_links("no links here").is_empty());
+    }
+
+    #[test]
+    fn test_download_links() {
+        let self_post = test_submission(
+            "https://www.reddit.com/r/Drumkits/comments/yj3k2d/kit/",
+            true,
+            "https://mega.nz/file/Xq3lCz8k#key\n\nsame again: https://mega.nz/file/Xq3lCz8k#key \
+             and my beats https://www.youtube.com/watch?v=abc",
+        );
+        let comments = vec!["backup https://we.tl/t-k2lQ0bM5l8".to_string()];
+
+        assert_eq!(
+            vec![
+                "https://mega.nz/file/Xq3lCz8k#key",
+                "https://we.tl/t-k2lQ0bM5l8"
+            ],
+            self_post.download_links(&comments)
+        );
+
+        // link posts keep their own link first, even when no provider
+        // matches it yet
+        let link_post = test_submission(
+            "https://bit.ly/3kXyZ",
+            false,
+            "mirror https://www.dropbox.com/s/abc/kit.zip",
+        );
+        assert_eq!(
+            vec![
+                "https://bit.ly/3kXyZ",
+                "https://www.dropbox.com/s/abc/kit.zip"
+            ],
+            link_post.download_links(&[])
+        );
+    }
+
+    #[test]
+    fn test_link_domain() {
+        let self_post = test_submission(
+            "https://www.reddit.com/r/Drumkits/comments/yj3k2d/kit/",
+            true,
+            "",
+        );
+        assert_eq!(
+            "mega.nz",
+            self_post.link_domain("https://mega.nz/file/Xq3lCz8k#key")
+        );
+
+        let mut link_post = test_submission("https://bit.ly/3kXyZ", false, "");
+        link_post.domain = "bit.ly".to_string();
+        assert_eq!("bit.ly", link_post.link_domain("https://bit.ly/3kXyZ"));
+        assert_eq!(
+            "www.dropbox.com",
+            link_post.link_domain("https://www.dropbox.com/s/abc/kit.zip")
+        );
+    }
+
+    #[test]
+    fn test_submission_search_url() {
+        let source = SourceSettings::default();
+
+        assert_eq!(
+            "https://api.pushshift.io/reddit/search/submission/?subreddit=drumkits&sort=desc\
+             &sort_type=created_utc&size=1000",
+            submission_search_url(&source, None, None).as_str()
+        );
+        assert_eq!(
+            "https://api.pushshift.io/reddit/search/submission/?subreddit=drumkits&sort=desc\
+             &sort_type=created_utc&size=1000&after=1586604030&before=1605097230\
+             &q=drum+%26+bass+%23808",
+            submission_search_url(
+                &source,
+                Some("drum & bass #808"),
+                Some("after=1586604030&before=1605097230")
+            )
+            .as_str()
+        );
+    }
+
+    #[test]
+    fn test_parse_top_level_comments() {
+        let comments = parse_top_level_comments(
+            include_str!("../../test_samples/fixtures/reddit/op_comments.json"),
+            "yj3k2d",
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec!["Link is dead? Mirror: https://www.mediafire.com/file/k2lq0bm5/Kit.zip/file"],
+            comments
+        );
+    }
 }
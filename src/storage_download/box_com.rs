@@ -0,0 +1,193 @@
+use reqwest;
+use reqwest::header::CONTENT_DISPOSITION;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio;
+
+use super::download_utils;
+use super::error::DownloadError;
+use crate::http::HttpClient;
+use crate::postgres_orm::MetadataStore;
+use crate::source::reddit::RedditPost;
+use crate::DownloadFiles;
+
+/// Name recorded as `file_source.provider` for archives from this host
+pub(super) const PROVIDER: &str = "box";
+
+/// The shared link page embeds the data its scripts render from as
+/// `Box.postStreamData = {...};`
+const STREAM_DATA_MARKER: &str = "Box.postStreamData = ";
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct SharedItem {
+    #[serde(rename = "itemID")]
+    item_id: u64,
+    item_type: String,
+    shared_name: String,
+}
+
+#[derive(Debug, PartialEq)]
+struct SharedFile {
+    item: SharedItem,
+    name: Option<String>,
+}
+
+impl SharedFile {
+    fn download_url(&self) -> String {
+        format!(
+            "https://app.box.com/index.php?rm=box_download_shared_file&shared_name={}&file_id=f_{}",
+            &self.item.shared_name, self.item.item_id
+        )
+    }
+}
+
+/// Pulls the shared item out of a shared link page. Folders can only be
+/// downloaded as a zip when logged in, so they are turned down.
+fn parse_shared_page(html: &str) -> anyhow::Result<SharedFile> {
+    let start = html
+        .find(STREAM_DATA_MARKER)
+        .ok_or_else(|| anyhow::anyhow!("Box page has no shared item data"))?;
+    // the object is followed by the rest of the script, so only read the
+    // first JSON value
+    let stream_data: Value =
+        serde_json::Deserializer::from_str(&html[start + STREAM_DATA_MARKER.len()..])
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Box page has no shared item data"))??;
+
+    let item: SharedItem = serde_json::from_value(
+        stream_data
+            .get("/app-api/enduserapp/shared-item")
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Box page has no shared item data"))?,
+    )?;
+    if item.item_type != "file" {
+        return Err(DownloadError::Unsupported(format!(
+            "Box shared {} {} can't be downloaded without logging in",
+            &item.item_type, item.item_id
+        ))
+        .into());
+    }
+
+    let name = stream_data
+        .pointer("/~1app-api~1enduserapp~1shared-file/name")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+
+    Ok(SharedFile { item, name })
+}
+
+#[derive(Debug)]
+pub struct BoxMetadata {
+    url: String,
+    file_path: String,
+    out_path: Option<String>,
+}
+
+impl BoxMetadata {
+    pub fn new(url: String, file_path: String) -> Self {
+        Self {
+            url,
+            file_path,
+            out_path: None,
+        }
+    }
+}
+
+impl DownloadFiles<String> for BoxMetadata {
+    fn metadata_to_sql(self, store: &dyn MetadataStore, post: &RedditPost) -> anyhow::Result<()> {
+        store.create_file_row(&post.new_file_source(
+            &self.url,
+            &self.out_path.unwrap(),
+            PROVIDER,
+        ))?;
+
+        Ok(())
+    }
+
+    #[tokio::main]
+    async fn download(
+        mut self,
+        client: &HttpClient,
+        _hub: Option<&String>,
+        store: &dyn MetadataStore,
+        post: &RedditPost,
+    ) -> anyhow::Result<Option<String>> {
+        let html = client
+            .text(
+                client
+                    .send(client.get(&self.url))
+                    .await?
+                    .error_for_status()?,
+            )
+            .await?;
+        let shared_file = parse_shared_page(&html)?;
+
+        info!(
+            "Downloading Box file {} from url: {}",
+            shared_file.item.item_id, &self.url
+        );
+        let response = client
+            .send(client.get(shared_file.download_url()))
+            .await?
+            .error_for_status()?;
+        let file_name = response
+            .headers()
+            .get(CONTENT_DISPOSITION)
+            .and_then(|val| val.to_str().ok())
+            .and_then(download_utils::content_disposition_file_name)
+            .or_else(|| shared_file.name.clone())
+            .map(|val| download_utils::keyed_file_name(&val, &shared_file.item.item_id.to_string()))
+            .unwrap_or_else(|| format!("box_{}.zip", shared_file.item.item_id));
+        let content = client.bytes(response).await?;
+
+        self.out_path = Some(download_utils::save_download(
+            &self.file_path,
+            &file_name,
+            &content,
+        )?);
+
+        self.metadata_to_sql(store, post)?;
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_shared_page() {
+        let shared_file = parse_shared_page(include_str!(
+            "../../test_samples/fixtures/box/shared_file.html"
+        ))
+        .unwrap();
+        let folder = parse_shared_page(include_str!(
+            "../../test_samples/fixtures/box/shared_folder.html"
+        ))
+        .unwrap_err();
+
+        assert_eq!(
+            SharedFile {
+                item: SharedItem {
+                    item_id: 987654321012,
+                    item_type: "file".to_string(),
+                    shared_name: "k2lq0bm5l8xz0c7e8y1t3pbqa9v4r6w".to_string(),
+                },
+                name: Some("Lofi Drum Kit.zip".to_string()),
+            },
+            shared_file
+        );
+        assert_eq!(
+            "https://app.box.com/index.php?rm=box_download_shared_file&shared_name=k2lq0bm5l8xz0c7e8y1t3pbqa9v4r6w&file_id=f_987654321012",
+            shared_file.download_url()
+        );
+        assert_eq!(
+            "Box shared folder 176543219876 can't be downloaded without logging in",
+            folder.to_string()
+        );
+        assert!(parse_shared_page("<html></html>").is_err());
+    }
+}
@@ -1,8 +1,143 @@
+use percent_encoding::percent_decode_str;
 use std::fs;
+use std::io::Write;
 use std::path::Path;
 use std::process::Command;
 use zip;
 
+use crate::progress;
+
+/// Folder, relative to the working directory, that archives are extracted to,
+/// unless `[storage] unzipped_dir` says otherwise
+pub const UNZIPPED_FOLDER: &str = "unzipped";
+
+/// File name from a `Content-Disposition` header, preferring the RFC 5987
+/// `filename*=UTF-8''...` form over the plain `filename="..."` one.
+pub fn content_disposition_file_name(header: &str) -> Option<String> {
+    let mut plain_name = None;
+
+    for part in header.split(';').map(str::trim) {
+        if let Some(value) = part.strip_prefix("filename*=") {
+            let encoded = value.splitn(3, '\'').nth(2).unwrap_or(value);
+            let decoded = percent_decode_str(encoded.trim_matches('"')).decode_utf8_lossy();
+            if !decoded.is_empty() {
+                return Some(decoded.to_string());
+            }
+        } else if let Some(value) = part.strip_prefix("filename=") {
+            let value = value.trim_matches('"');
+            if !value.is_empty() {
+                plain_name = Some(value.to_string());
+            }
+        }
+    }
+
+    plain_name
+}
+
+/// Name an archive is saved under, with anything that would put it in
+/// another folder replaced.
+pub fn saved_file_name(file_name: &str) -> String {
+    file_name.trim().replace(['/', '\\'], "_")
+}
+
+/// Name of the archive a link points at, when it is in the link's path
+/// (`mediafire.com/file/<key>/Kit.zip/file`).
+pub fn archive_name_in_url(url: &str) -> Option<String> {
+    let parsed = url::Url::parse(url.trim()).ok()?;
+
+    parsed
+        .path_segments()?
+        .rev()
+        .map(|val| percent_decode_str(val).decode_utf8_lossy().to_string())
+        .find(|val| {
+            let lower = val.to_lowercase();
+            [".zip", ".rar", ".7z"]
+                .iter()
+                .any(|extension| lower.ends_with(extension) && lower.len() > extension.len())
+        })
+}
+
+/// `file_name` with `key` added before its extension, `Drum Kit_1a2b.zip`,
+/// for hosts whose ids tell apart kits that share a name.
+pub fn keyed_file_name(file_name: &str, key: &str) -> String {
+    match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{}_{}.{}", stem, key, extension),
+        _ => format!("{}_{}", file_name, key),
+    }
+}
+
+/// `file_name` with ` (<n>)` added before its extension
+fn numbered_file_name(file_name: &str, n: usize) -> String {
+    match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{} ({}).{}", stem, n, extension)
+        }
+        _ => format!("{} ({})", file_name, n),
+    }
+}
+
+/// Writes a downloaded archive to `folder` and returns the file name used,
+/// which is what the `file_source` row should be keyed by. Archives already
+/// there are never written over: one with the same content is taken to be
+/// this one, saved by an earlier attempt, and any other moves this one to
+/// `<name> (2).zip` and so on.
+pub fn save_download(folder: &str, file_name: &str, content: &[u8]) -> anyhow::Result<String> {
+    let file_name = saved_file_name(file_name);
+    if file_name.is_empty() || file_name == "." || file_name == ".." {
+        anyhow::bail!("{:?} can't be used as a file name", file_name);
+    }
+
+    for n in 1.. {
+        let name = if n == 1 {
+            file_name.clone()
+        } else {
+            numbered_file_name(&file_name, n)
+        };
+        let path = Path::new(folder).join(&name);
+
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(mut file) => {
+                file.write_all(content)?;
+                info!("Saved {} bytes to {}", content.len(), path.display());
+                return Ok(name);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                if fs::read(&path)? == content {
+                    info!("{} was already saved", path.display());
+                    return Ok(name);
+                }
+            }
+            Err(e) => anyhow::bail!("Couldn't create {}: {}", path.display(), e),
+        }
+    }
+
+    unreachable!()
+}
+
+/// Every one of `keywords` found in a sample's file name, in the order they
+/// are given in, e.g. `["kick", "808"]` for `808 Kick.wav`.
+pub fn instrument_labels(file_name: &str, keywords: &[String]) -> Vec<String> {
+    let lower_name = file_name.to_lowercase();
+
+    keywords
+        .iter()
+        .filter(|keyword| lower_name.contains(&keyword.to_lowercase()))
+        .cloned()
+        .collect()
+}
+
+/// What's kept from an archive and how it's labelled, from the
+/// `[extraction]` and `[classification]` settings
+#[derive(Debug)]
+pub struct SampleRules<'a> {
+    pub accepted_extensions: &'a [String],
+    pub instrument_keywords: &'a [String],
+}
+
 #[derive(Debug)]
 pub struct FilesInCompressed {
     pub compressed_file_root: String,
@@ -11,9 +146,9 @@ pub struct FilesInCompressed {
 }
 
 impl FilesInCompressed {
-    fn new(compressed_file_root: String, file_name_list: Vec<String>) -> Self {
-        let filter_vec_list = Self::filter_files(file_name_list);
-        let instrument_list = Self::get_instrument(&filter_vec_list);
+    fn new(compressed_file_root: String, file_name_list: Vec<String>, rules: &SampleRules) -> Self {
+        let filter_vec_list = Self::filter_files(file_name_list, rules.accepted_extensions);
+        let instrument_list = Self::get_instrument(&filter_vec_list, rules.instrument_keywords);
 
         Self {
             compressed_file_root,
@@ -22,69 +157,35 @@ impl FilesInCompressed {
         }
     }
 
-    fn get_instrument(file_list: &Vec<String>) -> Vec<String> {
-        let mut instrument_list = Vec::new();
-
-        let instrument_func = |indi_file_string: &str| {
-            if indi_file_string.contains("kick") {
-                "kick".to_string()
-            } else if indi_file_string.contains("snare") {
-                "snare".to_string()
-            } else if indi_file_string.contains("hat") {
-                "hat".to_string()
-            } else if indi_file_string.contains("perc") {
-                "perc".to_string()
-            } else if indi_file_string.contains("rim") {
-                "rim".to_string()
-            } else if indi_file_string.contains("clap") {
-                "clap".to_string()
-            } else if indi_file_string.contains("shaker") {
-                "shaker".to_string()
-            } else if indi_file_string.contains("ride") {
-                "ride".to_string()
-            } else if indi_file_string.contains("808") {
-                "808".to_string()
-            } else if indi_file_string.contains("foley") {
-                "foley".to_string()
-            } else if indi_file_string.contains("tom") {
-                "tom".to_string()
-            } else if indi_file_string.contains("fx") {
-                "fx".to_string()
-            } else if indi_file_string.contains("snap") {
-                "snap".to_string()
-            } else if indi_file_string.contains("lead") {
-                "lead".to_string()
-            } else if indi_file_string.contains("pad") {
-                "pad".to_string()
-            } else if indi_file_string.contains("guitar") {
-                "guitar".to_string()
-            } else if indi_file_string.contains("piano") {
-                "piano".to_string()
-            } else if indi_file_string.contains("flute") {
-                "flute".to_string()
-            } else if indi_file_string.contains("loop") {
-                "loop".to_string()
-            } else {
-                "unspecified".to_string()
-            }
-        };
-
-        for indi_file in file_list {
-            let instrument_from_file = instrument_func(&indi_file.to_lowercase());
-            instrument_list.push(instrument_from_file);
-        }
+    /// File name of the archive on disk, which is the key shared with the
+    /// `file_source` rows written by the download providers.
+    pub fn archive_name(&self) -> String {
+        Path::new(&self.compressed_file_root)
+            .file_name()
+            .map(|val| val.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.compressed_file_root.clone())
+    }
 
-        instrument_list
+    fn get_instrument(file_list: &[String], keywords: &[String]) -> Vec<String> {
+        file_list
+            .iter()
+            .map(|indi_file| {
+                instrument_labels(indi_file, keywords)
+                    .into_iter()
+                    .next()
+                    .unwrap_or_else(|| "unspecified".to_string())
+            })
+            .collect()
     }
 
-    fn filter_files(file_vec_list: Vec<String>) -> Vec<String> {
+    fn filter_files(file_vec_list: Vec<String>, extensions: &[String]) -> Vec<String> {
         let is_music_file = |file_string: String| {
             if file_string.contains("__MACOSX") || file_string.contains("__macosx") {
                 false
             } else {
-                file_string.contains(".wav")
-                    || file_string.contains(".mp3")
-                    || file_string.contains(".flac")
+                extensions
+                    .iter()
+                    .any(|extension| file_string.contains(&format!(".{}", extension)))
             }
         };
 
@@ -99,14 +200,14 @@ impl FilesInCompressed {
     }
 }
 
-pub fn unzip_files(folder_path: &str) -> anyhow::Result<()> {
-    let file_paths = match fs::read_dir(&folder_path) {
-        Ok(val) => val,
-        Err(e) => panic!(
-            "Downloaded files were not saved to directory, so they cannot be read. {}",
+pub fn unzip_files(folder_path: &str, unzipped_dir: &str) -> anyhow::Result<()> {
+    let file_paths = fs::read_dir(folder_path).map_err(|e| {
+        anyhow::anyhow!(
+            "Downloaded files were not saved to {}, so they cannot be read: {}",
+            folder_path,
             e
-        ),
-    };
+        )
+    })?;
 
     let mut file_list: Vec<String> = Vec::new();
 
@@ -118,34 +219,53 @@ pub fn unzip_files(folder_path: &str) -> anyhow::Result<()> {
     }
 
     // use 7z for unzipping both rar and zip
+    let bar = progress::counter(file_list.len() as u64, "archives extracted");
     for zip_file in file_list {
         let _new_command = Command::new("7z")
             .arg("x")
             .arg(&zip_file)
-            .arg("-ounzipped")
+            .arg(format!("-o{}", unzipped_dir))
             .output()
-            .expect("failed to list files in rar.");
+            .map_err(|e| anyhow::anyhow!("Couldn't run 7z to extract {}: {}", &zip_file, e))?;
+        bar.inc(1);
     }
+    bar.finish_and_clear();
 
     // remove MACOSX directory
-    if fs::metadata("./unzipped/__MACOSX/").is_ok() {
-        fs::remove_dir_all("./unzipped/__MACOSX/")?;
+    let macosx_folder = Path::new(unzipped_dir).join("__MACOSX");
+    if fs::metadata(&macosx_folder).is_ok() {
+        fs::remove_dir_all(&macosx_folder)?;
         info!("removed MACOSX folder from the list of folders that were unzipped");
     }
 
     Ok(())
 }
 
-pub fn get_files(folder_path: &str) -> anyhow::Result<Vec<FilesInCompressed>> {
+/// Number of files in `folder` and the folders inside it
+pub fn count_files(folder: &str) -> anyhow::Result<usize> {
+    let mut count = 0;
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            count += count_files(&path.display().to_string())?;
+        } else {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+pub fn get_files(folder_path: &str, rules: &SampleRules) -> anyhow::Result<Vec<FilesInCompressed>> {
     let base_path = Path::new(folder_path);
 
-    let file_paths = match fs::read_dir(base_path) {
-        Ok(val) => val,
-        Err(e) => panic!(
-            "Downloaded files were not saved to directory, so they cannot be read. {}",
+    let file_paths = fs::read_dir(base_path).map_err(|e| {
+        anyhow::anyhow!(
+            "Downloaded files were not saved to {}, so they cannot be read: {}",
+            folder_path,
             e
-        ),
-    };
+        )
+    })?;
 
     let mut files_in_zip = Vec::new();
     let mut rar_files: Vec<String> = Vec::new();
@@ -164,11 +284,9 @@ pub fn get_files(folder_path: &str) -> anyhow::Result<Vec<FilesInCompressed>> {
         .into_iter()
         .filter_map(|zipped_file| {
             let temp_path = Path::new(&zipped_file);
-            let read_file = fs::File::open(temp_path).unwrap();
-            let zip_archive = match zip::ZipArchive::new(read_file) {
-                Ok(val) => Some(val),
-                Err(_) => None,
-            };
+            let zip_archive = fs::File::open(temp_path)
+                .ok()
+                .and_then(|read_file| zip::ZipArchive::new(read_file).ok());
 
             if let Some(temp_zip) = &zip_archive {
                 let temp_file_names = zip::ZipArchive::file_names(temp_zip);
@@ -180,6 +298,7 @@ pub fn get_files(folder_path: &str) -> anyhow::Result<Vec<FilesInCompressed>> {
                 Some(FilesInCompressed::new(
                     zipped_file.clone(),
                     file_names_to_string,
+                    rules,
                 ))
             } else {
                 None
@@ -187,24 +306,20 @@ pub fn get_files(folder_path: &str) -> anyhow::Result<Vec<FilesInCompressed>> {
         })
         .collect();
 
-    let mut all_rar_files: Vec<FilesInCompressed> = rar_files
-        .into_iter()
-        .map(|rar_file: String| {
-            let new_command = Command::new("rar")
-                .arg("lb")
-                .arg(&rar_file)
-                .output()
-                .expect("failed to list files in rar.");
-
-            let rar_contents = String::from_utf8(new_command.stdout).unwrap();
-            let vec_file_list = rar_contents
-                .split('\n')
-                .map(std::string::ToString::to_string);
-            FilesInCompressed::new(rar_file, vec_file_list.clone().collect::<Vec<_>>())
-        })
-        .collect();
+    for rar_file in rar_files {
+        let new_command = Command::new("rar")
+            .arg("lb")
+            .arg(&rar_file)
+            .output()
+            .map_err(|e| anyhow::anyhow!("Couldn't run rar to list {}: {}", &rar_file, e))?;
 
-    all_zip_files.append(&mut all_rar_files);
+        let rar_contents = String::from_utf8_lossy(&new_command.stdout);
+        let vec_file_list = rar_contents
+            .split('\n')
+            .map(std::string::ToString::to_string)
+            .collect::<Vec<_>>();
+        all_zip_files.push(FilesInCompressed::new(rar_file, vec_file_list, rules));
+    }
 
     Ok(all_zip_files)
 }
@@ -212,11 +327,19 @@ pub fn get_files(folder_path: &str) -> anyhow::Result<Vec<FilesInCompressed>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
+
+    fn test_rules(config: &Config) -> SampleRules<'_> {
+        SampleRules {
+            accepted_extensions: &config.extraction.accepted_extensions,
+            instrument_keywords: &config.classification.instrument_keywords,
+        }
+    }
 
     #[test]
     fn test_get_files() {
         let folder_path_one = "./test_samples";
-        let vec_list = vec![
+        let vec_list = [
             "test/Billie Eilish_Bad Guy (Snap).wav".to_string(),
             "test/Gunna_Idk That Bitch (808).wav".to_string(),
             "test/Kanye West_Broken Road (Snare).wav".to_string(),
@@ -226,7 +349,8 @@ mod tests {
             "test/Travis Scott_5% Tint (Rim).wav".to_string(),
             "test/temmmm/Nav_Champion (Kick).wav".to_string(),
         ];
-        let comp_files = get_files(folder_path_one).unwrap();
+        let config = Config::default();
+        let comp_files = get_files(folder_path_one, &test_rules(&config)).unwrap();
         let all_files: Vec<String> = comp_files
             .into_iter()
             .flat_map(|val| val.file_name_list)
@@ -234,4 +358,158 @@ mod tests {
 
         assert!(vec_list.iter().all(|item| all_files.contains(item)));
     }
+
+    #[test]
+    fn test_get_instrument() {
+        let file_list = [
+            "Drums/808 Kick.wav".to_string(),
+            "Drums/Open Hat 2.wav".to_string(),
+            "Vox/Chant.wav".to_string(),
+        ];
+
+        let keywords = Config::default().classification.instrument_keywords;
+
+        assert_eq!(
+            vec!["kick", "hat", "unspecified"],
+            FilesInCompressed::get_instrument(&file_list, &keywords)
+        );
+        assert_eq!(
+            vec!["kick", "808"],
+            instrument_labels("Drums/808 Kick.wav", &keywords)
+        );
+        assert!(instrument_labels("Vox/Chant.wav", &keywords).is_empty());
+        assert_eq!(
+            vec!["Vox"],
+            instrument_labels("Vox/Chant.wav", &["Vox".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_filter_files() {
+        let file_list = vec![
+            "Drums/Kick.wav".to_string(),
+            "Drums/Snare.aif".to_string(),
+            "__MACOSX/Drums/._Snare.aif".to_string(),
+            "readme.txt".to_string(),
+        ];
+
+        assert_eq!(
+            vec!["Drums/Kick.wav"],
+            FilesInCompressed::filter_files(file_list.clone(), &["wav".to_string()])
+        );
+        assert_eq!(
+            vec!["Drums/Kick.wav", "Drums/Snare.aif"],
+            FilesInCompressed::filter_files(file_list, &["wav".to_string(), "aif".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_content_disposition_file_name() {
+        assert_eq!(
+            Some("Drum Kit.zip".to_string()),
+            content_disposition_file_name("attachment; filename=\"Drum Kit.zip\"")
+        );
+        assert_eq!(
+            Some("Kit Ü.zip".to_string()),
+            content_disposition_file_name(
+                "attachment; filename=\"Kit .zip\"; filename*=UTF-8''Kit%20%C3%9C.zip"
+            )
+        );
+        assert_eq!(
+            Some("kit.rar".to_string()),
+            content_disposition_file_name("inline;filename=kit.rar")
+        );
+        assert_eq!(None, content_disposition_file_name("attachment"));
+    }
+
+    #[test]
+    fn test_archive_name_in_url() {
+        let cases = [
+            (
+                "https://www.mediafire.com/file/k2lq0bm5/Kit%20Vol.%202.zip/file",
+                Some("Kit Vol. 2.zip"),
+            ),
+            ("https://host/kits/808.RAR?dl=1", Some("808.RAR")),
+            ("https://mega.nz/file/Xq3lCz8k#key", None),
+            ("https://host/.zip", None),
+            ("not a url", None),
+        ];
+
+        for (url, expected) in cases.iter() {
+            assert_eq!(
+                expected.map(|val| val.to_string()),
+                archive_name_in_url(url),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn test_save_download() {
+        let folder = tempfile::tempdir().unwrap();
+        let dir = folder.path().to_str().unwrap();
+
+        let name = save_download(dir, "kits/808.zip", b"zip").unwrap();
+        let content = fs::read(folder.path().join(&name)).unwrap();
+
+        assert_eq!("kits_808.zip", name);
+        assert_eq!(b"zip".to_vec(), content);
+        assert!(save_download(dir, " ", b"").is_err());
+    }
+
+    #[test]
+    fn test_save_same_named_downloads() {
+        let folder = tempfile::tempdir().unwrap();
+        let dir = folder.path().to_str().unwrap();
+
+        let first = save_download(dir, "Drum Kit.zip", b"first kit").unwrap();
+        let second = save_download(dir, "Drum Kit.zip", b"second kit").unwrap();
+        let retried = save_download(dir, "Drum Kit.zip", b"second kit").unwrap();
+        let contents = (
+            fs::read(folder.path().join(&first)).unwrap(),
+            fs::read(folder.path().join(&second)).unwrap(),
+        );
+
+        assert_eq!("Drum Kit.zip", first);
+        assert_eq!("Drum Kit (2).zip", second);
+        assert_eq!(second, retried);
+        assert_eq!((b"first kit".to_vec(), b"second kit".to_vec()), contents);
+    }
+
+    #[test]
+    fn test_keyed_file_name() {
+        assert_eq!(
+            "Drum Kit_1a2b.zip",
+            keyed_file_name("Drum Kit", "1a2b") + ".zip"
+        );
+        assert_eq!("Drum Kit_1a2b.zip", keyed_file_name("Drum Kit.zip", "1a2b"));
+        assert_eq!("Kit.v2_1a2b.rar", keyed_file_name("Kit.v2.rar", "1a2b"));
+        assert_eq!(".zip_1a2b", keyed_file_name(".zip", "1a2b"));
+    }
+
+    #[test]
+    fn test_archive_name() {
+        let config = Config::default();
+        let comp_files = get_files("./test_samples", &test_rules(&config)).unwrap();
+        let archive_names: Vec<String> = comp_files.iter().map(|val| val.archive_name()).collect();
+
+        assert_eq!(vec!["test.zip".to_string()], archive_names);
+    }
+
+    #[test]
+    fn test_count_files() {
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().to_path_buf();
+        fs::create_dir_all(path.join("kit/drums")).unwrap();
+        fs::write(path.join("kit/drums/kick.wav"), b"").unwrap();
+        fs::write(path.join("kit/snare.wav"), b"").unwrap();
+        fs::write(path.join("readme.txt"), b"").unwrap();
+
+        let count = count_files(path.to_str().unwrap()).unwrap();
+        folder.close().unwrap();
+
+        assert_eq!(3, count);
+        assert!(count_files(path.to_str().unwrap()).is_err());
+    }
 }
@@ -1,67 +1,143 @@
-use crate::postgres_orm;
+use crate::http::HttpClient;
+use crate::postgres_orm::MetadataStore;
+use crate::source::reddit::RedditPost;
 use crate::DownloadFiles;
+use percent_encoding::percent_decode_str;
 use reqwest;
+use reqwest::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
-use std::io::Write;
-use std::path::Path;
 use tokio;
+use url::Url;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct DropboxAudienceOptions {
-    allowed: bool,
-    audience: HashMap<String, String>,
-}
+use super::download_utils;
+use super::error::DownloadError;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct DropboxPolicyOptions {
-    allowed: bool,
-    policy: HashMap<String, String>,
-    resolved_policy: HashMap<String, String>,
-}
+/// Name recorded as `file_source.provider` for archives from this host
+pub(super) const PROVIDER: &str = "dropbox";
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct DropboxLinkPermissions {
-    all_comments: bool,
-    allow_download: bool,
-    audience_options: Vec<DropboxAudienceOptions>,
-    can_allow_download: bool,
-    can_disallow_download: bool,
-    can_remove_expiry: bool,
-    can_remove_password: bool,
-    can_revoke: bool,
-    can_set_expiry: bool,
-    can_set_password: bool,
-    can_use_extended_sharing_controls: bool,
-    require_password: bool,
-    resolved_visibility: HashMap<String, String>,
-    revoke_failure_reason: HashMap<String, String>,
-    team_restrics_comments: bool,
-    visibility_policies: Vec<DropboxAudienceOptions>,
+#[derive(Debug, PartialEq)]
+enum LinkKind {
+    File,
+    /// Folders are downloaded as a zip of their contents
+    Folder,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct DropboxTeamMemberInfo {
-    display_name: String,
-    member_id: String,
-    team_info: HashMap<String, String>,
+/// A shared link, which is either the older `/s/<id>/<name>` (file) and
+/// `/sh/<id>/<key>` (folder) form, or the newer `/scl/fi/` and `/scl/fo/`
+/// form that only works with its `rlkey`.
+#[derive(Debug, PartialEq)]
+struct DropboxLink {
+    kind: LinkKind,
+    /// The id in the link's path, which tells apart links to files or
+    /// folders with the same name
+    id: String,
+    url: Url,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct DropboxDownloadResponse {
-    #[serde(rename = "tag")]
-    tag: String,
-    client_modified: String,
-    id: String,
-    link_permissions: DropboxLinkPermissions,
-    name: String,
-    path_lower: String,
-    rev: String,
-    server_modified: String,
-    size: f64,
-    team_member_info: DropboxTeamMemberInfo,
-    url: String,
+impl DropboxLink {
+    fn parse(url: &str) -> anyhow::Result<Self> {
+        // links pulled out of Reddit markdown keep its escaping, `&amp;` for
+        // `&` and a backslash (`%5C` once encoded) before underscores
+        let cleaned = url
+            .trim()
+            .replace("&amp;", "&")
+            .replace("%5C", "")
+            .replace('\\', "");
+        let url = Url::parse(&cleaned)?;
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+
+        let segments: Vec<String> = url
+            .path_segments()
+            .map(|val| {
+                val.filter(|val| !val.is_empty())
+                    .map(|val| val.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let segments: Vec<&str> = segments.iter().map(|val| val.as_str()).collect();
+
+        // already a direct link to the file
+        if host == "dl.dropboxusercontent.com" {
+            let id = match segments.as_slice() {
+                ["s", id, ..] | ["scl", "fi", id, ..] => id.to_string(),
+                _ => anyhow::bail!("{} is not a Dropbox download link", &cleaned),
+            };
+            return Ok(Self {
+                kind: LinkKind::File,
+                id,
+                url,
+            });
+        }
+        if host != "dropbox.com" && !host.ends_with(".dropbox.com") {
+            anyhow::bail!("{} is not a Dropbox link", &cleaned);
+        }
+
+        let (kind, id) = match segments.as_slice() {
+            ["s", id, ..] => (LinkKind::File, id),
+            ["sh", id, ..] => (LinkKind::Folder, id),
+            ["scl", "fi", id, ..] => (LinkKind::File, id),
+            ["scl", "fo", id, ..] => (LinkKind::Folder, id),
+            _ => anyhow::bail!("{} is not a Dropbox shared link", &cleaned),
+        };
+        let id = id.to_string();
+
+        let has_rlkey = url
+            .query_pairs()
+            .any(|(key, val)| key == "rlkey" && !val.is_empty());
+        if segments[0] == "scl" && !has_rlkey {
+            return Err(DownloadError::Unsupported(format!(
+                "Dropbox link {} is missing its rlkey",
+                &cleaned
+            ))
+            .into());
+        }
+
+        Ok(Self { kind, id, url })
+    }
+
+    /// The same link with `dl=1`, which serves the file (or a zip of the
+    /// folder) rather than the preview page. Everything else in the query,
+    /// the `rlkey` in particular, is kept.
+    fn direct_url(&self) -> String {
+        let mut url = self.url.clone();
+        if url.host_str() == Some("dl.dropboxusercontent.com") {
+            return url.to_string();
+        }
+
+        let query: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(key, _)| key != "dl" && key != "raw")
+            .map(|(key, val)| (key.to_string(), val.to_string()))
+            .collect();
+        url.query_pairs_mut()
+            .clear()
+            .extend_pairs(query)
+            .append_pair("dl", "1");
+
+        url.to_string()
+    }
+
+    /// Name to save `file_name` under, with the link's id in it, as folder
+    /// zips in particular come with names that are often the same
+    fn saved_file_name(&self, file_name: &str) -> String {
+        download_utils::keyed_file_name(file_name, &self.id)
+    }
+
+    /// Name to save under when the response doesn't give one. Files are
+    /// named by the last part of their path, folders after the post.
+    fn fallback_file_name(&self, title: &str) -> String {
+        let last_segment = self
+            .url
+            .path_segments()
+            .and_then(|mut val| val.next_back())
+            .map(|val| percent_decode_str(val).decode_utf8_lossy().to_string())
+            .filter(|val| val.contains('.'));
+
+        match (&self.kind, last_segment) {
+            (LinkKind::File, Some(name)) => name,
+            _ => format!("{}.zip", title.replace(' ', "_")),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -74,30 +150,30 @@ pub struct DropboxMetadata {
 
 impl DropboxMetadata {
     pub fn new(url: String, file_name: String, file_path: String) -> Self {
-        // hack for now
-        // roux on url adds amp; and %5C
-        // on decoding on string from
-        // this has created an issue with
-        // getting the right url for
-        // dropbox
-        let new_url = url.replace("amp;", "");
-        let new_url = new_url.replace("%5C", "");
-        let new_url = new_url.replace("dl=0", "dl=1");
-
-        let new_file_name = file_name.replace(' ', "_");
-
         Self {
-            url: new_url,
-            file_name: new_file_name,
+            url,
+            file_name,
             file_path,
             out_path: None,
         }
     }
+
+    /// Name the download is saved under when Dropbox doesn't send one, which
+    /// for files is also the name it sends
+    pub fn planned_file_name(&self) -> Option<String> {
+        DropboxLink::parse(&self.url)
+            .ok()
+            .map(|link| link.saved_file_name(&link.fallback_file_name(&self.file_name)))
+    }
 }
 
 impl DownloadFiles<String> for DropboxMetadata {
-    fn metadata_to_sql(self, conn: &diesel::PgConnection) -> anyhow::Result<()> {
-        postgres_orm::create_file_row(conn, &self.url, &self.out_path.unwrap())?;
+    fn metadata_to_sql(self, store: &dyn MetadataStore, post: &RedditPost) -> anyhow::Result<()> {
+        store.create_file_row(&post.new_file_source(
+            &self.url,
+            &self.out_path.unwrap(),
+            PROVIDER,
+        ))?;
 
         Ok(())
     }
@@ -105,30 +181,136 @@ impl DownloadFiles<String> for DropboxMetadata {
     #[tokio::main]
     async fn download(
         mut self,
+        client: &HttpClient,
         _hub: Option<&String>,
-        conn: &diesel::PgConnection,
-    ) -> anyhow::Result<()> {
-        let new_file_name = self.file_name.clone().replace('/', "_");
-        let full_file_path = format!("{}/{}.zip", &self.file_path, new_file_name);
+        store: &dyn MetadataStore,
+        post: &RedditPost,
+    ) -> anyhow::Result<Option<String>> {
+        let link = DropboxLink::parse(&self.url)?;
+        self.url = link.direct_url();
 
         info!(
-            "Name of the compressed file to be saved from dropbox: {}",
-            &full_file_path
+            "Downloading Dropbox {:?} from url: {}",
+            link.kind, &self.url
         );
+        let response = client
+            .send(client.get(&self.url))
+            .await?
+            .error_for_status()?;
 
-        let path = Path::new(&full_file_path);
-        let mut file = match fs::File::create(path) {
-            Ok(val) => val,
-            Err(e) => panic!("Couldn't open the file: {}", e),
-        };
+        // removed and private links land on an HTML page rather than failing
+        let is_html = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|val| val.to_str().ok())
+            .is_some_and(|val| val.starts_with("text/html"));
+        if is_html {
+            return Err(DownloadError::NotFound(format!(
+                "Dropbox link {} has no file to download",
+                &self.url
+            ))
+            .into());
+        }
 
-        let resp = reqwest::get(&self.url).await?.bytes().await?;
+        let file_name = link.saved_file_name(
+            &response
+                .headers()
+                .get(CONTENT_DISPOSITION)
+                .and_then(|val| val.to_str().ok())
+                .and_then(download_utils::content_disposition_file_name)
+                .unwrap_or_else(|| link.fallback_file_name(&self.file_name)),
+        );
+        let content = client.bytes(response).await?;
 
-        file.write_all(&resp)?;
-        self.out_path = Some(new_file_name.clone());
+        self.out_path = Some(download_utils::save_download(
+            &self.file_path,
+            &file_name,
+            &content,
+        )?);
 
-        self.metadata_to_sql(conn)?;
+        self.metadata_to_sql(store, post)?;
 
-        Ok(())
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_link() {
+        let cases = [
+            (
+                "https://www.dropbox.com/s/abc123/Drum%20Kit.zip?dl=0",
+                LinkKind::File,
+                "https://www.dropbox.com/s/abc123/Drum%20Kit.zip?dl=1",
+            ),
+            (
+                "https://dropbox.com/sh/hkgtorveen2jvh6/AAAf0TStSQD_9PAOTjubPU1Ma?dl=0",
+                LinkKind::Folder,
+                "https://dropbox.com/sh/hkgtorveen2jvh6/AAAf0TStSQD_9PAOTjubPU1Ma?dl=1",
+            ),
+            (
+                "https://www.dropbox.com/scl/fi/x1y2z3/kit.zip?rlkey=abc&amp;dl=0",
+                LinkKind::File,
+                "https://www.dropbox.com/scl/fi/x1y2z3/kit.zip?rlkey=abc&dl=1",
+            ),
+            (
+                "https://www.dropbox.com/scl/fo/q9w8e7/AAB1c2d3?rlkey=xyz&st=5k2l&dl=0",
+                LinkKind::Folder,
+                "https://www.dropbox.com/scl/fo/q9w8e7/AAB1c2d3?rlkey=xyz&st=5k2l&dl=1",
+            ),
+            (
+                "https://www.dropbox.com/s/abc123/808%5C_kit.zip?raw=1",
+                LinkKind::File,
+                "https://www.dropbox.com/s/abc123/808_kit.zip?dl=1",
+            ),
+            (
+                "https://www.dropbox.com/s/abc123/kit.zip",
+                LinkKind::File,
+                "https://www.dropbox.com/s/abc123/kit.zip?dl=1",
+            ),
+            (
+                "https://dl.dropboxusercontent.com/s/abc123/kit.zip",
+                LinkKind::File,
+                "https://dl.dropboxusercontent.com/s/abc123/kit.zip",
+            ),
+        ];
+
+        for (url, kind, direct_url) in cases.iter() {
+            let link = DropboxLink::parse(url).unwrap();
+            assert_eq!(*kind, link.kind, "{}", url);
+            assert_eq!(*direct_url, link.direct_url(), "{}", url);
+        }
+
+        assert_eq!(
+            "Dropbox link https://www.dropbox.com/scl/fi/x1y2z3/kit.zip?dl=0 is missing its rlkey",
+            DropboxLink::parse("https://www.dropbox.com/scl/fi/x1y2z3/kit.zip?dl=0")
+                .unwrap_err()
+                .to_string()
+        );
+        assert!(DropboxLink::parse("https://www.dropbox.com/home").is_err());
+        assert!(DropboxLink::parse("https://notdropbox.com/s/abc123/kit.zip").is_err());
+    }
+
+    #[test]
+    fn test_fallback_file_name() {
+        let file =
+            DropboxLink::parse("https://www.dropbox.com/s/abc123/Drum%20Kit.zip?dl=0").unwrap();
+        let folder =
+            DropboxLink::parse("https://www.dropbox.com/scl/fo/q9w8e7/AAB1c2d3?rlkey=xyz&dl=0")
+                .unwrap();
+
+        assert_eq!("Drum Kit.zip", file.fallback_file_name("Kit"));
+        assert_eq!(
+            "Lofi_Kit_Vol._2.zip",
+            folder.fallback_file_name("Lofi Kit Vol. 2")
+        );
+        assert_eq!("Drum Kit_abc123.zip", file.saved_file_name("Drum Kit.zip"));
+        assert_eq!(
+            "Lofi_Kit_Vol._2_q9w8e7.zip",
+            folder.saved_file_name(&folder.fallback_file_name("Lofi Kit Vol. 2"))
+        );
     }
 }
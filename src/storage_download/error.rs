@@ -0,0 +1,214 @@
+use reqwest::StatusCode;
+use std::fmt;
+
+use super::google_drive::DriveLinkError;
+use super::mediafire::MediafireError;
+use crate::http::HttpError;
+
+/// Why a kit couldn't be downloaded. Providers return the variant they know
+/// applies; anything else is sorted into one by `DownloadError::from`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadError {
+    /// The host couldn't be reached, or answered with something unexpected
+    Network(String),
+    /// Credentials are missing or were turned down
+    Auth(String),
+    /// The link is dead: removed, expired or never existed
+    NotFound(String),
+    /// The host is rate limiting or over its download quota
+    Quota(String),
+    /// The link points at something that can't be downloaded as a kit
+    Unsupported(String),
+    Io(String),
+    Db(String),
+}
+
+impl DownloadError {
+    /// Short name of the variant, used to group failures in logs and records
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DownloadError::Network(_) => "network",
+            DownloadError::Auth(_) => "auth",
+            DownloadError::NotFound(_) => "not_found",
+            DownloadError::Quota(_) => "quota",
+            DownloadError::Unsupported(_) => "unsupported",
+            DownloadError::Io(_) => "io",
+            DownloadError::Db(_) => "db",
+        }
+    }
+
+    /// Variant for an HTTP error status
+    pub fn from_status(status: StatusCode, message: String) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => DownloadError::Auth(message),
+            StatusCode::NOT_FOUND | StatusCode::GONE => DownloadError::NotFound(message),
+            StatusCode::TOO_MANY_REQUESTS => DownloadError::Quota(message),
+            _ => DownloadError::Network(message),
+        }
+    }
+
+    /// Variant for a request that failed or whose response couldn't be read
+    fn from_http(e: &HttpError, message: String) -> Self {
+        match e {
+            HttpError::Request(val) => match val.status() {
+                Some(status) => DownloadError::from_status(status, message),
+                None => DownloadError::Network(message),
+            },
+            HttpError::ReadTimeout { .. } => DownloadError::Network(message),
+            HttpError::TooLarge { .. } => DownloadError::Unsupported(message),
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            DownloadError::Network(val)
+            | DownloadError::Auth(val)
+            | DownloadError::NotFound(val)
+            | DownloadError::Quota(val)
+            | DownloadError::Unsupported(val)
+            | DownloadError::Io(val)
+            | DownloadError::Db(val) => val,
+        }
+    }
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+/// Sorts an error from a provider by the first cause in its chain that says
+/// what went wrong. Errors nothing is known about count as network errors,
+/// as they're most often a host answering with something unexpected.
+impl From<anyhow::Error> for DownloadError {
+    fn from(e: anyhow::Error) -> Self {
+        let message = format!("{:#}", e);
+
+        for cause in e.chain() {
+            if let Some(val) = cause.downcast_ref::<DownloadError>() {
+                return match val {
+                    // keep the context added around it
+                    DownloadError::Network(_) => DownloadError::Network(message),
+                    DownloadError::Auth(_) => DownloadError::Auth(message),
+                    DownloadError::NotFound(_) => DownloadError::NotFound(message),
+                    DownloadError::Quota(_) => DownloadError::Quota(message),
+                    DownloadError::Unsupported(_) => DownloadError::Unsupported(message),
+                    DownloadError::Io(_) => DownloadError::Io(message),
+                    DownloadError::Db(_) => DownloadError::Db(message),
+                };
+            }
+            if let Some(val) = cause.downcast_ref::<HttpError>() {
+                return DownloadError::from_http(val, message);
+            }
+            if let Some(val) = cause.downcast_ref::<reqwest::Error>() {
+                return match val.status() {
+                    Some(status) => DownloadError::from_status(status, message),
+                    None => DownloadError::Network(message),
+                };
+            }
+            if let Some(val) = cause.downcast_ref::<MediafireError>() {
+                return match val {
+                    MediafireError::Status { status, .. } => {
+                        DownloadError::from_status(*status, message)
+                    }
+                    MediafireError::Http(e) => DownloadError::from_http(e, message),
+                    MediafireError::NoDownloadButton => DownloadError::NotFound(message),
+                    MediafireError::FolderApi { .. } => DownloadError::NotFound(message),
+                    _ => DownloadError::Network(message),
+                };
+            }
+            if cause.is::<DriveLinkError>() {
+                return DownloadError::Unsupported(message);
+            }
+            if cause.is::<std::io::Error>() {
+                return DownloadError::Io(message);
+            }
+            if cause.is::<diesel::result::Error>() || cause.is::<diesel::ConnectionError>() {
+                return DownloadError::Db(message);
+            }
+        }
+
+        DownloadError::Network(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_from_anyhow() {
+        let cases = [
+            (
+                anyhow::Error::new(DownloadError::Quota("over quota".to_string()))
+                    .context("couldn't download kit.zip"),
+                DownloadError::Quota("couldn't download kit.zip: over quota".to_string()),
+            ),
+            (
+                anyhow::Error::new(MediafireError::NoDownloadButton),
+                DownloadError::NotFound("Mediafire page has no download button".to_string()),
+            ),
+            (
+                anyhow::Error::new(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "denied",
+                )),
+                DownloadError::Io("denied".to_string()),
+            ),
+            (
+                anyhow::Error::new(diesel::result::Error::NotFound),
+                DownloadError::Db("NotFound".to_string()),
+            ),
+            (
+                anyhow::Error::new(HttpError::TooLarge {
+                    url: "https://host/video.mp4".to_string(),
+                    limit: 1024,
+                }),
+                DownloadError::Unsupported(
+                    "https://host/video.mp4 is larger than the 1024 byte limit".to_string(),
+                ),
+            ),
+            (
+                anyhow::anyhow!("pCloud returned no download hosts"),
+                DownloadError::Network("pCloud returned no download hosts".to_string()),
+            ),
+        ];
+
+        for (error, expected) in cases {
+            assert_eq!(expected, DownloadError::from(error));
+        }
+
+        let error: anyhow::Result<()> = Err(DriveLinkError::UnknownShape(
+            "https://drive.google.com/drive/my-drive".to_string(),
+        ))
+        .context("skipping");
+        assert_eq!(
+            "unsupported",
+            DownloadError::from(error.unwrap_err()).kind()
+        );
+    }
+
+    #[test]
+    fn test_from_status() {
+        assert_eq!(
+            "auth",
+            DownloadError::from_status(StatusCode::FORBIDDEN, String::new()).kind()
+        );
+        assert_eq!(
+            "not_found",
+            DownloadError::from_status(StatusCode::GONE, String::new()).kind()
+        );
+        assert_eq!(
+            "quota",
+            DownloadError::from_status(StatusCode::TOO_MANY_REQUESTS, String::new()).kind()
+        );
+        assert_eq!(
+            "network",
+            DownloadError::from_status(StatusCode::BAD_GATEWAY, String::new()).kind()
+        );
+    }
+}
@@ -1,172 +1,443 @@
+use chrono::{DateTime, NaiveDateTime};
+use google_drive3::api;
 use google_drive3::api::Scope;
-use google_drive3::hyper::body::to_bytes;
-use google_drive3::{hyper, hyper_rustls, oauth2, DriveHub, Error};
-use lazy_static::lazy_static;
-use regex::{Captures, Regex};
-use std::env;
-use std::fs;
-use std::io::Write;
-use std::path::Path;
+use google_drive3::hyper_rustls::HttpsConnector;
+use google_drive3::oauth2::authenticator::Authenticator;
+use google_drive3::{hyper, oauth2};
+use reqwest;
+use reqwest::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use soup::prelude::*;
+use std::fmt;
+use std::io::Cursor;
+use std::time::Duration;
 use tokio;
+use url::Url;
 use yup_oauth2;
 
+use super::download_utils;
+use super::error::DownloadError;
+use crate::http::connector::{self, ProxyConnector};
+use crate::http::{HttpClient, HttpConfig};
 use crate::DownloadFiles;
 
-use crate::postgres_orm;
+use crate::postgres_orm::models::NewFileSource;
+use crate::postgres_orm::MetadataStore;
+use crate::source::reddit::RedditPost;
 
-#[derive(Debug)]
-pub struct GoogleFolder {
-    #[allow(dead_code)]
-    file_id: String,
-    #[allow(dead_code)]
-    file_name: String,
+/// Name recorded as `file_source.provider` for archives from this host
+pub(super) const PROVIDER: &str = "google_drive";
+
+/// Serves publicly shared files without credentials
+const PUBLIC_DOWNLOAD_URL: &str = "https://drive.google.com/uc";
+
+const API_FILES_URL: &str = "https://www.googleapis.com/drive/v3/files";
+
+const DRIVE_HOSTS: [&str; 3] = [
+    "drive.google.com",
+    "docs.google.com",
+    "drive.usercontent.google.com",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum DriveItemKind {
+    File,
+    Folder,
 }
 
-#[derive(Debug)]
-pub struct GoogleFile {
-    #[allow(dead_code)]
-    file_id: String,
-    #[allow(dead_code)]
-    file_name: String,
+/// Why a link couldn't be read as a Google Drive file or folder
+#[derive(Debug, PartialEq)]
+pub enum DriveLinkError {
+    NotDrive(String),
+    UnknownShape(String),
+    InvalidId(String),
 }
 
-#[derive(Debug)]
-pub enum GoogleFileType {
-    GoogleFolder(GoogleFolder),
-    GoogleFile(GoogleFile),
+impl fmt::Display for DriveLinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriveLinkError::NotDrive(url) => write!(f, "{} is not a Google Drive link", url),
+            DriveLinkError::UnknownShape(url) => {
+                write!(f, "{} doesn't link to a Google Drive file or folder", url)
+            }
+            DriveLinkError::InvalidId(id) => write!(f, "{} is not a Google Drive id", id),
+        }
+    }
+}
+
+impl std::error::Error for DriveLinkError {}
+
+/// The file or folder a Drive link points at. Links to files shared before
+/// 2021 may also carry a `resourcekey`, without which they can't be opened.
+#[derive(Debug, PartialEq)]
+pub struct DriveLink {
+    pub id: String,
+    pub kind: DriveItemKind,
+    pub resource_key: Option<String>,
+}
+
+impl DriveLink {
+    /// Handles `/file/d/<id>`, `/drive/folders/<id>` (including the
+    /// `/drive/u/<n>/` and `/drive/mobile/` variants), `open?id=`, `uc?id=`
+    /// and `folderview?id=` on drive.google.com and docs.google.com.
+    /// `open?id=` can be either, and is taken to be a file.
+    pub fn parse(url: &str) -> Result<Self, DriveLinkError> {
+        let url = url.trim();
+        let parsed = Url::parse(url).map_err(|_| DriveLinkError::NotDrive(url.to_string()))?;
+        let host = parsed.host_str().unwrap_or_default().to_lowercase();
+        if !DRIVE_HOSTS.contains(&host.as_str()) {
+            return Err(DriveLinkError::NotDrive(url.to_string()));
+        }
+
+        // signed in links put the account index in the path, as `/u/<n>/`
+        let raw_segments: Vec<&str> = parsed
+            .path_segments()
+            .map(|val| val.filter(|val| !val.is_empty()).collect())
+            .unwrap_or_default();
+        let mut segments = Vec::new();
+        let mut i = 0;
+        while i < raw_segments.len() {
+            let is_account = raw_segments[i] == "u"
+                && raw_segments
+                    .get(i + 1)
+                    .is_some_and(|val| val.chars().all(|c| c.is_ascii_digit()));
+            if is_account {
+                i += 2;
+            } else {
+                segments.push(raw_segments[i]);
+                i += 1;
+            }
+        }
+
+        let query = |name: &str| {
+            parsed
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, val)| val.to_string())
+                .filter(|val| !val.is_empty())
+        };
+
+        let (id, kind) = match segments.as_slice() {
+            ["file", "d", id, ..] => (id.to_string(), DriveItemKind::File),
+            ["drive", rest @ ..] => match rest.iter().position(|val| *val == "folders") {
+                Some(pos) if pos + 1 < rest.len() => {
+                    (rest[pos + 1].to_string(), DriveItemKind::Folder)
+                }
+                _ => return Err(DriveLinkError::UnknownShape(url.to_string())),
+            },
+            ["open"] | ["uc"] | ["download"] => (
+                query("id").ok_or_else(|| DriveLinkError::UnknownShape(url.to_string()))?,
+                DriveItemKind::File,
+            ),
+            ["folderview"] | ["embeddedfolderview"] => (
+                query("id").ok_or_else(|| DriveLinkError::UnknownShape(url.to_string()))?,
+                DriveItemKind::Folder,
+            ),
+            _ => return Err(DriveLinkError::UnknownShape(url.to_string())),
+        };
+
+        let is_valid_id = id.len() >= 10
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !is_valid_id {
+            return Err(DriveLinkError::InvalidId(id));
+        }
+
+        Ok(Self {
+            id,
+            kind,
+            resource_key: query("resourcekey"),
+        })
+    }
 }
 
 #[derive(Debug)]
 pub struct GoogleDriveMetadata {
     id: String,
+    resource_key: Option<String>,
     url: String,
-    pub file_metadata: Option<GoogleFileType>,
+    pub kind: DriveItemKind,
     file_path: String,
     out_path: Option<String>,
+    details: Option<FileDetails>,
 }
 
-#[tokio::main]
-pub async fn get_google_drive_connector() -> Result<DriveHub, Error> {
-    let path_to_app_json = match env::var("GOOGLE_APPLICATION_CREDENTIALS") {
-        Ok(val) => val,
-        Err(e) => panic!(
-            "The google application credentials couldn't be read. {} . Exiting.",
-            e
-        ),
-    };
-
-    let secret = yup_oauth2::read_service_account_key(path_to_app_json).await?;
-    let auth_result = oauth2::ServiceAccountAuthenticator::builder(secret)
-        .build()
-        .await;
-
-    let auth = match auth_result {
-        Ok(val) => val,
-        Err(e) => panic!(
-            "Authentication for Google Drive failed with reason: {}. Quitting program.",
-            e
-        ),
-    };
+/// Fields asked of the API for a file before it is downloaded
+const DETAIL_FIELDS: &str = "name,mimeType,size,md5Checksum,modifiedTime";
 
-    let hub = DriveHub::new(
-        hyper::Client::builder().build(
-            hyper_rustls::HttpsConnectorBuilder::new()
-                .with_native_roots()
-                .https_or_http()
-                .enable_http1()
-                .enable_http2()
-                .build(),
-        ),
-        auth,
-    );
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
 
-    Ok(hub)
+/// What Drive knows about a file. Files Drive generates itself (Docs,
+/// Sheets) have no size or checksum.
+#[derive(Debug, Default, PartialEq)]
+struct FileDetails {
+    name: Option<String>,
+    mime_type: Option<String>,
+    size_bytes: Option<i64>,
+    md5_checksum: Option<String>,
+    modified_at: Option<NaiveDateTime>,
 }
 
-impl GoogleDriveMetadata {
-    fn file_or_folder(url: &str) -> &str {
-        if url.contains("folder") {
-            "folder"
-        } else if url.contains("file") {
-            "file"
-        } else {
-            "other"
+impl FileDetails {
+    fn from_api(file: &api::File) -> Self {
+        Self {
+            name: file.name.clone(),
+            mime_type: file.mime_type.clone(),
+            size_bytes: file.size.as_ref().and_then(|val| val.parse().ok()),
+            md5_checksum: file.md5_checksum.clone(),
+            modified_at: file
+                .modified_time
+                .as_ref()
+                .and_then(|val| DateTime::parse_from_rfc3339(val).ok())
+                .map(|val| val.naive_utc()),
         }
     }
 
-    fn get_id(url: &str) -> anyhow::Result<String> {
-        lazy_static! {
-            static ref RE: Regex = Regex::new(
-                "https://drive.google.com/file/d/([a-zA-z0-9-]+)([/view]+)?.?usp=[a-zA-Z]*"
-            )
-            .unwrap();
-            static ref RE_TWO: Regex =
-                Regex::new("https://drive.google.com/drive/folders/([a-zA-z0-9-]+).?usp=[a-zA-Z]*")
-                    .unwrap();
-            static ref RE_THREE: Regex =
-                Regex::new("https://drive.google.com/drive[/a-zA-z/]+?folders/([a-zA-z0-9-]+)")
-                    .unwrap();
-            static ref RE_FOUR: Regex =
-                Regex::new("https://drive.google.com/file/d/([a-zA-z0-9-]+)/view").unwrap();
-            static ref RE_FIVE: Regex =
-                Regex::new("https://drive.google.com/drive/u/[0-9]/folders/([a-zA-z0-9-]+)")
-                    .unwrap();
-            static ref REGEXS: Vec<&'static Regex> =
-                vec![&RE, &RE_TWO, &RE_THREE, &RE_FOUR, &RE_FIVE];
+    /// Fails when Drive has a checksum for the file that the downloaded
+    /// content doesn't match, which means the download was cut short or
+    /// isn't the file at all (an error page, say).
+    fn verify(&self, content: &[u8]) -> anyhow::Result<()> {
+        if let Some(expected) = &self.md5_checksum {
+            let actual = format!("{:x}", md5::compute(content));
+            if !actual.eq_ignore_ascii_case(expected) {
+                anyhow::bail!(
+                    "Downloaded {} bytes with MD5 {}, but Google Drive has {}",
+                    content.len(),
+                    actual,
+                    expected
+                );
+            }
         }
 
-        // set to first regex
-        let mut use_re = &*REGEXS[0];
-        for regex in &*REGEXS {
-            if regex.is_match(url) {
-                use_re = regex;
-            }
+        Ok(())
+    }
+}
+
+/// The Drive API, as the service account at `[providers] google_credentials`
+/// (or `GOOGLE_APPLICATION_CREDENTIALS`). Its tokens come from yup_oauth2
+/// over a connector following the `[http]` settings, and the requests for
+/// files go through the shared client like every other.
+pub struct DriveApi {
+    auth: Authenticator<HttpsConnector<ProxyConnector>>,
+    /// Longest wait for a token
+    read_timeout: Duration,
+}
+
+impl DriveApi {
+    /// An access token for the service account. The authenticator keeps it
+    /// until it is about to run out.
+    async fn token(&self) -> anyhow::Result<String> {
+        let token = tokio::time::timeout(self.read_timeout, self.auth.token(&[Scope::Full]))
+            .await
+            .map_err(|_| {
+                DownloadError::Auth(format!(
+                    "Google sent no token for {} seconds",
+                    self.read_timeout.as_secs()
+                ))
+            })?
+            .map_err(|e| {
+                DownloadError::Auth(format!(
+                    "Authentication for Google Drive failed with reason: {}",
+                    e
+                ))
+            })?;
+
+        Ok(token.as_str().to_string())
+    }
+
+    /// Metadata of the file, as far as `DETAIL_FIELDS` go
+    async fn file(&self, client: &HttpClient, id: &str) -> anyhow::Result<api::File> {
+        let request = client
+            .get(format!("{}/{}", API_FILES_URL, id))
+            .query(&[("fields", DETAIL_FIELDS), ("supportsAllDrives", "true")])
+            .bearer_auth(self.token().await?);
+        let body = client
+            .text(client.send(request).await?.error_for_status()?)
+            .await?;
+
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Content of the file
+    async fn media(&self, client: &HttpClient, id: &str) -> anyhow::Result<Vec<u8>> {
+        let request = client
+            .get(format!("{}/{}", API_FILES_URL, id))
+            .query(&[
+                ("alt", "media"),
+                ("supportsAllDrives", "true"),
+                ("acknowledgeAbuse", "false"),
+            ])
+            .bearer_auth(self.token().await?);
+
+        Ok(client
+            .bytes(client.send(request).await?.error_for_status()?)
+            .await?)
+    }
+}
+
+/// Connects to the Drive API with the service account key at `credentials`.
+/// Without one set there is no connection, and only publicly shared files
+/// can be downloaded.
+#[tokio::main]
+pub async fn get_google_drive_connector(
+    credentials: Option<&str>,
+    config: &HttpConfig,
+) -> Result<Option<DriveApi>, DownloadError> {
+    let path_to_app_json = match credentials {
+        Some(val) => val,
+        None => {
+            warn!("No google application credentials are set, only public Google Drive files will be downloaded");
+            return Ok(None);
         }
+    };
 
-        let regex_func = |cap: Option<Captures>| -> String {
-            if let Some(cap_re) = cap {
-                let id = cap_re.get(1).map_or("", |m| m.as_str());
-                id.to_string()
-            } else {
-                "".to_string()
+    let secret = yup_oauth2::read_service_account_key(path_to_app_json)
+        .await
+        .map_err(|e| {
+            DownloadError::Auth(format!(
+                "Couldn't read the service account key {}: {}",
+                path_to_app_json, e
+            ))
+        })?;
+
+    let connector = connector::https_connector(config)
+        .map_err(|e| DownloadError::Auth(format!("Couldn't connect to Google Drive: {}", e)))?;
+    // every download runs on a runtime of its own, so connections aren't
+    // kept for the next one
+    let client = hyper::Client::builder()
+        .pool_max_idle_per_host(0)
+        .build(connector);
+    let auth = oauth2::ServiceAccountAuthenticator::builder(secret)
+        .hyper_client(client)
+        .build()
+        .await
+        .map_err(|e| {
+            DownloadError::Auth(format!(
+                "Authentication for Google Drive failed with reason: {}",
+                e
+            ))
+        })?;
+
+    Ok(Some(DriveApi {
+        auth,
+        read_timeout: config.read_timeout,
+    }))
+}
+
+/// Where to go from the HTML page `uc?export=download` answers with when
+/// it won't hand over a file straight away. Files too large to be scanned
+/// for viruses get a warning page, which confirms the download either with
+/// a form (newer pages) or a link carrying a `confirm` token (older ones).
+/// Anything else is an error page, such as the download quota running out.
+fn parse_confirm_page(html: &str) -> anyhow::Result<String> {
+    let soup = Soup::new(html);
+
+    if let Some(form) = soup.tag("form").attr("id", "download-form").find() {
+        let action = form
+            .get("action")
+            .ok_or_else(|| anyhow::anyhow!("Google Drive download form has no action"))?;
+        let mut url = Url::parse(PUBLIC_DOWNLOAD_URL)?.join(&action)?;
+        for input in form.tag("input").attr("type", "hidden").find_all() {
+            if let (Some(name), Some(value)) = (input.get("name"), input.get("value")) {
+                url.query_pairs_mut().append_pair(&name, &value);
             }
-        };
+        }
 
-        let captured = regex_func(use_re.captures(url));
-        Ok(captured)
+        return Ok(url.to_string());
     }
 
-    pub fn new(url: &str, title: String, file_path: String) -> Self {
-        //TODO fix unwrap
-        let file_type = Self::file_or_folder(url);
-        let file_id = Self::get_id(url).unwrap();
-
-        let file_metadata = match file_type {
-            "file" => Some(GoogleFileType::GoogleFile(GoogleFile {
-                file_id: file_id.clone(),
-                file_name: title,
-            })),
-            "folder" => Some(GoogleFileType::GoogleFolder(GoogleFolder {
-                file_id: file_id.clone(),
-                file_name: title,
-            })),
-            _ => None,
-        };
+    let confirm_link = soup
+        .tag("a")
+        .find_all()
+        .filter_map(|val| val.get("href"))
+        .find(|val| val.contains("confirm="));
+    if let Some(href) = confirm_link {
+        return Ok(Url::parse(PUBLIC_DOWNLOAD_URL)?.join(&href)?.to_string());
+    }
 
-        Self {
-            id: file_id,
+    let reason = soup
+        .class("uc-error-subcaption")
+        .find()
+        .or_else(|| soup.tag("title").find())
+        .map(|val| val.text().trim().to_string())
+        .unwrap_or_default();
+    let message = format!("Google Drive won't serve the file: {}", reason);
+    Err(if reason.contains("Too many users") {
+        DownloadError::Quota(message)
+    } else {
+        DownloadError::NotFound(message)
+    }
+    .into())
+}
+
+/// Downloads a publicly shared file without the API, going through the
+/// virus scan warning if there is one. Returns the file name the response
+/// gave, if any, along with the content.
+async fn download_public(
+    client: &HttpClient,
+    id: &str,
+    resource_key: Option<&str>,
+) -> anyhow::Result<(Option<String>, Vec<u8>)> {
+    // the warning page sets a cookie the confirmed download checks
+    let mut url = format!("{}?export=download&id={}", PUBLIC_DOWNLOAD_URL, id);
+    if let Some(resource_key) = resource_key {
+        url = format!("{}&resourcekey={}", url, resource_key);
+    }
+
+    // the first request may answer with the warning page, the confirmed one
+    // has to answer with the file
+    for _ in 0..2 {
+        let response = client.send(client.get(&url)).await?.error_for_status()?;
+        let is_html = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|val| val.to_str().ok())
+            .is_some_and(|val| val.starts_with("text/html"));
+
+        if !is_html {
+            let file_name = response
+                .headers()
+                .get(CONTENT_DISPOSITION)
+                .and_then(|val| val.to_str().ok())
+                .and_then(download_utils::content_disposition_file_name);
+            return Ok((file_name, client.bytes(response).await?.to_vec()));
+        }
+
+        url = parse_confirm_page(&client.text(response).await?)?;
+        debug!("Confirming Google Drive download of {} at {}", id, &url);
+    }
+
+    anyhow::bail!("Google Drive kept asking to confirm the download of {}", id)
+}
+
+impl GoogleDriveMetadata {
+    pub fn new(url: &str, file_path: String) -> Result<Self, DriveLinkError> {
+        let link = DriveLink::parse(url)?;
+
+        Ok(Self {
+            id: link.id,
+            resource_key: link.resource_key,
             url: url.to_string(),
-            file_metadata,
+            kind: link.kind,
             file_path,
             out_path: None,
-        }
+            details: None,
+        })
     }
 }
 
-impl DownloadFiles<DriveHub> for GoogleDriveMetadata {
-    fn metadata_to_sql(self, conn: &diesel::PgConnection) -> anyhow::Result<()> {
-        //TODO fix unwrap
-        postgres_orm::create_file_row(conn, &self.url, &self.out_path.unwrap())?;
+impl DownloadFiles<DriveApi> for GoogleDriveMetadata {
+    fn metadata_to_sql(self, store: &dyn MetadataStore, post: &RedditPost) -> anyhow::Result<()> {
+        let out_path = self.out_path.unwrap();
+        let details = self.details.unwrap_or_default();
+
+        store.create_file_row(&NewFileSource {
+            size_bytes: details.size_bytes,
+            mime_type: details.mime_type.as_deref(),
+            md5_checksum: details.md5_checksum.as_deref(),
+            source_modified_at: details.modified_at,
+            ..post.new_file_source(&self.url, &out_path, PROVIDER)
+        })?;
 
         Ok(())
     }
@@ -174,85 +445,92 @@ impl DownloadFiles<DriveHub> for GoogleDriveMetadata {
     #[tokio::main]
     async fn download(
         mut self,
-        hub: Option<&DriveHub>,
-        conn: &diesel::PgConnection,
-    ) -> anyhow::Result<()> {
-        //TODO need to handle here
-        let resp = hub
-            .unwrap()
-            .files()
-            .get(self.id.as_str())
-            .param("alt", "media")
-            .supports_team_drives(true)
-            .supports_all_drives(true)
-            .include_permissions_for_view("published")
-            .acknowledge_abuse(false)
-            .add_scope(Scope::Full)
-            .doit()
-            .await;
-
-        let data_resp = match resp {
-            Ok(val) => Some(val),
-            Err(e) => {
-                warn!(
-                    "Got no response from {} with error response {}. Setting to None",
-                    &self.url, e
-                );
-                None
-            }
-        };
-
+        client: &HttpClient,
+        hub: Option<&DriveApi>,
+        store: &dyn MetadataStore,
+        post: &RedditPost,
+    ) -> anyhow::Result<Option<String>> {
         debug!(
             "Google drive metadata associated with compressed file: {:?}",
             &self
         );
 
-        if let Some((resp, _)) = data_resp {
-            let path_str = format!("{}/{}.zip", &self.file_path, &self.id);
-            let path = Path::new(&path_str);
-            let display = path.display();
-
-            let mut file = match fs::File::create(&path) {
-                Ok(file) => file,
-                Err(e) => panic!("couldn't open {}: {}", display, e),
-            };
-
-            self.out_path = Some(self.id.clone());
-
-            info!(
-                "Name of the compressed file to be saved from google drive: {}",
-                &path_str
-            );
-
-            let new_response = to_bytes(resp.into_body()).await?;
-            file.write_all(&new_response)?;
-
-            info!("Successfully created zip file: {}", &path_str);
-
-            let new_file = fs::File::open(&path)?;
-            let new_archive = zip::ZipArchive::new(new_file);
-            let new_path_str = path_str.clone().replace(".zip", ".rar");
-
-            if new_archive.is_err() {
-                // the file isn't a zip file but it is a RAR file
-                // deletes the created zip file and creates a RAR file
-                warn!(
-                    "Saved zip file {} is actually a rar file. Will save as rar",
-                    &path_str
-                );
-                fs::remove_file(&path_str)?;
-                let mut rar_file = fs::File::create(&new_path_str)?;
+        let mut api_content = None;
+        if let Some(hub) = hub {
+            match hub.file(client, &self.id).await {
+                Ok(file) => {
+                    let details = FileDetails::from_api(&file);
+                    if details.mime_type.as_deref() == Some(FOLDER_MIME_TYPE) {
+                        return Err(DownloadError::Unsupported(format!(
+                            "Google Drive link {} is a folder",
+                            &self.url
+                        ))
+                        .into());
+                    }
+                    self.details = Some(details);
+
+                    match hub.media(client, &self.id).await {
+                        Ok(content) => api_content = Some(content),
+                        Err(e) => warn!(
+                            "Got no response from {} with error response {}. Trying the public download",
+                            &self.url, e
+                        ),
+                    }
+                }
+                // the service account only sees files shared with it, public
+                // ones can still be downloaded without it
+                Err(e) => warn!(
+                    "Got no metadata for {} with error response {}. Trying the public download",
+                    &self.url, e
+                ),
+            }
+        }
 
-                self.out_path = Some(self.id.clone());
+        let (response_name, content) = match api_content {
+            Some(content) => (None, content),
+            None => download_public(client, &self.id, self.resource_key.as_deref()).await?,
+        };
 
-                rar_file.write_all(&new_response)?;
-                info!("Successfully created rar file: {}", &new_path_str);
-            }
+        let mut details = self.details.take().unwrap_or_default();
+        details.verify(&content)?;
+        details.size_bytes = Some(content.len() as i64);
+        details.md5_checksum = Some(format!("{:x}", md5::compute(&content)));
+
+        // kits on Drive often share a name, so the id goes in the name the
+        // API or the download gave, and files without one are named by their
+        // id alone, with the extension sniffed from the content
+        let file_name = details
+            .name
+            .clone()
+            .or(response_name)
+            .map(|val| download_utils::keyed_file_name(&val, &self.id))
+            .unwrap_or_else(|| {
+                if zip::ZipArchive::new(Cursor::new(&content)).is_ok() {
+                    format!("{}.zip", &self.id)
+                } else {
+                    // the file isn't a zip file but it is a RAR file
+                    warn!(
+                        "Google Drive file {} is actually a rar file. Will save as rar",
+                        &self.id
+                    );
+                    format!("{}.rar", &self.id)
+                }
+            });
+        self.details = Some(details);
+
+        info!(
+            "Name of the compressed file to be saved from google drive: {}",
+            &file_name
+        );
+        self.out_path = Some(download_utils::save_download(
+            &self.file_path,
+            &file_name,
+            &content,
+        )?);
 
-            self.metadata_to_sql(conn)?;
-        }
+        self.metadata_to_sql(store, post)?;
 
-        Ok(())
+        Ok(None)
     }
 }
 
@@ -261,46 +539,181 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_file_or_folder() {
-        let test_one_url =
-            "https://drive.google.com/drive/folders/1Ny62TwY-Rgz4cfQDwcdBHL0vtWJgy6DI";
-        let test_two_url = "https://drive.google.com/file/d/1-cgL6_YlB8gOVgoLrwCnP19OqHt34WVj/view";
-        let test_three_url =
-            "https://www.dropbox.com/sh/hkgtorveen2jvh6/AAAf0TStSQD_9PAOTjubPU1Ma?dl=0";
-        let test_four_url =
-            "https://drive.google.com/drive/u/4/folders/1Xw-HoupNY75aYB1Hc0zLifFxu3g5RQGX";
-
-        assert_eq!("folder", GoogleDriveMetadata::file_or_folder(test_one_url));
-        assert_eq!("file", GoogleDriveMetadata::file_or_folder(test_two_url));
-        assert_eq!("other", GoogleDriveMetadata::file_or_folder(test_three_url));
-        assert_eq!("folder", GoogleDriveMetadata::file_or_folder(test_four_url));
+    fn test_parse_link() {
+        let file = |id: &str| DriveLink {
+            id: id.to_string(),
+            kind: DriveItemKind::File,
+            resource_key: None,
+        };
+        let folder = |id: &str| DriveLink {
+            id: id.to_string(),
+            kind: DriveItemKind::Folder,
+            resource_key: None,
+        };
+
+        let cases = [
+            (
+                "https://drive.google.com/drive/folders/1Ny62TwY-Rgz4cfQDwcdBHL0vtWJgy6DI",
+                Ok(folder("1Ny62TwY-Rgz4cfQDwcdBHL0vtWJgy6DI")),
+            ),
+            (
+                "https://drive.google.com/drive/folders/1Ny62TwY-Rgz4cfQDwcdBHL0vtWJgy6DI?usp=sharing",
+                Ok(folder("1Ny62TwY-Rgz4cfQDwcdBHL0vtWJgy6DI")),
+            ),
+            (
+                "https://drive.google.com/drive/u/4/folders/1Xw-HoupNY75aYB1Hc0zLifFxu3g5RQGX",
+                Ok(folder("1Xw-HoupNY75aYB1Hc0zLifFxu3g5RQGX")),
+            ),
+            (
+                "https://drive.google.com/drive/mobile/folders/1Xw-HoupNY75aYB1Hc0zLifFxu3g5RQGX",
+                Ok(folder("1Xw-HoupNY75aYB1Hc0zLifFxu3g5RQGX")),
+            ),
+            (
+                "https://drive.google.com/file/d/1-cgL6_YlB8gOVgoLrwCnP19OqHt34WVj/view",
+                Ok(file("1-cgL6_YlB8gOVgoLrwCnP19OqHt34WVj")),
+            ),
+            (
+                "https://drive.google.com/file/d/1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ/view?usp=sharing",
+                Ok(file("1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ")),
+            ),
+            (
+                "https://drive.google.com/file/d/1fkzvvlllNowwuZOdlAc0A05p5sZvnsuv",
+                Ok(file("1fkzvvlllNowwuZOdlAc0A05p5sZvnsuv")),
+            ),
+            (
+                "https://drive.google.com/u/0/file/d/1fkzvvlllNowwuZOdlAc0A05p5sZvnsuv/edit",
+                Ok(file("1fkzvvlllNowwuZOdlAc0A05p5sZvnsuv")),
+            ),
+            (
+                "https://drive.google.com/open?id=1fkzvvlllNowwuZOdlAc0A05p5sZvnsuv",
+                Ok(file("1fkzvvlllNowwuZOdlAc0A05p5sZvnsuv")),
+            ),
+            (
+                "https://drive.google.com/uc?id=1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ&export=download",
+                Ok(file("1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ")),
+            ),
+            (
+                "https://drive.google.com/u/1/uc?id=1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ&export=download",
+                Ok(file("1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ")),
+            ),
+            (
+                "https://docs.google.com/uc?export=download&id=1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ",
+                Ok(file("1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ")),
+            ),
+            (
+                "https://docs.google.com/file/d/1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ/edit",
+                Ok(file("1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ")),
+            ),
+            (
+                "https://drive.google.com/folderview?id=1Ny62TwY-Rgz4cfQDwcdBHL0vtWJgy6DI",
+                Ok(folder("1Ny62TwY-Rgz4cfQDwcdBHL0vtWJgy6DI")),
+            ),
+            (
+                "https://drive.google.com/file/d/0B7x9kAbCdEfGhIjKlMnOpQrStU/view?resourcekey=0-aBcD_eFgH",
+                Ok(DriveLink {
+                    id: "0B7x9kAbCdEfGhIjKlMnOpQrStU".to_string(),
+                    kind: DriveItemKind::File,
+                    resource_key: Some("0-aBcD_eFgH".to_string()),
+                }),
+            ),
+            (
+                "https://www.dropbox.com/sh/hkgtorveen2jvh6/AAAf0TStSQD_9PAOTjubPU1Ma?dl=0",
+                Err(DriveLinkError::NotDrive(
+                    "https://www.dropbox.com/sh/hkgtorveen2jvh6/AAAf0TStSQD_9PAOTjubPU1Ma?dl=0"
+                        .to_string(),
+                )),
+            ),
+            (
+                "https://drive.google.com/drive/my-drive",
+                Err(DriveLinkError::UnknownShape(
+                    "https://drive.google.com/drive/my-drive".to_string(),
+                )),
+            ),
+            (
+                "https://drive.google.com/open",
+                Err(DriveLinkError::UnknownShape(
+                    "https://drive.google.com/open".to_string(),
+                )),
+            ),
+            (
+                "https://docs.google.com/spreadsheets/d/1abc/edit",
+                Err(DriveLinkError::UnknownShape(
+                    "https://docs.google.com/spreadsheets/d/1abc/edit".to_string(),
+                )),
+            ),
+            (
+                "https://drive.google.com/file/d/1K4f[ar]^/view",
+                Err(DriveLinkError::InvalidId("1K4f[ar]^".to_string())),
+            ),
+            (
+                "not a url",
+                Err(DriveLinkError::NotDrive("not a url".to_string())),
+            ),
+        ];
+
+        for (url, expected) in cases.iter() {
+            assert_eq!(*expected, DriveLink::parse(url), "{}", url);
+        }
     }
 
     #[test]
-    fn test_get_id() {
-        let test_one_url =
-            "https://drive.google.com/drive/folders/1Ny62TwY-Rgz4cfQDwcdBHL0vtWJgy6DI";
-        let test_two_url = "https://drive.google.com/file/d/1-cgL6_YlB8gOVgoLrwCnP19OqHt34WVj/view";
-        let test_three_url =
-            "https://drive.google.com/file/d/1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ/view";
-        let test_four_url =
-            "https://drive.google.com/file/d/1fkzvvlllNowwuZOdlAc0A05p5sZvnsuv/view";
-
+    fn test_parse_confirm_page() {
         assert_eq!(
-            "1Ny62TwY-Rgz4cfQDwcdBHL0vtWJgy6DI",
-            GoogleDriveMetadata::get_id(test_one_url).unwrap()
+            "https://drive.usercontent.google.com/download?id=1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ&export=download&confirm=t&uuid=3f1c9a52-7d4e-4b8a-9e0f-2a6b5c8d1e7f",
+            parse_confirm_page(include_str!(
+                "../../test_samples/fixtures/google_drive/virus_scan_form.html"
+            ))
+            .unwrap()
         );
         assert_eq!(
-            "1-cgL6_YlB8gOVgoLrwCnP19OqHt34WVj",
-            GoogleDriveMetadata::get_id(test_two_url).unwrap()
+            "https://drive.google.com/uc?export=download&confirm=Xk2_&id=1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ",
+            parse_confirm_page(include_str!(
+                "../../test_samples/fixtures/google_drive/virus_scan_link.html"
+            ))
+            .unwrap()
         );
+        assert!(parse_confirm_page(include_str!(
+            "../../test_samples/fixtures/google_drive/quota_exceeded.html"
+        ))
+        .unwrap_err()
+        .to_string()
+        .starts_with(
+            "Google Drive won't serve the file: Too many users have viewed or downloaded"
+        ));
+    }
+
+    #[test]
+    fn test_file_details() {
+        let file = api::File {
+            name: Some("Trap Kit Vol. 3.zip".to_string()),
+            mime_type: Some("application/zip".to_string()),
+            size: Some("34".to_string()),
+            md5_checksum: Some("B2C8A1F0D5E4E0F1B1D0F3C9A2E6C7D8".to_string()),
+            modified_time: Some("2022-03-14T19:22:05.123Z".to_string()),
+            ..Default::default()
+        };
+        let details = FileDetails::from_api(&file);
+
         assert_eq!(
-            "1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ",
-            GoogleDriveMetadata::get_id(test_three_url).unwrap()
+            FileDetails {
+                name: Some("Trap Kit Vol. 3.zip".to_string()),
+                mime_type: Some("application/zip".to_string()),
+                size_bytes: Some(34),
+                md5_checksum: Some("B2C8A1F0D5E4E0F1B1D0F3C9A2E6C7D8".to_string()),
+                modified_at: Some("2022-03-14T19:22:05.123".parse::<NaiveDateTime>().unwrap()),
+            },
+            details
         );
         assert_eq!(
-            "1fkzvvlllNowwuZOdlAc0A05p5sZvnsuv",
-            GoogleDriveMetadata::get_id(test_four_url).unwrap()
+            "Downloaded 4 bytes with MD5 8d777f385d3dfec8815d20f7496026dc, but Google Drive has B2C8A1F0D5E4E0F1B1D0F3C9A2E6C7D8",
+            details.verify(b"data").unwrap_err().to_string()
         );
+
+        let matching = FileDetails {
+            md5_checksum: Some("8D777F385D3DFEC8815D20F7496026DC".to_string()),
+            ..Default::default()
+        };
+        assert!(matching.verify(b"data").is_ok());
+        assert!(FileDetails::default().verify(b"data").is_ok());
     }
 }
@@ -0,0 +1,96 @@
+use clap::ArgEnum;
+
+use super::error::DownloadError;
+
+/// Times a job is tried before it's left as failed, unless
+/// `[providers] max_attempts` says otherwise
+pub const MAX_ATTEMPTS: i32 = 3;
+
+/// A job still marked running after this long was left behind by a crawl
+/// that stopped part way, and is claimed again.
+pub const STALE_AFTER_MINUTES: i64 = 60;
+
+/// Where a link is in the crawl, stored as `download_jobs.status`
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum JobStatus {
+    /// Waiting to be downloaded, or to be tried again
+    Pending,
+    /// Claimed by a crawl that's downloading it
+    Running,
+    Done,
+    /// Gave up on after an error that won't go away by trying again, or
+    /// after running out of attempts
+    Failed,
+    /// No provider handles the link, or it isn't something to download
+    Skipped,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Pending => "pending",
+            JobStatus::Running => "running",
+            JobStatus::Done => "done",
+            JobStatus::Failed => "failed",
+            JobStatus::Skipped => "skipped",
+        }
+    }
+
+    /// Status a job is left in after a failed attempt. Errors that can clear
+    /// up by themselves put the job back in the queue until it runs out of
+    /// attempts; dead links and links that can't be downloaded fail straight
+    /// away.
+    pub fn after_failure(error: &DownloadError, attempts: i32, max_attempts: i32) -> Self {
+        let retryable = matches!(
+            error,
+            DownloadError::Network(_)
+                | DownloadError::Quota(_)
+                | DownloadError::Io(_)
+                | DownloadError::Db(_)
+        );
+
+        if retryable && attempts < max_attempts {
+            JobStatus::Pending
+        } else {
+            JobStatus::Failed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_after_failure() {
+        let cases = [
+            (DownloadError::Network(String::new()), 1, JobStatus::Pending),
+            (DownloadError::Quota(String::new()), 2, JobStatus::Pending),
+            (
+                DownloadError::Network(String::new()),
+                MAX_ATTEMPTS,
+                JobStatus::Failed,
+            ),
+            (DownloadError::NotFound(String::new()), 1, JobStatus::Failed),
+            (
+                DownloadError::Unsupported(String::new()),
+                1,
+                JobStatus::Failed,
+            ),
+            (DownloadError::Auth(String::new()), 1, JobStatus::Failed),
+        ];
+
+        for (error, attempts, expected) in cases.iter() {
+            assert_eq!(
+                *expected,
+                JobStatus::after_failure(error, *attempts, MAX_ATTEMPTS),
+                "{:?}",
+                error
+            );
+        }
+        assert_eq!(
+            JobStatus::Pending,
+            JobStatus::after_failure(&DownloadError::Network(String::new()), MAX_ATTEMPTS, 5)
+        );
+    }
+}
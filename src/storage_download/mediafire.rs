@@ -1,84 +1,347 @@
+use anyhow::Context;
 use reqwest;
-use reqwest::header::USER_AGENT;
+use reqwest::StatusCode;
+use serde::Deserialize;
 use soup::prelude::*;
-use std::fs;
-use std::io::Write;
-use std::path::Path;
+use std::fmt;
 use tokio;
+use url::Url;
 
-use crate::postgres_orm;
+use super::download_utils;
+use crate::http::{HttpClient, HttpError};
+use crate::postgres_orm::MetadataStore;
+use crate::source::reddit::RedditPost;
 use crate::DownloadFiles;
 
+/// Name recorded as `file_source.provider` for archives from this host
+pub(super) const PROVIDER: &str = "mediafire";
+
+const FOLDER_API_URL: &str = "https://www.mediafire.com/api/1.5/folder/get_content.php";
+
+/// Files in a folder are only downloaded when the upload step can extract them
+const ARCHIVE_EXTENSIONS: [&str; 2] = [".zip", ".rar"];
+
+/// Why a Mediafire page or file couldn't be fetched
+#[derive(Debug)]
+pub enum MediafireError {
+    Http(HttpError),
+    Status {
+        url: String,
+        status: StatusCode,
+    },
+    /// The page loaded but had no download button, usually because the file
+    /// was removed
+    NoDownloadButton,
+    NoDownloadLink,
+    NoFileName,
+    FolderApi {
+        result: String,
+        message: String,
+    },
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for MediafireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MediafireError::Http(e) => write!(f, "Mediafire request failed: {}", e),
+            MediafireError::Status { url, status } => {
+                write!(f, "Mediafire returned {} for {}", status, url)
+            }
+            MediafireError::NoDownloadButton => write!(f, "Mediafire page has no download button"),
+            MediafireError::NoDownloadLink => {
+                write!(f, "Mediafire download button has no link")
+            }
+            MediafireError::NoFileName => write!(f, "Mediafire page has no file name"),
+            MediafireError::FolderApi { result, message } => {
+                write!(f, "Mediafire folder api returned {}: {}", result, message)
+            }
+            MediafireError::Json(e) => write!(f, "Mediafire returned unreadable json: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MediafireError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MediafireError::Http(e) => Some(e),
+            MediafireError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<HttpError> for MediafireError {
+    fn from(e: HttpError) -> Self {
+        MediafireError::Http(e)
+    }
+}
+
+impl From<reqwest::Error> for MediafireError {
+    fn from(e: reqwest::Error) -> Self {
+        MediafireError::Http(HttpError::Request(e))
+    }
+}
+
+impl From<serde_json::Error> for MediafireError {
+    fn from(e: serde_json::Error) -> Self {
+        MediafireError::Json(e)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum MediafireLink {
+    /// A file page, which has the download button on it
+    File,
+    Folder {
+        key: String,
+    },
+}
+
+/// Folders are linked as `/folder/<key>/<name>` on both `www.` and `app.`,
+/// everything else (`/file/`, `/download/`, `/?<key>`) is a file page.
+fn parse_link(url: &str) -> MediafireLink {
+    let folder_key = Url::parse(url.trim()).ok().and_then(|parsed| {
+        let mut segments = parsed.path_segments()?;
+        match (segments.next(), segments.next()) {
+            (Some("folder"), Some(key)) if !key.is_empty() => Some(key.to_string()),
+            _ => None,
+        }
+    });
+
+    match folder_key {
+        Some(key) => MediafireLink::Folder { key },
+        None => MediafireLink::File,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct FilePage {
+    file_name: String,
+    download_url: String,
+}
+
+/// Reads the download button off a file page. Older pages link the file
+/// straight from `a.popsok`, newer ones leave the href empty and keep the
+/// url base64 encoded in `data-scrambled-url`.
+fn parse_file_page(html: &str) -> Result<FilePage, MediafireError> {
+    let soup = Soup::new(html);
+
+    let button = soup
+        .tag("a")
+        .find_all()
+        .find(|val| {
+            val.get("id").as_deref() == Some("downloadButton")
+                || val
+                    .get("class")
+                    .is_some_and(|class| class.split_whitespace().any(|c| c == "popsok"))
+        })
+        .ok_or(MediafireError::NoDownloadButton)?;
+
+    let scrambled = button.get("data-scrambled-url").and_then(|val| {
+        base64::decode(val.trim())
+            .ok()
+            .and_then(|val| String::from_utf8(val).ok())
+    });
+    let download_url = scrambled
+        .or_else(|| button.get("href"))
+        .filter(|val| val.starts_with("http"))
+        .ok_or(MediafireError::NoDownloadLink)?;
+
+    let file_name = soup
+        .tag("div")
+        .attr("class", "filename")
+        .find()
+        .map(|val| val.text())
+        .or_else(|| {
+            soup.tag("div")
+                .find_all()
+                .find(|val| {
+                    val.get("class")
+                        .is_some_and(|class| class.split_whitespace().any(|c| c == "dl-btn-label"))
+                })
+                .and_then(|val| val.get("title"))
+        })
+        .map(|val| val.trim().to_string())
+        .filter(|val| !val.is_empty())
+        .or_else(|| {
+            download_url
+                .rsplit('/')
+                .next()
+                .map(|val| val.replace('+', " "))
+        })
+        .ok_or(MediafireError::NoFileName)?;
+
+    Ok(FilePage {
+        file_name,
+        download_url,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+struct FolderFileLinks {
+    normal_download: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct FolderFile {
+    filename: String,
+    links: FolderFileLinks,
+}
+
+#[derive(Deserialize, Debug)]
+struct Subfolder {
+    folderkey: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct FolderContent {
+    #[serde(default)]
+    files: Vec<FolderFile>,
+    #[serde(default)]
+    folders: Vec<Subfolder>,
+    more_chunks: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct FolderResponse {
+    result: String,
+    message: Option<String>,
+    folder_content: Option<FolderContent>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FolderApiResponse {
+    response: FolderResponse,
+}
+
+fn parse_folder_content(body: &str) -> Result<FolderContent, MediafireError> {
+    let response: FolderApiResponse = serde_json::from_str(body)?;
+    let response = response.response;
+
+    match response.folder_content {
+        Some(content) if response.result == "Success" => Ok(content),
+        _ => Err(MediafireError::FolderApi {
+            result: response.result,
+            message: response.message.unwrap_or_default(),
+        }),
+    }
+}
+
+/// A Mediafire link, which isn't fetched until it's downloaded.
 #[derive(Debug)]
 pub struct MediaFireMetadata {
     url: String,
-    raw_html: String,
     file_path: String,
     out_path: Option<String>,
 }
 
 impl MediaFireMetadata {
     pub fn new(url: String, file_path: String) -> Self {
-        //TODO remove unwrap
-        let raw_html = Self::set_html(&url).unwrap();
         Self {
             url,
-            raw_html,
             file_path,
             out_path: None,
         }
     }
+}
 
-    #[tokio::main]
-    async fn set_html(url: &str) -> anyhow::Result<String> {
-        let client = reqwest::Client::builder().build()?;
-        info!("Getting HTML from Mediafire url: {}", &url);
-
-        let response = client
-            .get(url)
-            .header(
-                USER_AGENT,
-                "Mozilla/5.0 (X11; Ubuntu; Linux x86_64; rv:52.0) Gecko/20100101 Firefox/52.0",
-            )
-            .header("Access-Control-Max-Age", "3600")
-            .header("Access-Control-Allow-Headers", "Content-Type")
-            .header("Access-Control-Allow-Methods", "GET")
-            .header("Access-Control-Allow-Origin", "*")
-            .send()
-            .await?
-            .text()
-            .await?;
-
-        Ok(response)
-    }
-
-    fn get_file_name(&self) -> Option<String> {
-        let soup = Soup::new(&self.raw_html);
-
-        soup.tag("div")
-            .attr("class", "filename")
-            .find()
-            .map(|val| val.text())
-    }
-
-    fn get_download_url(&self) -> Option<String> {
-        let soup = Soup::new(&self.raw_html);
-
-        let find_url = soup.tag("a").attr("class", "popsok").find();
-
-        match find_url {
-            Some(val) => val.get("href"),
-            None => {
-                warn!("Mediafire url {} is invalid", &self.url);
-                None
+async fn get_html(client: &HttpClient, url: &str) -> Result<String, MediafireError> {
+    info!("Getting HTML from Mediafire url: {}", &url);
+
+    let response = client.send(client.get(url)).await?;
+    if !response.status().is_success() {
+        return Err(MediafireError::Status {
+            url: url.to_string(),
+            status: response.status(),
+        });
+    }
+
+    Ok(client.text(response).await?)
+}
+
+/// Every file in a folder and the folders below it, a chunk of the listing
+/// at a time.
+async fn list_folder(client: &HttpClient, key: &str) -> Result<Vec<FolderFile>, MediafireError> {
+    let mut files = Vec::new();
+    let mut pending = vec![key.to_string()];
+
+    while let Some(folder_key) = pending.pop() {
+        for content_type in ["files", "folders"].iter() {
+            let mut chunk = 1;
+            loop {
+                let body = client
+                    .text(
+                        client
+                            .send(client.get(FOLDER_API_URL).query(&[
+                                ("folder_key", folder_key.as_str()),
+                                ("content_type", content_type),
+                                ("chunk", &chunk.to_string()),
+                                ("response_format", "json"),
+                            ]))
+                            .await?,
+                    )
+                    .await?;
+                let content = parse_folder_content(&body)?;
+
+                files.extend(content.files);
+                pending.extend(content.folders.into_iter().map(|val| val.folderkey));
+                if content.more_chunks != "yes" {
+                    break;
+                }
+                chunk += 1;
             }
         }
     }
+
+    Ok(files)
+}
+
+/// Loads a file page and downloads the file its button links to.
+async fn fetch_file(
+    client: &HttpClient,
+    page_url: &str,
+) -> Result<(FilePage, Vec<u8>), MediafireError> {
+    let html = get_html(client, page_url).await?;
+    let page = parse_file_page(&html)?;
+
+    let response = client.send(client.get(&page.download_url)).await?;
+    if !response.status().is_success() {
+        return Err(MediafireError::Status {
+            url: page.download_url,
+            status: response.status(),
+        });
+    }
+    let content = client.bytes(response).await?.to_vec();
+
+    Ok((page, content))
+}
+
+/// Downloads one file of a folder and records it against its own page
+async fn save_folder_file(
+    client: &HttpClient,
+    folder: &str,
+    store: &dyn MetadataStore,
+    post: &RedditPost,
+    page_url: &str,
+) -> anyhow::Result<()> {
+    let (page, content) = fetch_file(client, page_url)
+        .await
+        .with_context(|| format!("couldn't download Mediafire file {}", page_url))?;
+    let out_path = download_utils::save_download(folder, &page.file_name, &content)?;
+
+    store.create_file_row(&post.new_file_source(page_url, &out_path, PROVIDER))?;
+
+    Ok(())
 }
 
 impl DownloadFiles<String> for MediaFireMetadata {
-    fn metadata_to_sql(self, conn: &diesel::PgConnection) -> anyhow::Result<()> {
-        postgres_orm::create_file_row(conn, &self.url, &self.out_path.unwrap())?;
+    fn metadata_to_sql(self, store: &dyn MetadataStore, post: &RedditPost) -> anyhow::Result<()> {
+        store.create_file_row(&post.new_file_source(
+            &self.url,
+            &self.out_path.unwrap(),
+            PROVIDER,
+        ))?;
 
         Ok(())
     }
@@ -86,35 +349,192 @@ impl DownloadFiles<String> for MediaFireMetadata {
     #[tokio::main]
     async fn download(
         mut self,
+        client: &HttpClient,
         _resp: Option<&String>,
-        conn: &diesel::PgConnection,
-    ) -> anyhow::Result<()> {
-        let resp_download_url = self.get_download_url();
-        let resp_file_name = self.get_file_name();
-
-        if let (Some(download_url), Some(original_file_name)) = (resp_download_url, resp_file_name)
-        {
-            let file_name = format!("{}/{}", &self.file_path, &original_file_name);
-            let path_str = &file_name;
-
-            let path = Path::new(path_str);
-            let mut file = match fs::File::create(&path) {
-                Ok(file) => file,
-                Err(e) => panic!("Couldn't open {}", e),
-            };
-
-            let resp_content = reqwest::get(&download_url).await?.bytes().await?;
-
-            file.write_all(&resp_content)?;
-            self.out_path = Some(
-                original_file_name
-                    .clone()
-                    .replace(".zip", "")
-                    .replace(".rar", ""),
-            );
+        store: &dyn MetadataStore,
+        post: &RedditPost,
+    ) -> anyhow::Result<Option<String>> {
+        match parse_link(&self.url) {
+            MediafireLink::File => {
+                let (page, content) = fetch_file(client, &self.url)
+                    .await
+                    .with_context(|| format!("couldn't download Mediafire file {}", &self.url))?;
+
+                self.out_path = Some(download_utils::save_download(
+                    &self.file_path,
+                    &page.file_name,
+                    &content,
+                )?);
+
+                self.metadata_to_sql(store, post)?;
+            }
+            // each archive in a folder is its own kit, recorded against the
+            // page it was downloaded from. One that fails doesn't stop the
+            // rest, and the ones already recorded are left alone when the job
+            // is tried again.
+            MediafireLink::Folder { key } => {
+                let files = list_folder(client, &key)
+                    .await
+                    .with_context(|| format!("couldn't list Mediafire folder {}", &self.url))?;
+                info!("Mediafire folder {} has {} files", &self.url, files.len());
+
+                let mut archives = 0;
+                let mut failures = Vec::new();
+                for file in files {
+                    let is_archive = ARCHIVE_EXTENSIONS
+                        .iter()
+                        .any(|val| file.filename.to_lowercase().ends_with(val));
+                    if !is_archive {
+                        debug!(
+                            "Skipping {} in Mediafire folder {}",
+                            &file.filename, &self.url
+                        );
+                        continue;
+                    }
+
+                    let page_url = &file.links.normal_download;
+                    if store.find_file_source_by_url(page_url)?.is_some() {
+                        debug!("{} was downloaded by an earlier attempt", page_url);
+                        continue;
+                    }
 
-            self.metadata_to_sql(conn)?;
+                    archives += 1;
+                    if let Err(e) =
+                        save_folder_file(client, &self.file_path, store, post, page_url).await
+                    {
+                        warn!("{:#}", e);
+                        failures.push(e);
+                    }
+                }
+
+                let failed = failures.len();
+                if let Some(first) = failures.into_iter().next() {
+                    return Err(first.context(format!(
+                        "{} of the {} archives left in Mediafire folder {} couldn't be downloaded",
+                        failed, archives, &self.url
+                    )));
+                }
+                // nothing was downloaded, so the kit isn't counted as one
+                if archives == 0 {
+                    return Ok(Some(format!(
+                        "Mediafire folder {} has no archives left to download",
+                        &self.url
+                    )));
+                }
+            }
         }
-        Ok(())
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_link() {
+        let folder = MediafireLink::Folder {
+            key: "q8w7e6r5t4y3u".to_string(),
+        };
+        let cases = [
+            (
+                "https://www.mediafire.com/file/k2lq0bm5l8xz0c7/Kit.zip/file",
+                MediafireLink::File,
+            ),
+            (
+                "http://www.mediafire.com/?k2lq0bm5l8xz0c7",
+                MediafireLink::File,
+            ),
+            (
+                "https://www.mediafire.com/download/k2lq0bm5l8xz0c7",
+                MediafireLink::File,
+            ),
+            (
+                "https://www.mediafire.com/folder/q8w7e6r5t4y3u/Drum+Kits",
+                folder,
+            ),
+        ];
+
+        for (url, expected) in cases.iter() {
+            assert_eq!(*expected, parse_link(url), "{}", url);
+        }
+        assert_eq!(
+            MediafireLink::Folder {
+                key: "q8w7e6r5t4y3u".to_string()
+            },
+            parse_link("https://app.mediafire.com/folder/q8w7e6r5t4y3u")
+        );
+    }
+
+    #[test]
+    fn test_parse_file_page() {
+        let cases = [
+            (
+                include_str!("../../test_samples/fixtures/mediafire/file_page.html"),
+                "808 Kit.zip",
+                "https://download1507.mediafire.com/t8e9abcd/k2lq0bm5l8xz0c7/808+Kit.zip",
+            ),
+            (
+                include_str!("../../test_samples/fixtures/mediafire/file_page_scrambled.html"),
+                "Lofi Drum Kit.zip",
+                "https://download2390.mediafire.com/k2lq0bm5abcd/k2lq0bm5l8xz0c7/Lofi+Drum+Kit.zip",
+            ),
+        ];
+
+        for (html, file_name, download_url) in cases.iter() {
+            assert_eq!(
+                FilePage {
+                    file_name: file_name.to_string(),
+                    download_url: download_url.to_string(),
+                },
+                parse_file_page(html).unwrap()
+            );
+        }
+        assert!(matches!(
+            parse_file_page(include_str!(
+                "../../test_samples/fixtures/mediafire/file_removed.html"
+            )),
+            Err(MediafireError::NoDownloadButton)
+        ));
+        assert!(matches!(
+            parse_file_page(r#"<a class="popsok" href="javascript:void(0)">Download</a>"#),
+            Err(MediafireError::NoDownloadLink)
+        ));
+    }
+
+    #[test]
+    fn test_parse_folder_content() {
+        let files = parse_folder_content(include_str!(
+            "../../test_samples/fixtures/mediafire/folder_files.json"
+        ))
+        .unwrap();
+        let folders = parse_folder_content(include_str!(
+            "../../test_samples/fixtures/mediafire/folder_folders.json"
+        ))
+        .unwrap();
+        let not_found = parse_folder_content(include_str!(
+            "../../test_samples/fixtures/mediafire/folder_not_found.json"
+        ))
+        .unwrap_err();
+
+        assert_eq!(
+            vec!["808 Kit.zip", "cover.png"],
+            files
+                .files
+                .iter()
+                .map(|val| val.filename.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            "https://www.mediafire.com/file/k2lq0bm5l8xz0c7/808_Kit.zip/file",
+            files.files[0].links.normal_download
+        );
+        assert_eq!("no", files.more_chunks);
+        assert_eq!("z1x2c3v4b5n6m", folders.folders[0].folderkey);
+        assert_eq!(
+            "Mediafire folder api returned Error: Unknown or invalid FolderKey",
+            not_found.to_string()
+        );
     }
 }
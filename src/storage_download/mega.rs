@@ -0,0 +1,354 @@
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, NewBlockCipher};
+use aes::Aes128;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Deserialize;
+use tokio;
+
+use super::download_utils;
+use super::error::DownloadError;
+use crate::http::HttpClient;
+use crate::postgres_orm::MetadataStore;
+use crate::source::reddit::RedditPost;
+use crate::DownloadFiles;
+
+/// Name recorded as `file_source.provider` for archives from this host
+pub(super) const PROVIDER: &str = "mega";
+
+const API_URL: &str = "https://g.api.mega.co.nz/cs";
+
+/// A MEGA link is the node handle plus the key it is encrypted with, which
+/// only ever appears in the URL fragment and never reaches MEGA's servers.
+#[derive(Debug, PartialEq)]
+pub enum MegaLink {
+    File { id: String, key: String },
+    Folder { id: String, key: String },
+}
+
+impl MegaLink {
+    /// Parses both the current `/file/<id>#<key>` links and the older
+    /// `#!<id>!<key>` ones (`#F!` for folders).
+    pub fn parse(url: &str) -> anyhow::Result<Self> {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(
+                r"mega(?:\.co)?\.nz/(?:(file|folder)/([\w-]+)#([\w-]+)|#(F?)!([\w-]+)!([\w-]+))"
+            )
+            .unwrap();
+        }
+
+        let caps = RE
+            .captures(url)
+            .ok_or_else(|| anyhow::anyhow!("{} is not a MEGA file or folder link", url))?;
+
+        let (is_folder, id, key) = match (caps.get(1), caps.get(5)) {
+            (Some(kind), _) => (kind.as_str() == "folder", &caps[2], &caps[3]),
+            (None, Some(_)) => (&caps[4] == "F", &caps[5], &caps[6]),
+            _ => anyhow::bail!("{} is not a MEGA file or folder link", url),
+        };
+
+        let (id, key) = (id.to_string(), key.to_string());
+        Ok(if is_folder {
+            MegaLink::Folder { id, key }
+        } else {
+            MegaLink::File { id, key }
+        })
+    }
+}
+
+/// AES key and CTR nonce of a file, unpacked from the 256 bit key in its link.
+#[derive(Debug, PartialEq)]
+struct FileKey {
+    aes_key: [u8; 16],
+    nonce: [u8; 8],
+}
+
+impl FileKey {
+    fn from_link_key(key: &str) -> anyhow::Result<Self> {
+        let raw = base64::decode_config(key, base64::URL_SAFE_NO_PAD)
+            .map_err(|e| anyhow::anyhow!("MEGA file key {} isn't valid base64: {}", key, e))?;
+        if raw.len() != 32 {
+            anyhow::bail!("MEGA file key is {} bytes rather than 32", raw.len());
+        }
+
+        let mut aes_key = [0u8; 16];
+        for (i, byte) in aes_key.iter_mut().enumerate() {
+            *byte = raw[i] ^ raw[i + 16];
+        }
+        let mut nonce = [0u8; 8];
+        nonce.copy_from_slice(&raw[16..24]);
+
+        Ok(Self { aes_key, nonce })
+    }
+
+    /// File attributes are AES-CBC encrypted with a zero IV, as
+    /// `MEGA{"n":"<name>",...}` padded with null bytes.
+    fn decrypt_attributes(&self, attributes: &str) -> anyhow::Result<MegaAttributes> {
+        let mut data = base64::decode_config(attributes, base64::URL_SAFE_NO_PAD)?;
+        if data.len() % 16 != 0 {
+            anyhow::bail!("MEGA attributes aren't a whole number of AES blocks");
+        }
+
+        let cipher = Aes128::new(GenericArray::from_slice(&self.aes_key));
+        let mut previous = [0u8; 16];
+        for block in data.chunks_mut(16) {
+            let mut current = [0u8; 16];
+            current.copy_from_slice(block);
+            cipher.decrypt_block(GenericArray::from_mut_slice(block));
+            block.iter_mut().zip(&previous).for_each(|(a, b)| *a ^= b);
+            previous = current;
+        }
+
+        let text = String::from_utf8_lossy(&data);
+        let json = text
+            .strip_prefix("MEGA")
+            .ok_or_else(|| anyhow::anyhow!("MEGA attributes didn't decrypt, the key is wrong"))?
+            .trim_end_matches('\0');
+
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// File contents are AES-CTR encrypted, the counter starting at zero
+    /// after the nonce.
+    fn decrypt_content(&self, content: &mut [u8]) {
+        let cipher = Aes128::new(GenericArray::from_slice(&self.aes_key));
+
+        for (counter, chunk) in content.chunks_mut(16).enumerate() {
+            let mut block = [0u8; 16];
+            block[..8].copy_from_slice(&self.nonce);
+            block[8..].copy_from_slice(&(counter as u64).to_be_bytes());
+            let mut keystream = GenericArray::from(block);
+            cipher.encrypt_block(&mut keystream);
+            chunk.iter_mut().zip(&keystream).for_each(|(a, b)| *a ^= b);
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct MegaAttributes {
+    #[serde(rename = "n")]
+    name: String,
+}
+
+/// Response to a `g` (get download url) API command.
+#[derive(Deserialize, Debug, PartialEq)]
+struct MegaFileInfo {
+    #[serde(rename = "s")]
+    size: u64,
+    #[serde(rename = "at")]
+    attributes: String,
+    #[serde(rename = "g")]
+    download_url: String,
+}
+
+fn api_error(code: i64) -> &'static str {
+    match code {
+        -2 => "bad arguments",
+        -9 => "file not found",
+        -11 => "access denied",
+        -16 => "file taken down",
+        -17 | -24 => "transfer quota exceeded",
+        -18 => "temporarily unavailable",
+        _ => "unknown error",
+    }
+}
+
+/// The API answers with an array of results, one per command, where a
+/// negative number in place of an object is an error code.
+fn parse_file_info(body: &str) -> anyhow::Result<MegaFileInfo> {
+    let value: serde_json::Value = serde_json::from_str(body)?;
+    let result = match &value {
+        serde_json::Value::Array(results) => results
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("MEGA returned no results"))?,
+        other => other,
+    };
+
+    if let Some(code) = result.as_i64() {
+        let message = format!("MEGA returned error {} ({})", code, api_error(code));
+        return Err(match code {
+            -9 | -16 => DownloadError::NotFound(message),
+            -11 => DownloadError::Auth(message),
+            -17 | -24 => DownloadError::Quota(message),
+            _ => DownloadError::Network(message),
+        }
+        .into());
+    }
+
+    Ok(serde_json::from_value(result.clone())?)
+}
+
+#[derive(Debug)]
+pub struct MegaMetadata {
+    url: String,
+    file_path: String,
+    out_path: Option<String>,
+}
+
+impl MegaMetadata {
+    pub fn new(url: String, file_path: String) -> Self {
+        Self {
+            url,
+            file_path,
+            out_path: None,
+        }
+    }
+}
+
+impl DownloadFiles<String> for MegaMetadata {
+    fn metadata_to_sql(self, store: &dyn MetadataStore, post: &RedditPost) -> anyhow::Result<()> {
+        store.create_file_row(&post.new_file_source(
+            &self.url,
+            &self.out_path.unwrap(),
+            PROVIDER,
+        ))?;
+
+        Ok(())
+    }
+
+    #[tokio::main]
+    async fn download(
+        mut self,
+        client: &HttpClient,
+        _hub: Option<&String>,
+        store: &dyn MetadataStore,
+        post: &RedditPost,
+    ) -> anyhow::Result<Option<String>> {
+        let (id, key) = match MegaLink::parse(&self.url)? {
+            MegaLink::File { id, key } => (id, key),
+            // folder nodes each have their own key, encrypted with the folder's
+            MegaLink::Folder { .. } => {
+                return Err(DownloadError::Unsupported(format!(
+                    "MEGA folder links aren't supported yet: {}",
+                    &self.url
+                ))
+                .into())
+            }
+        };
+        let file_key = FileKey::from_link_key(&key)?;
+        let body = client
+            .text(
+                client
+                    .send(
+                        client
+                            .post(API_URL)
+                            .query(&[("id", "0")])
+                            .json(&serde_json::json!([{ "a": "g", "g": 1, "p": id }])),
+                    )
+                    .await?,
+            )
+            .await?;
+        let file_info = parse_file_info(&body)?;
+        let attributes = file_key.decrypt_attributes(&file_info.attributes)?;
+
+        info!(
+            "Downloading {} ({} bytes) from MEGA url: {}",
+            &attributes.name, file_info.size, &self.url
+        );
+
+        let mut content = client
+            .bytes(
+                client
+                    .send(client.get(&file_info.download_url))
+                    .await?
+                    .error_for_status()?,
+            )
+            .await?
+            .to_vec();
+        file_key.decrypt_content(&mut content);
+
+        // re-uploads of a kit keep its name but get a new handle
+        self.out_path = Some(download_utils::save_download(
+            &self.file_path,
+            &download_utils::keyed_file_name(&attributes.name, &id),
+            &content,
+        )?);
+
+        self.metadata_to_sql(store, post)?;
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "AQIDBAUGBwgJCgsMDQ4PEBESExQVFhcYGRobHB0eHyA";
+
+    #[test]
+    fn test_parse_link() {
+        let file = MegaLink::File {
+            id: "Xq3lCz8k".to_string(),
+            key: KEY.to_string(),
+        };
+
+        assert_eq!(
+            file,
+            MegaLink::parse(&format!("https://mega.nz/file/Xq3lCz8k#{}", KEY)).unwrap()
+        );
+        assert_eq!(
+            file,
+            MegaLink::parse(&format!("https://mega.nz/#!Xq3lCz8k!{}", KEY)).unwrap()
+        );
+        assert_eq!(
+            file,
+            MegaLink::parse(&format!("https://mega.co.nz/#!Xq3lCz8k!{}", KEY)).unwrap()
+        );
+        assert_eq!(
+            MegaLink::Folder {
+                id: "b9lBTa7J".to_string(),
+                key: "hx3gH7Q3hs_PCsTEbjVjcw".to_string(),
+            },
+            MegaLink::parse("https://mega.nz/folder/b9lBTa7J#hx3gH7Q3hs_PCsTEbjVjcw").unwrap()
+        );
+        assert!(matches!(
+            MegaLink::parse("https://mega.nz/#F!b9lBTa7J!hx3gH7Q3hs_PCsTEbjVjcw").unwrap(),
+            MegaLink::Folder { .. }
+        ));
+        assert!(MegaLink::parse("https://mega.nz/file/Xq3lCz8k").is_err());
+    }
+
+    #[test]
+    fn test_parse_file_info() {
+        let file_info = parse_file_info(include_str!(
+            "../../test_samples/fixtures/mega/file_info.json"
+        ))
+        .unwrap();
+        let error = parse_file_info(include_str!(
+            "../../test_samples/fixtures/mega/not_found.json"
+        ))
+        .unwrap_err();
+
+        assert_eq!(34, file_info.size);
+        assert_eq!(
+            "https://gfs270n171.userstorage.mega.co.nz/dl/Xq3lCz8k0qF2SgJ1b7dJ5lH9mY4",
+            file_info.download_url
+        );
+        assert_eq!("MEGA returned error -9 (file not found)", error.to_string());
+    }
+
+    #[test]
+    fn test_decrypt() {
+        let file_key = FileKey::from_link_key(KEY).unwrap();
+        let file_info = parse_file_info(include_str!(
+            "../../test_samples/fixtures/mega/file_info.json"
+        ))
+        .unwrap();
+        let mut content = include_bytes!("../../test_samples/fixtures/mega/content.bin").to_vec();
+        file_key.decrypt_content(&mut content);
+
+        assert_eq!(
+            "Trap Drum Kit Vol. 2.zip",
+            file_key
+                .decrypt_attributes(&file_info.attributes)
+                .unwrap()
+                .name
+        );
+        assert_eq!(
+            b"PK\x03\x04 pretend this is a zip archive".to_vec(),
+            content
+        );
+        assert!(FileKey::from_link_key("AQID").is_err());
+    }
+}
@@ -1,22 +1,49 @@
+pub mod box_com;
 pub mod download_utils;
 pub mod dropbox;
+pub mod error;
 pub mod google_drive;
+pub mod jobs;
 pub mod mediafire;
+pub mod mega;
+pub mod onedrive;
+pub mod pcloud;
+pub mod plan;
+pub mod registry;
+pub mod resolver;
+pub mod summary;
+pub mod wetransfer;
 
-use crate::DropboxMetadata;
-use crate::GoogleDriveMetadata;
-use crate::MediaFireMetadata;
+use self::box_com::BoxMetadata;
+use self::dropbox::DropboxMetadata;
+use self::google_drive::{DriveItemKind, GoogleDriveMetadata};
+use self::mediafire::MediaFireMetadata;
+use self::mega::MegaMetadata;
+use self::onedrive::OneDriveMetadata;
+use self::pcloud::PCloudMetadata;
+use self::wetransfer::WeTransferMetadata;
 
-use diesel::pg::PgConnection;
+use crate::http::HttpClient;
+use crate::postgres_orm::MetadataStore;
+use crate::source::reddit::RedditPost;
 
 pub trait DownloadFiles<T> {
+    /// Downloads the kit and records it. Returns why nothing was downloaded
+    /// when the link turns out to hold nothing to download, such as a folder
+    /// without archives.
     fn download(
         self,
+        client: &HttpClient,
         hub_conn: Option<&T>,
-        conn: &PgConnection,
-    ) -> anyhow::Result<(), anyhow::Error>;
+        store: &dyn MetadataStore,
+        post: &RedditPost,
+    ) -> anyhow::Result<Option<String>, anyhow::Error>;
 
-    fn metadata_to_sql(self, conn: &PgConnection) -> anyhow::Result<(), anyhow::Error>;
+    fn metadata_to_sql(
+        self,
+        store: &dyn MetadataStore,
+        post: &RedditPost,
+    ) -> anyhow::Result<(), anyhow::Error>;
 }
 
 #[derive(Debug)]
@@ -24,10 +51,42 @@ pub enum DownloadOptions {
     GoogleDrive(GoogleDriveMetadata),
     Dropbox(DropboxMetadata),
     Mediafire(MediaFireMetadata),
+    Mega(MegaMetadata),
+    WeTransfer(WeTransferMetadata),
+    OneDrive(OneDriveMetadata),
+    Box(BoxMetadata),
+    PCloud(PCloudMetadata),
 }
 
-#[derive(Debug)]
-pub struct AssocDataForDownload<DownloadOptions, V> {
-    pub download: DownloadOptions,
-    pub website_metadata: V,
+impl DownloadOptions {
+    /// Why a link a provider handles still isn't downloaded
+    pub fn skip_reason(&self) -> Option<&'static str> {
+        match self {
+            DownloadOptions::GoogleDrive(val) => match val.kind {
+                DriveItemKind::File => None,
+                DriveItemKind::Folder => Some("Google Drive folders aren't downloaded"),
+            },
+            _ => None,
+        }
+    }
+
+    /// Name the archive would be saved under, when the link alone tells.
+    /// Most hosts only give the name once the download starts, and a name
+    /// given then wins over this one.
+    pub fn planned_file_name(&self, url: &str) -> Option<String> {
+        let name = match self {
+            DownloadOptions::Dropbox(val) => val.planned_file_name(),
+            // named from their APIs, with an id added that the link doesn't
+            // always carry
+            DownloadOptions::GoogleDrive(_)
+            | DownloadOptions::Mega(_)
+            | DownloadOptions::WeTransfer(_)
+            | DownloadOptions::OneDrive(_)
+            | DownloadOptions::Box(_)
+            | DownloadOptions::PCloud(_) => None,
+            DownloadOptions::Mediafire(_) => download_utils::archive_name_in_url(url),
+        };
+
+        name.map(|val| download_utils::saved_file_name(&val))
+    }
 }
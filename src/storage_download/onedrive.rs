@@ -0,0 +1,196 @@
+use serde::Deserialize;
+use tokio;
+
+use super::download_utils;
+use super::error::DownloadError;
+use crate::http::HttpClient;
+use crate::postgres_orm::MetadataStore;
+use crate::source::reddit::RedditPost;
+use crate::DownloadFiles;
+
+/// Name recorded as `file_source.provider` for archives from this host
+pub(super) const PROVIDER: &str = "onedrive";
+
+const API_URL: &str = "https://api.onedrive.com/v1.0/shares";
+
+/// The shares API takes the sharing url itself as an id, base64url encoded
+/// without padding and prefixed with `u!`. This works the same for `1drv.ms`
+/// short links and full `onedrive.live.com` ones.
+fn share_id(url: &str) -> String {
+    format!(
+        "u!{}",
+        base64::encode_config(url.trim(), base64::URL_SAFE_NO_PAD)
+    )
+}
+
+#[derive(Deserialize, Debug)]
+struct DriveItem {
+    id: String,
+    name: String,
+    size: Option<u64>,
+    #[serde(rename = "@content.downloadUrl")]
+    download_url: Option<String>,
+    folder: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiError {
+    code: String,
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum SharesResponse {
+    Error { error: ApiError },
+    Item(DriveItem),
+}
+
+/// Item id, name and download url of a shared file. Shared folders can only
+/// be downloaded one file at a time, so they are turned down.
+fn parse_drive_item(body: &str) -> anyhow::Result<(String, String, String)> {
+    match serde_json::from_str(body)? {
+        SharesResponse::Error { error } => {
+            let message = format!("OneDrive returned {}: {}", error.code, error.message);
+            Err(match error.code.as_str() {
+                "itemNotFound" => DownloadError::NotFound(message),
+                "accessDenied" | "unauthenticated" => DownloadError::Auth(message),
+                "activityLimitReached" | "quotaLimitReached" => DownloadError::Quota(message),
+                _ => DownloadError::Network(message),
+            }
+            .into())
+        }
+        SharesResponse::Item(item) if item.folder.is_some() => {
+            Err(DownloadError::Unsupported(format!(
+                "OneDrive folder {} can't be downloaded as one file",
+                item.name
+            ))
+            .into())
+        }
+        SharesResponse::Item(item) => match item.download_url {
+            Some(download_url) => {
+                debug!("OneDrive file {} is {:?} bytes", &item.name, item.size);
+                Ok((item.id, item.name, download_url))
+            }
+            None => anyhow::bail!("OneDrive gave no download url for {}", item.name),
+        },
+    }
+}
+
+#[derive(Debug)]
+pub struct OneDriveMetadata {
+    url: String,
+    file_path: String,
+    out_path: Option<String>,
+}
+
+impl OneDriveMetadata {
+    pub fn new(url: String, file_path: String) -> Self {
+        Self {
+            url,
+            file_path,
+            out_path: None,
+        }
+    }
+}
+
+impl DownloadFiles<String> for OneDriveMetadata {
+    fn metadata_to_sql(self, store: &dyn MetadataStore, post: &RedditPost) -> anyhow::Result<()> {
+        store.create_file_row(&post.new_file_source(
+            &self.url,
+            &self.out_path.unwrap(),
+            PROVIDER,
+        ))?;
+
+        Ok(())
+    }
+
+    #[tokio::main]
+    async fn download(
+        mut self,
+        client: &HttpClient,
+        _hub: Option<&String>,
+        store: &dyn MetadataStore,
+        post: &RedditPost,
+    ) -> anyhow::Result<Option<String>> {
+        let body = client
+            .text(
+                client
+                    .send(client.get(format!("{}/{}/root", API_URL, share_id(&self.url))))
+                    .await?,
+            )
+            .await?;
+        let (id, file_name, download_url) = parse_drive_item(&body)?;
+
+        info!(
+            "Downloading {} from OneDrive url: {}",
+            &file_name, &self.url
+        );
+        let content = client
+            .bytes(
+                client
+                    .send(client.get(&download_url))
+                    .await?
+                    .error_for_status()?,
+            )
+            .await?;
+
+        self.out_path = Some(download_utils::save_download(
+            &self.file_path,
+            &download_utils::keyed_file_name(&file_name, &id),
+            &content,
+        )?);
+
+        self.metadata_to_sql(store, post)?;
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_id() {
+        assert_eq!(
+            "u!aHR0cHM6Ly9vbmVkcml2ZS5saXZlLmNvbS9yZWRpcj9yZXNpZD0xMjM0NSUyMTE2Jmlzc3VlZD0x",
+            share_id("https://onedrive.live.com/redir?resid=12345%2116&issued=1")
+        );
+        assert_eq!(
+            share_id("https://1drv.ms/u/s!AqR"),
+            share_id(" https://1drv.ms/u/s!AqR\n")
+        );
+    }
+
+    #[test]
+    fn test_parse_drive_item() {
+        let (id, file_name, download_url) = parse_drive_item(include_str!(
+            "../../test_samples/fixtures/onedrive/file.json"
+        ))
+        .unwrap();
+        let folder = parse_drive_item(include_str!(
+            "../../test_samples/fixtures/onedrive/folder.json"
+        ))
+        .unwrap_err();
+        let not_found = parse_drive_item(include_str!(
+            "../../test_samples/fixtures/onedrive/not_found.json"
+        ))
+        .unwrap_err();
+
+        assert_eq!("3F2A1B4C5D6E7F80!105", id);
+        assert_eq!("808 Mafia Kit.zip", file_name);
+        assert_eq!(
+            "https://public.am.files.1drv.com/y4mZ8kXb3Qp/808%20Mafia%20Kit.zip?download&psid=1",
+            download_url
+        );
+        assert_eq!(
+            "OneDrive folder Drum Kits can't be downloaded as one file",
+            folder.to_string()
+        );
+        assert_eq!(
+            "OneDrive returned itemNotFound: Item does not exist",
+            not_found.to_string()
+        );
+    }
+}
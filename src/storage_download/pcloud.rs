@@ -0,0 +1,282 @@
+use reqwest;
+use reqwest::header::CONTENT_TYPE;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tokio;
+use url::Url;
+
+use super::download_utils;
+use super::error::DownloadError;
+use crate::http::HttpClient;
+use crate::postgres_orm::MetadataStore;
+use crate::source::reddit::RedditPost;
+use crate::DownloadFiles;
+
+/// Name recorded as `file_source.provider` for archives from this host
+pub(super) const PROVIDER: &str = "pcloud";
+
+/// A public link code and the API host of the region it lives in. Links on
+/// `e.pcloud.link` are stored in the EU and only resolve on the EU API.
+#[derive(Debug, PartialEq)]
+struct PublicLink {
+    code: String,
+    api_host: &'static str,
+}
+
+impl PublicLink {
+    /// Accepts `.../publink/show?code=<code>` links on any pCloud host, as
+    /// well as the `my.pcloud.com/#page=publink&code=<code>` form.
+    fn parse(url: &str) -> anyhow::Result<Self> {
+        let parsed = Url::parse(url)?;
+        let host = parsed.host_str().unwrap_or_default();
+        if !host.contains("pcloud") {
+            anyhow::bail!("{} is not a pCloud link", url);
+        }
+
+        let from_query = parsed
+            .query_pairs()
+            .find(|(key, _)| key == "code")
+            .map(|(_, val)| val.to_string());
+        let from_fragment = parsed.fragment().and_then(|fragment| {
+            url::form_urlencoded::parse(fragment.as_bytes())
+                .find(|(key, _)| key == "code")
+                .map(|(_, val)| val.to_string())
+        });
+        let code = from_query
+            .or(from_fragment)
+            .filter(|val| !val.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("{} has no pCloud link code", url))?;
+
+        let api_host = if host.starts_with("e.") || host.starts_with("e1.") {
+            "eapi.pcloud.com"
+        } else {
+            "api.pcloud.com"
+        };
+
+        Ok(Self { code, api_host })
+    }
+
+    fn api_url(&self, method: &str) -> String {
+        format!("https://{}/{}?code={}", self.api_host, method, &self.code)
+    }
+}
+
+/// Every API response has a `result`, which is 0 on success and an error
+/// code otherwise, alongside an `error` message.
+#[derive(Deserialize, Debug)]
+struct ApiStatus {
+    result: i64,
+    error: Option<String>,
+}
+
+fn parse_response<T: DeserializeOwned>(body: &str) -> anyhow::Result<T> {
+    let status: ApiStatus = serde_json::from_str(body)?;
+    if status.result != 0 {
+        let message = format!(
+            "pCloud returned error {}: {}",
+            status.result,
+            status.error.unwrap_or_default()
+        );
+        // 7xxx are problems with the link itself, such as it being deleted
+        return Err(match status.result {
+            7000..=7999 => DownloadError::NotFound(message),
+            _ => DownloadError::Network(message),
+        }
+        .into());
+    }
+
+    Ok(serde_json::from_str(body)?)
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct LinkMetadata {
+    name: String,
+    isfolder: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct ShowLink {
+    metadata: LinkMetadata,
+}
+
+#[derive(Deserialize, Debug)]
+struct LinkDownload {
+    path: String,
+    hosts: Vec<String>,
+}
+
+impl LinkDownload {
+    fn url(&self) -> anyhow::Result<String> {
+        let host = self
+            .hosts
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("pCloud returned no download hosts"))?;
+
+        Ok(format!("https://{}{}", host, &self.path))
+    }
+}
+
+#[derive(Debug)]
+pub struct PCloudMetadata {
+    url: String,
+    file_path: String,
+    out_path: Option<String>,
+}
+
+impl PCloudMetadata {
+    pub fn new(url: String, file_path: String) -> Self {
+        Self {
+            url,
+            file_path,
+            out_path: None,
+        }
+    }
+}
+
+impl DownloadFiles<String> for PCloudMetadata {
+    fn metadata_to_sql(self, store: &dyn MetadataStore, post: &RedditPost) -> anyhow::Result<()> {
+        store.create_file_row(&post.new_file_source(
+            &self.url,
+            &self.out_path.unwrap(),
+            PROVIDER,
+        ))?;
+
+        Ok(())
+    }
+
+    #[tokio::main]
+    async fn download(
+        mut self,
+        client: &HttpClient,
+        _hub: Option<&String>,
+        store: &dyn MetadataStore,
+        post: &RedditPost,
+    ) -> anyhow::Result<Option<String>> {
+        let link = PublicLink::parse(&self.url)?;
+
+        let body = client
+            .text(client.send(client.get(link.api_url("showpublink"))).await?)
+            .await?;
+        let metadata = parse_response::<ShowLink>(&body)?.metadata;
+
+        // folders are zipped up on pCloud's side
+        let (download_url, file_name) = if metadata.isfolder {
+            let file_name = format!("{}.zip", &metadata.name);
+            (link.api_url("getpubzip"), file_name)
+        } else {
+            let body = client
+                .text(
+                    client
+                        .send(client.get(link.api_url("getpublinkdownload")))
+                        .await?,
+                )
+                .await?;
+            let download = parse_response::<LinkDownload>(&body)?;
+            (download.url()?, metadata.name)
+        };
+
+        info!("Downloading {} from pCloud url: {}", &file_name, &self.url);
+        let response = client
+            .send(client.get(&download_url))
+            .await?
+            .error_for_status()?;
+        let is_json = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|val| val.to_str().ok())
+            .is_some_and(|val| val.starts_with("application/json"));
+        if is_json {
+            // getpubzip reports errors in place of the zip
+            parse_response::<ApiStatus>(&client.text(response).await?)?;
+            anyhow::bail!("pCloud returned no file for {}", &self.url);
+        }
+        let content = client.bytes(response).await?;
+
+        self.out_path = Some(download_utils::save_download(
+            &self.file_path,
+            &download_utils::keyed_file_name(&file_name, &link.code),
+            &content,
+        )?);
+
+        self.metadata_to_sql(store, post)?;
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_link() {
+        let link = PublicLink {
+            code: "XZk2lQ0bM5l8xz0c7e8y1t3pbqa".to_string(),
+            api_host: "api.pcloud.com",
+        };
+
+        assert_eq!(
+            link,
+            PublicLink::parse(
+                "https://u.pcloud.link/publink/show?code=XZk2lQ0bM5l8xz0c7e8y1t3pbqa"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            link,
+            PublicLink::parse(
+                "https://my.pcloud.com/#page=publink&code=XZk2lQ0bM5l8xz0c7e8y1t3pbqa"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "eapi.pcloud.com",
+            PublicLink::parse("https://e.pcloud.link/publink/show?code=kZabc")
+                .unwrap()
+                .api_host
+        );
+        assert_eq!(
+            "https://api.pcloud.com/getpubzip?code=XZk2lQ0bM5l8xz0c7e8y1t3pbqa",
+            link.api_url("getpubzip")
+        );
+        assert!(PublicLink::parse("https://u.pcloud.link/publink/show").is_err());
+        assert!(PublicLink::parse("https://example.com/?code=abc").is_err());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let file = parse_response::<ShowLink>(include_str!(
+            "../../test_samples/fixtures/pcloud/showpublink_file.json"
+        ))
+        .unwrap();
+        let folder = parse_response::<ShowLink>(include_str!(
+            "../../test_samples/fixtures/pcloud/showpublink_folder.json"
+        ))
+        .unwrap();
+        let download = parse_response::<LinkDownload>(include_str!(
+            "../../test_samples/fixtures/pcloud/getpublinkdownload.json"
+        ))
+        .unwrap();
+        let error = parse_response::<ShowLink>(include_str!(
+            "../../test_samples/fixtures/pcloud/invalid_code.json"
+        ))
+        .unwrap_err();
+
+        assert_eq!(
+            LinkMetadata {
+                name: "Lofi Kit.zip".to_string(),
+                isfolder: false,
+            },
+            file.metadata
+        );
+        assert!(folder.metadata.isfolder);
+        assert_eq!(
+            "https://c166.pcloud.com/cBZ3Wt2kZ0XpGk8y7Z8xyQ7ZnfZ1XkZmVZ2XZ4Zk7Z/Lofi%20Kit.zip",
+            download.url().unwrap()
+        );
+        assert_eq!(
+            "pCloud returned error 7001: Invalid link \"code\".",
+            error.to_string()
+        );
+    }
+}
@@ -0,0 +1,303 @@
+use serde::Serialize;
+use std::path::Path;
+
+use super::download_utils::FilesInCompressed;
+use super::registry::{provider_for, ProviderRegistry};
+use super::resolver::ResolvedLink;
+use crate::dataset::manifest::sample_uri;
+use crate::query::{optional_cell, TableRow};
+
+/// What a crawl would do with a link, as told by `download --dry-run`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlanStatus {
+    /// Would be downloaded
+    New,
+    /// An archive with the name it would be saved under is already there
+    Downloaded,
+    /// Would be downloaded, but the host only gives the archive's name
+    /// once the download starts, so it can't be told whether it's there
+    Unknown,
+    /// No provider handles the link, or it isn't something to download
+    Skipped,
+    /// Following the link's redirects failed
+    Unresolved,
+}
+
+impl PlanStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlanStatus::New => "new",
+            PlanStatus::Downloaded => "downloaded",
+            PlanStatus::Unknown => "unknown",
+            PlanStatus::Skipped => "skipped",
+            PlanStatus::Unresolved => "unresolved",
+        }
+    }
+}
+
+/// A link found in a post, with the provider and file name it would be
+/// downloaded with. Nothing is fetched from the provider to work these out.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PlannedDownload {
+    pub post_id: String,
+    pub status: String,
+    pub provider: Option<String>,
+    pub file_name: Option<String>,
+    /// Where the link ends up once shorteners are followed, or the link as
+    /// posted when they couldn't be
+    pub url: String,
+    pub post_title: String,
+    /// Why the link would be skipped, or couldn't be followed
+    pub note: Option<String>,
+}
+
+impl PlannedDownload {
+    pub fn new(
+        post_id: &str,
+        post_title: &str,
+        posted_url: &str,
+        link: anyhow::Result<ResolvedLink>,
+        registry: &ProviderRegistry,
+        download_dir: &str,
+    ) -> Self {
+        let row = |status: PlanStatus, url: &str| Self {
+            post_id: post_id.to_string(),
+            status: status.as_str().to_string(),
+            provider: provider_for(url).map(|val| val.name().to_string()),
+            file_name: None,
+            url: url.to_string(),
+            post_title: post_title.to_string(),
+            note: None,
+        };
+
+        let link = match link {
+            Ok(val) => val,
+            Err(e) => {
+                return Self {
+                    note: Some(format!("{:#}", e)),
+                    ..row(PlanStatus::Unresolved, posted_url)
+                }
+            }
+        };
+        let url = link.final_url.as_str();
+
+        let download = match registry.resolve(url, post_title) {
+            Some(val) => val,
+            None => {
+                return Self {
+                    note: Some("No provider for this link".to_string()),
+                    ..row(PlanStatus::Skipped, url)
+                }
+            }
+        };
+        if let Some(reason) = download.skip_reason() {
+            return Self {
+                note: Some(reason.to_string()),
+                ..row(PlanStatus::Skipped, url)
+            };
+        }
+
+        let file_name = download.planned_file_name(url);
+        let status = match &file_name {
+            Some(val) if Path::new(download_dir).join(val).exists() => PlanStatus::Downloaded,
+            Some(_) => PlanStatus::New,
+            None => PlanStatus::Unknown,
+        };
+
+        Self {
+            file_name,
+            ..row(status, url)
+        }
+    }
+}
+
+impl TableRow for PlannedDownload {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "post_id",
+            "status",
+            "provider",
+            "file_name",
+            "url",
+            "post_title",
+            "note",
+        ]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.post_id.clone(),
+            self.status.clone(),
+            optional_cell(&self.provider),
+            optional_cell(&self.file_name),
+            self.url.clone(),
+            self.post_title.clone(),
+            optional_cell(&self.note),
+        ]
+    }
+}
+
+/// A sample `upload --dry-run` would insert, and the object it would be
+/// uploaded to
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PlannedUpload {
+    pub archive: String,
+    pub file: String,
+    pub instrument: String,
+    pub object: String,
+}
+
+impl PlannedUpload {
+    /// One row for each sample kept from each archive. Archives are only
+    /// listed, not extracted.
+    pub fn from_archives(
+        archives: &[FilesInCompressed],
+        bucket: &str,
+        unzipped_dir: &str,
+    ) -> Vec<Self> {
+        archives
+            .iter()
+            .flat_map(|archive| {
+                let archive_name = archive.archive_name();
+                archive.file_name_list.iter().zip(&archive.instrument).map(
+                    move |(file, instrument)| Self {
+                        archive: archive_name.clone(),
+                        file: file.clone(),
+                        instrument: instrument.clone(),
+                        object: sample_uri(Some(bucket), unzipped_dir, file),
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+impl TableRow for PlannedUpload {
+    fn headers() -> Vec<&'static str> {
+        vec!["archive", "file", "instrument", "object"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.archive.clone(),
+            self.file.clone(),
+            self.instrument.clone(),
+            self.object.clone(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::storage_download::download_utils::{get_files, SampleRules};
+    use std::fs;
+
+    fn resolved(url: &str) -> anyhow::Result<ResolvedLink> {
+        Ok(ResolvedLink {
+            original_url: url.to_string(),
+            final_url: url.to_string(),
+            hops: 0,
+        })
+    }
+
+    #[test]
+    fn test_planned_download() {
+        let folder = tempfile::tempdir().unwrap();
+        fs::write(folder.path().join("Drum Kit_abc.zip"), b"zip").unwrap();
+        let download_dir = folder.path().to_str().unwrap();
+        let registry = ProviderRegistry::new(download_dir.to_string());
+
+        let cases = [
+            (
+                "https://www.dropbox.com/s/abc/Drum%20Kit.zip?dl=0",
+                PlanStatus::Downloaded,
+                Some("Drum Kit_abc.zip"),
+            ),
+            (
+                "https://www.mediafire.com/file/k2lq0bm5/808%20Kit.zip/file",
+                PlanStatus::New,
+                Some("808 Kit.zip"),
+            ),
+            (
+                "https://mega.nz/file/Xq3lCz8k#key",
+                PlanStatus::Unknown,
+                None,
+            ),
+            (
+                "https://drive.google.com/drive/folders/1AbCdEfGhIjKlMnOp",
+                PlanStatus::Skipped,
+                None,
+            ),
+            (
+                "https://www.youtube.com/watch?v=abc",
+                PlanStatus::Skipped,
+                None,
+            ),
+        ];
+
+        for (url, status, file_name) in cases.iter() {
+            let row =
+                PlannedDownload::new("abc", "Kit", url, resolved(url), &registry, download_dir);
+
+            assert_eq!(status.as_str(), row.status, "{}", url);
+            assert_eq!(
+                file_name.map(|val| val.to_string()),
+                row.file_name,
+                "{}",
+                url
+            );
+        }
+
+        let row = PlannedDownload::new(
+            "abc",
+            "Kit",
+            "https://bit.ly/x",
+            Err(anyhow::anyhow!(
+                "https://bit.ly/x redirects more than 10 times"
+            )),
+            &registry,
+            download_dir,
+        );
+        assert_eq!("unresolved", row.status);
+        assert_eq!("https://bit.ly/x", row.url);
+        assert_eq!(
+            Some("https://bit.ly/x redirects more than 10 times".to_string()),
+            row.note
+        );
+    }
+
+    #[test]
+    fn test_planned_upload() {
+        let config = Config::default();
+        let archives = get_files(
+            "./test_samples",
+            &SampleRules {
+                accepted_extensions: &config.extraction.accepted_extensions,
+                instrument_keywords: &config.classification.instrument_keywords,
+            },
+        )
+        .unwrap();
+
+        let rows = PlannedUpload::from_archives(&archives, "chimecho_bucket", "unzipped");
+        let kick = rows
+            .iter()
+            .find(|val| val.file == "test/Nav_Champion (Kick).wav")
+            .unwrap();
+
+        assert_eq!(
+            archives
+                .iter()
+                .map(|val| val.file_name_list.len())
+                .sum::<usize>(),
+            rows.len()
+        );
+        assert_eq!("test.zip", kick.archive);
+        assert_eq!("kick", kick.instrument);
+        assert_eq!(
+            "gs://chimecho_bucket/unzipped/test/Nav_Champion (Kick).wav",
+            kick.object
+        );
+    }
+}
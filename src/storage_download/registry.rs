@@ -0,0 +1,352 @@
+use url::Url;
+
+use super::box_com::BoxMetadata;
+use super::dropbox::DropboxMetadata;
+use super::google_drive::GoogleDriveMetadata;
+use super::mediafire::MediaFireMetadata;
+use super::mega::MegaMetadata;
+use super::onedrive::OneDriveMetadata;
+use super::pcloud::PCloudMetadata;
+use super::wetransfer::WeTransferMetadata;
+use super::DownloadOptions;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProviderKind {
+    GoogleDrive,
+    Dropbox,
+    Mediafire,
+    Mega,
+    WeTransfer,
+    OneDrive,
+    Box,
+    PCloud,
+}
+
+impl ProviderKind {
+    /// Name recorded as `file_source.provider` and `download_jobs.provider`
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProviderKind::GoogleDrive => super::google_drive::PROVIDER,
+            ProviderKind::Dropbox => super::dropbox::PROVIDER,
+            ProviderKind::Mediafire => super::mediafire::PROVIDER,
+            ProviderKind::Mega => super::mega::PROVIDER,
+            ProviderKind::WeTransfer => super::wetransfer::PROVIDER,
+            ProviderKind::OneDrive => super::onedrive::PROVIDER,
+            ProviderKind::Box => super::box_com::PROVIDER,
+            ProviderKind::PCloud => super::pcloud::PROVIDER,
+        }
+    }
+}
+
+/// A host a provider serves links from. With `subdomains` set, any subdomain
+/// of `host` matches too (`www.dropbox.com` for `dropbox.com`). An empty
+/// `paths` matches every path, otherwise the path has to start with one of
+/// the prefixes.
+#[derive(Debug)]
+pub struct UrlPattern {
+    pub host: &'static str,
+    pub subdomains: bool,
+    pub paths: &'static [&'static str],
+}
+
+impl UrlPattern {
+    fn matches(&self, host: &str, path: &str) -> bool {
+        let host_matches = host == self.host
+            || (self.subdomains
+                && host
+                    .strip_suffix(self.host)
+                    .is_some_and(|val| val.ends_with('.')));
+
+        host_matches
+            && (self.paths.is_empty() || self.paths.iter().any(|val| path.starts_with(val)))
+    }
+}
+
+pub struct Provider {
+    pub kind: ProviderKind,
+    pub patterns: &'static [UrlPattern],
+}
+
+const fn host(host: &'static str) -> UrlPattern {
+    UrlPattern {
+        host,
+        subdomains: true,
+        paths: &[],
+    }
+}
+
+/// Every supported provider and the links it handles. A link is given to the
+/// first provider with a matching pattern.
+pub const PROVIDERS: &[Provider] = &[
+    Provider {
+        kind: ProviderKind::GoogleDrive,
+        patterns: &[
+            host("drive.google.com"),
+            UrlPattern {
+                host: "docs.google.com",
+                subdomains: false,
+                paths: &["/uc", "/file/", "/open"],
+            },
+        ],
+    },
+    Provider {
+        kind: ProviderKind::Dropbox,
+        patterns: &[
+            UrlPattern {
+                host: "dropbox.com",
+                subdomains: true,
+                paths: &["/s/", "/sh/", "/scl/"],
+            },
+            host("dl.dropboxusercontent.com"),
+        ],
+    },
+    Provider {
+        kind: ProviderKind::Mediafire,
+        patterns: &[host("mediafire.com")],
+    },
+    Provider {
+        kind: ProviderKind::Mega,
+        patterns: &[host("mega.nz"), host("mega.co.nz")],
+    },
+    Provider {
+        kind: ProviderKind::WeTransfer,
+        patterns: &[host("wetransfer.com"), host("we.tl")],
+    },
+    Provider {
+        kind: ProviderKind::OneDrive,
+        patterns: &[host("1drv.ms"), host("onedrive.live.com")],
+    },
+    Provider {
+        kind: ProviderKind::Box,
+        patterns: &[UrlPattern {
+            host: "box.com",
+            subdomains: true,
+            paths: &["/s/", "/v/"],
+        }],
+    },
+    Provider {
+        kind: ProviderKind::PCloud,
+        patterns: &[host("pcloud.link"), host("my.pcloud.com")],
+    },
+];
+
+/// Provider whose patterns match a link, if any.
+pub fn provider_for(url: &str) -> Option<ProviderKind> {
+    let parsed = Url::parse(url.trim()).ok()?;
+    let host = parsed.host_str()?.to_lowercase();
+
+    PROVIDERS
+        .iter()
+        .find(|provider| {
+            provider
+                .patterns
+                .iter()
+                .any(|pattern| pattern.matches(&host, parsed.path()))
+        })
+        .map(|provider| provider.kind)
+}
+
+/// Builds the downloads for links posted to Reddit, saving them under
+/// `file_path`.
+pub struct ProviderRegistry {
+    file_path: String,
+}
+
+impl ProviderRegistry {
+    pub fn new(file_path: String) -> Self {
+        Self { file_path }
+    }
+
+    /// Download for a link, or `None` when no provider handles it. `title` is
+    /// the title of the post the link is from, which some providers name the
+    /// archive after.
+    pub fn resolve(&self, url: &str, title: &str) -> Option<DownloadOptions> {
+        let file_path = self.file_path.clone();
+        let url = url.trim().to_string();
+
+        let download = match provider_for(&url)? {
+            ProviderKind::GoogleDrive => match GoogleDriveMetadata::new(&url, file_path) {
+                Ok(val) => DownloadOptions::GoogleDrive(val),
+                Err(e) => {
+                    warn!("Skipping Google Drive link: {}", e);
+                    return None;
+                }
+            },
+            ProviderKind::Dropbox => {
+                DownloadOptions::Dropbox(DropboxMetadata::new(url, title.to_string(), file_path))
+            }
+            ProviderKind::Mediafire => {
+                DownloadOptions::Mediafire(MediaFireMetadata::new(url, file_path))
+            }
+            ProviderKind::Mega => DownloadOptions::Mega(MegaMetadata::new(url, file_path)),
+            ProviderKind::WeTransfer => {
+                DownloadOptions::WeTransfer(WeTransferMetadata::new(url, file_path))
+            }
+            ProviderKind::OneDrive => {
+                DownloadOptions::OneDrive(OneDriveMetadata::new(url, file_path))
+            }
+            ProviderKind::Box => DownloadOptions::Box(BoxMetadata::new(url, file_path)),
+            ProviderKind::PCloud => DownloadOptions::PCloud(PCloudMetadata::new(url, file_path)),
+        };
+
+        Some(download)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_for() {
+        let cases = [
+            // google drive
+            (
+                "https://drive.google.com/file/d/1-cgL6_YlB8gOVgoLrwCnP19OqHt34WVj/view?usp=sharing",
+                Some(ProviderKind::GoogleDrive),
+            ),
+            (
+                "https://drive.google.com/drive/u/4/folders/1Xw-HoupNY75aYB1Hc0zLifFxu3g5RQGX",
+                Some(ProviderKind::GoogleDrive),
+            ),
+            (
+                "https://docs.google.com/uc?export=download&id=1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ",
+                Some(ProviderKind::GoogleDrive),
+            ),
+            (
+                "https://docs.google.com/file/d/1K4fCarvyqHrkE08H-b2B-fgaOwMRlSkJ/edit",
+                Some(ProviderKind::GoogleDrive),
+            ),
+            ("https://docs.google.com/spreadsheets/d/1abc/edit", None),
+            // dropbox
+            (
+                "https://www.dropbox.com/s/abc123/Drum%20Kit.zip?dl=0",
+                Some(ProviderKind::Dropbox),
+            ),
+            (
+                "https://dropbox.com/sh/hkgtorveen2jvh6/AAAf0TStSQD_9PAOTjubPU1Ma?dl=0",
+                Some(ProviderKind::Dropbox),
+            ),
+            (
+                "https://www.dropbox.com/scl/fi/x1y2z3/kit.zip?rlkey=abc&dl=0",
+                Some(ProviderKind::Dropbox),
+            ),
+            (
+                "https://dl.dropboxusercontent.com/s/abc123/kit.zip",
+                Some(ProviderKind::Dropbox),
+            ),
+            ("https://www.dropbox.com/home", None),
+            // mediafire
+            (
+                "https://www.mediafire.com/file/k2lq0bm5l8xz0c7/Kit.zip/file",
+                Some(ProviderKind::Mediafire),
+            ),
+            (
+                "https://mediafire.com/folder/q8w7e6r5t4y3u/Drum+Kits",
+                Some(ProviderKind::Mediafire),
+            ),
+            (
+                "https://app.mediafire.com/folder/q8w7e6r5t4y3u",
+                Some(ProviderKind::Mediafire),
+            ),
+            (
+                "http://www.mediafire.com/?k2lq0bm5l8xz0c7",
+                Some(ProviderKind::Mediafire),
+            ),
+            // mega
+            (
+                "https://mega.nz/file/Xq3lCz8k#AQIDBAUGBwgJCgsMDQ4PEBESExQVFhcYGRobHB0eHyA",
+                Some(ProviderKind::Mega),
+            ),
+            (
+                "https://mega.co.nz/#!Xq3lCz8k!AQIDBAUGBwgJCgsMDQ4PEBESExQ",
+                Some(ProviderKind::Mega),
+            ),
+            // wetransfer
+            (
+                "https://wetransfer.com/downloads/4b3c1d2e5f6a7b8c9d0e/7e6d5c",
+                Some(ProviderKind::WeTransfer),
+            ),
+            ("https://we.tl/t-k2lQ0bM5l8", Some(ProviderKind::WeTransfer)),
+            (
+                "https://drumkits.wetransfer.com/downloads/4b3c1d/7e6d5c",
+                Some(ProviderKind::WeTransfer),
+            ),
+            // onedrive
+            (
+                "https://1drv.ms/u/s!AqRkZ3gM5l8xgQ7e8y1t3pbqa9v4",
+                Some(ProviderKind::OneDrive),
+            ),
+            (
+                "https://onedrive.live.com/?cid=3F2A1B4C5D6E7F80&id=3F2A1B4C5D6E7F80%21105&authkey=!AB",
+                Some(ProviderKind::OneDrive),
+            ),
+            // box
+            (
+                "https://app.box.com/s/k2lq0bm5l8xz0c7e8y1t3pbqa9v4r6w",
+                Some(ProviderKind::Box),
+            ),
+            (
+                "https://berklee.app.box.com/v/drumkit",
+                Some(ProviderKind::Box),
+            ),
+            ("https://www.box.com/pricing", None),
+            // pcloud
+            (
+                "https://u.pcloud.link/publink/show?code=XZk2lQ0bM5l8xz0c7e8y1t3pbqa",
+                Some(ProviderKind::PCloud),
+            ),
+            (
+                "https://e.pcloud.link/publink/show?code=kZabc",
+                Some(ProviderKind::PCloud),
+            ),
+            (
+                "https://my.pcloud.com/#page=publink&code=XZk2lQ0bM5l8",
+                Some(ProviderKind::PCloud),
+            ),
+            // everything else
+            ("https://WWW.DROPBOX.COM/s/abc123/kit.zip", Some(ProviderKind::Dropbox)),
+            ("https://notdropbox.com/s/abc123/kit.zip", None),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ", None),
+            ("https://www.reddit.com/r/Drumkits/comments/abc/kit/", None),
+            ("not a url", None),
+        ];
+
+        for (url, expected) in cases.iter() {
+            assert_eq!(*expected, provider_for(url), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        // building a download doesn't touch the network, so routing a link
+        // to its provider can be checked offline
+        let registry = ProviderRegistry::new("data".to_string());
+
+        assert!(matches!(
+            registry.resolve(
+                "https://www.mediafire.com/file/k2lq0bm5l8xz0c7/Kit.zip/file",
+                "Kit"
+            ),
+            Some(DownloadOptions::Mediafire(_))
+        ));
+        assert!(matches!(
+            registry.resolve(
+                "https://www.mediafire.com/folder/q8w7e6r5t4y3u/Kits",
+                "Kits"
+            ),
+            Some(DownloadOptions::Mediafire(_))
+        ));
+        assert!(matches!(
+            registry.resolve(" https://www.dropbox.com/s/abc123/kit.zip?dl=0\n", "Kit"),
+            Some(DownloadOptions::Dropbox(_))
+        ));
+        assert!(matches!(
+            registry.resolve("https://we.tl/t-k2lQ0bM5l8", "Kit"),
+            Some(DownloadOptions::WeTransfer(_))
+        ));
+        assert!(registry
+            .resolve("https://www.youtube.com/watch?v=dQw4w9WgXcQ", "Kit")
+            .is_none());
+    }
+}
@@ -0,0 +1,286 @@
+use crate::http::HttpClient;
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest;
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use tokio;
+use url::Url;
+
+use super::registry::provider_for;
+
+/// Redirects followed before a link is given up on, unless
+/// `[providers] max_hops` says otherwise
+pub const MAX_HOPS: usize = 10;
+
+/// Hosts that never lead on to a download, so aren't worth a request. Reddit's
+/// outbound redirects are unwrapped before this is checked.
+const SKIPPED_HOSTS: &[&str] = &[
+    "reddit.com",
+    "redd.it",
+    "imgur.com",
+    "youtube.com",
+    "youtu.be",
+];
+
+/// Where a posted link ended up after its redirects were followed.
+#[derive(Debug, PartialEq)]
+pub struct ResolvedLink {
+    pub original_url: String,
+    pub final_url: String,
+    pub hops: usize,
+}
+
+/// Follows shorteners (bit.ly, tinyurl and the like) and Reddit's outbound
+/// redirects until the link lands on a provider host, or stops redirecting.
+pub struct LinkResolver {
+    client: HttpClient,
+    max_hops: usize,
+}
+
+impl LinkResolver {
+    pub fn new(client: &HttpClient, max_hops: usize) -> anyhow::Result<Self> {
+        // redirects are followed by hand so that each hop can be checked
+        // against the providers before anything is fetched from it
+        Ok(Self {
+            client: client.without_redirects()?,
+            max_hops,
+        })
+    }
+
+    #[tokio::main]
+    pub async fn resolve(&self, url: &str) -> anyhow::Result<ResolvedLink> {
+        let original_url = url.trim().to_string();
+        let mut current = original_url.clone();
+        let mut hops = 0;
+
+        loop {
+            let parsed = Url::parse(&current)?;
+            let next = match reddit_outbound_target(&parsed) {
+                Some(target) => Some(target),
+                None if provider_for(&current).is_some() || is_skipped(&parsed) => None,
+                None => fetch_next_hop(&self.client, &parsed).await?,
+            };
+
+            match next {
+                Some(next) if hops < self.max_hops => {
+                    debug!("{} redirects to {}", &current, &next);
+                    current = next;
+                    hops += 1;
+                }
+                Some(_) => anyhow::bail!(
+                    "{} redirects more than {} times",
+                    &original_url,
+                    self.max_hops
+                ),
+                None => {
+                    return Ok(ResolvedLink {
+                        original_url,
+                        final_url: current,
+                        hops,
+                    })
+                }
+            }
+        }
+    }
+}
+
+fn is_skipped(url: &Url) -> bool {
+    let host = url.host_str().unwrap_or_default().to_lowercase();
+
+    !matches!(url.scheme(), "http" | "https")
+        || SKIPPED_HOSTS.iter().any(|val| {
+            host == *val
+                || host
+                    .strip_suffix(val)
+                    .is_some_and(|rest| rest.ends_with('.'))
+        })
+}
+
+/// Reddit wraps outbound links as `out.reddit.com/<thing>?url=<link>`, which
+/// can be unwrapped without a request.
+fn reddit_outbound_target(url: &Url) -> Option<String> {
+    if url.host_str()? != "out.reddit.com" {
+        return None;
+    }
+
+    url.query_pairs()
+        .find(|(key, _)| key == "url")
+        .map(|(_, val)| val.to_string())
+        .filter(|val| !val.is_empty())
+}
+
+/// Next link in the chain, from either a `Location` header or an HTML
+/// meta refresh. `None` once the page is a destination of its own.
+async fn fetch_next_hop(client: &HttpClient, url: &Url) -> anyhow::Result<Option<String>> {
+    let response = client.send(client.get(url.clone())).await?;
+
+    if response.status().is_redirection() {
+        return Ok(response
+            .headers()
+            .get(LOCATION)
+            .and_then(|val| val.to_str().ok())
+            .and_then(|val| url.join(val).ok())
+            .map(String::from));
+    }
+
+    let is_html = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|val| val.to_str().ok())
+        .is_some_and(|val| val.starts_with("text/html"));
+    if !response.status().is_success() || !is_html {
+        return Ok(None);
+    }
+
+    let html = client.text(response).await?;
+    Ok(meta_refresh_target(&html)
+        .and_then(|val| url.join(&val).ok())
+        .map(String::from))
+}
+
+/// Target of a `<meta http-equiv="refresh" content="0; url=...">` tag.
+fn meta_refresh_target(html: &str) -> Option<String> {
+    lazy_static! {
+        static ref META: Regex = Regex::new(r"(?is)<meta\b[^>]*>").unwrap();
+        static ref REFRESH: Regex = Regex::new(r#"(?i)http-equiv\s*=\s*["']?refresh\b"#).unwrap();
+        static ref CONTENT: Regex =
+            Regex::new(r#"(?is)content\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+        static ref TARGET: Regex =
+            Regex::new(r#"(?is)^\s*\d*(?:\.\d*)?\s*[;,]\s*url\s*=\s*['"]?([^'"]+)"#).unwrap();
+    }
+
+    META.find_iter(html)
+        .map(|tag| tag.as_str())
+        .filter(|tag| REFRESH.is_match(tag))
+        .find_map(|tag| {
+            let caps = CONTENT.captures(tag)?;
+            let content = caps.get(1).or_else(|| caps.get(2))?.as_str();
+            let target = TARGET.captures(content)?.get(1)?.as_str().trim();
+
+            Some(target.replace("&amp;", "&")).filter(|val| !val.is_empty())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{test_server, HttpConfig};
+
+    /// Serves `(path, status line and headers, body)` responses, and 404s
+    /// for any other path
+    fn serve(responses: &'static [(&'static str, &'static str, &'static str)]) -> String {
+        test_server::serve(move |path| {
+            Some(
+                responses
+                    .iter()
+                    .find(|(val, _, _)| *val == path)
+                    .map(|(_, head, body)| (*head, *body))
+                    .unwrap_or(("HTTP/1.1 404 Not Found", "")),
+            )
+        })
+    }
+
+    #[test]
+    fn test_meta_refresh_target() {
+        let cases = [
+            (
+                r#"<meta http-equiv="refresh" content="0; url=https://www.dropbox.com/s/abc/kit.zip">"#,
+                Some("https://www.dropbox.com/s/abc/kit.zip"),
+            ),
+            (
+                r#"<META CONTENT='0;URL=https://mega.nz/file/abc#key' HTTP-EQUIV='Refresh'>"#,
+                Some("https://mega.nz/file/abc#key"),
+            ),
+            (
+                r#"<meta http-equiv=refresh content="5; url='/next?a=1&amp;b=2'" />"#,
+                Some("/next?a=1&b=2"),
+            ),
+            (r#"<meta http-equiv="refresh" content="30">"#, None),
+            (
+                r#"<meta name="description" content="0; url=https://a.com">"#,
+                None,
+            ),
+            ("<html><body>no refresh here</body></html>", None),
+        ];
+
+        for (html, expected) in cases.iter() {
+            assert_eq!(
+                expected.map(String::from),
+                meta_refresh_target(html),
+                "{}",
+                html
+            );
+        }
+    }
+
+    #[test]
+    fn test_reddit_outbound_target() {
+        let outbound = Url::parse(
+            "https://out.reddit.com/t3_abc123?url=https%3A%2F%2Fbit.ly%2F3kXyZ&token=AQAA&app_name=reddit.com",
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some("https://bit.ly/3kXyZ".to_string()),
+            reddit_outbound_target(&outbound)
+        );
+        assert_eq!(
+            None,
+            reddit_outbound_target(&Url::parse("https://out.reddit.com/t3_abc123").unwrap())
+        );
+        assert_eq!(
+            None,
+            reddit_outbound_target(&Url::parse("https://bit.ly/?url=https://a.com").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let address = serve(&[
+            (
+                "/short",
+                "HTTP/1.1 301 Moved Permanently\r\nLocation: /landing",
+                "",
+            ),
+            (
+                "/landing",
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8",
+                r#"<meta http-equiv="refresh" content="0; url=https://www.dropbox.com/s/abc/kit.zip">"#,
+            ),
+            ("/loop", "HTTP/1.1 302 Found\r\nLocation: /loop", ""),
+            (
+                "/page",
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html",
+                "<p>a page</p>",
+            ),
+        ]);
+        let client = HttpClient::new(HttpConfig::default()).unwrap();
+        let resolver = LinkResolver::new(&client, MAX_HOPS).unwrap();
+
+        let short = format!("{}/short", address);
+        assert_eq!(
+            ResolvedLink {
+                original_url: short.clone(),
+                final_url: "https://www.dropbox.com/s/abc/kit.zip".to_string(),
+                hops: 2,
+            },
+            resolver.resolve(&short).unwrap()
+        );
+
+        let page = format!("{}/page", address);
+        assert_eq!(page, resolver.resolve(&page).unwrap().final_url);
+
+        assert_eq!(
+            format!("{}/loop redirects more than 3 times", address),
+            LinkResolver::new(&client, 3)
+                .unwrap()
+                .resolve(&format!("{}/loop", address))
+                .unwrap_err()
+                .to_string()
+        );
+
+        // provider links are never fetched
+        let provider = "https://mega.nz/file/Xq3lCz8k#key";
+        assert_eq!(0, resolver.resolve(provider).unwrap().hops);
+    }
+}
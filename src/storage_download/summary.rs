@@ -0,0 +1,221 @@
+use indicatif::HumanBytes;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use super::error::DownloadError;
+use super::jobs::JobStatus;
+use crate::query::TableRow;
+
+/// How the jobs of one provider went in a crawl, printed once it's over.
+/// The `all` row adds up every provider, with the time the whole run took.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ProviderSummaryRow {
+    pub provider: String,
+    pub succeeded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Failures by error kind, such as `network 2, not_found 1`
+    pub failed_by_reason: String,
+    /// Bytes of the files downloaded, including attempts that failed
+    pub bytes: u64,
+    pub elapsed_seconds: f64,
+}
+
+impl TableRow for ProviderSummaryRow {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "provider",
+            "succeeded",
+            "skipped",
+            "failed",
+            "failed_by_reason",
+            "bytes",
+            "elapsed",
+        ]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.provider.clone(),
+            self.succeeded.to_string(),
+            self.skipped.to_string(),
+            self.failed.to_string(),
+            self.failed_by_reason.clone(),
+            HumanBytes(self.bytes).to_string(),
+            format!("{:.1}s", self.elapsed_seconds),
+        ]
+    }
+}
+
+#[derive(Default)]
+struct ProviderTally {
+    succeeded: usize,
+    skipped: usize,
+    failed: usize,
+    reasons: BTreeMap<&'static str, usize>,
+    bytes: u64,
+    elapsed: Duration,
+}
+
+impl ProviderTally {
+    fn add(&mut self, other: &ProviderTally) {
+        self.succeeded += other.succeeded;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+        for (reason, count) in &other.reasons {
+            *self.reasons.entry(reason).or_insert(0) += count;
+        }
+        self.bytes += other.bytes;
+        self.elapsed += other.elapsed;
+    }
+
+    fn to_row(&self, provider: &str, elapsed: Duration) -> ProviderSummaryRow {
+        ProviderSummaryRow {
+            provider: provider.to_string(),
+            succeeded: self.succeeded,
+            skipped: self.skipped,
+            failed: self.failed,
+            failed_by_reason: self
+                .reasons
+                .iter()
+                .map(|(reason, count)| format!("{} {}", reason, count))
+                .collect::<Vec<_>>()
+                .join(", "),
+            bytes: self.bytes,
+            elapsed_seconds: elapsed.as_secs_f64(),
+        }
+    }
+}
+
+/// Adds up how each attempt at a job went, by provider
+#[derive(Default)]
+pub struct RunSummary {
+    providers: BTreeMap<String, ProviderTally>,
+}
+
+impl RunSummary {
+    /// Records one attempt at a job. Jobs put back in the queue only add
+    /// their bytes and time, as they are counted once they are done with.
+    pub fn record(
+        &mut self,
+        provider: Option<&str>,
+        status: JobStatus,
+        error: Option<&DownloadError>,
+        bytes: u64,
+        elapsed: Duration,
+    ) {
+        let tally = self
+            .providers
+            .entry(provider.unwrap_or("unknown").to_string())
+            .or_default();
+        match status {
+            JobStatus::Done => tally.succeeded += 1,
+            JobStatus::Skipped => tally.skipped += 1,
+            JobStatus::Failed => {
+                tally.failed += 1;
+                if let Some(error) = error {
+                    *tally.reasons.entry(error.kind()).or_insert(0) += 1;
+                }
+            }
+            _ => {}
+        }
+        tally.bytes += bytes;
+        tally.elapsed += elapsed;
+    }
+
+    /// A row per provider, then the `all` row, which takes `run_elapsed`
+    /// as its time
+    pub fn rows(&self, run_elapsed: Duration) -> Vec<ProviderSummaryRow> {
+        let mut all = ProviderTally::default();
+        let mut rows: Vec<ProviderSummaryRow> = self
+            .providers
+            .iter()
+            .map(|(provider, tally)| {
+                all.add(tally);
+                tally.to_row(provider, tally.elapsed)
+            })
+            .collect();
+        rows.push(all.to_row("all", run_elapsed));
+
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_summary() {
+        let mut summary = RunSummary::default();
+        let network = DownloadError::Network("timed out".to_string());
+        let not_found = DownloadError::NotFound("gone".to_string());
+        let secs = Duration::from_secs;
+
+        let attempts = [
+            (Some("dropbox"), JobStatus::Done, None, 100, secs(2)),
+            (
+                Some("dropbox"),
+                JobStatus::Pending,
+                Some(&network),
+                40,
+                secs(1),
+            ),
+            (
+                Some("dropbox"),
+                JobStatus::Failed,
+                Some(&network),
+                0,
+                secs(1),
+            ),
+            (
+                Some("mega"),
+                JobStatus::Failed,
+                Some(&not_found),
+                0,
+                secs(1),
+            ),
+            (Some("mega"), JobStatus::Done, None, 60, secs(3)),
+            (None, JobStatus::Skipped, None, 0, secs(0)),
+        ];
+        for (provider, status, error, bytes, elapsed) in attempts.iter() {
+            summary.record(*provider, *status, *error, *bytes, *elapsed);
+        }
+
+        let rows = summary.rows(secs(10));
+        let row = |provider: &str| rows.iter().find(|val| val.provider == provider).unwrap();
+
+        assert_eq!(
+            vec!["dropbox", "mega", "unknown", "all"],
+            rows.iter()
+                .map(|val| val.provider.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ProviderSummaryRow {
+                provider: "dropbox".to_string(),
+                succeeded: 1,
+                skipped: 0,
+                failed: 1,
+                failed_by_reason: "network 1".to_string(),
+                bytes: 140,
+                elapsed_seconds: 4.0,
+            },
+            *row("dropbox")
+        );
+        assert_eq!(
+            ProviderSummaryRow {
+                provider: "all".to_string(),
+                succeeded: 2,
+                skipped: 1,
+                failed: 2,
+                failed_by_reason: "network 1, not_found 1".to_string(),
+                bytes: 200,
+                elapsed_seconds: 10.0,
+            },
+            *row("all")
+        );
+        assert_eq!(1, row("unknown").skipped);
+    }
+}
@@ -0,0 +1,253 @@
+use reqwest;
+use reqwest::header::CONTENT_DISPOSITION;
+use serde::{Deserialize, Serialize};
+use soup::prelude::*;
+use tokio;
+use url::Url;
+
+use super::download_utils;
+use super::error::DownloadError;
+use crate::http::HttpClient;
+use crate::postgres_orm::MetadataStore;
+use crate::source::reddit::RedditPost;
+use crate::DownloadFiles;
+
+/// Name recorded as `file_source.provider` for archives from this host
+pub(super) const PROVIDER: &str = "wetransfer";
+
+/// Ids in a `wetransfer.com/downloads/<transfer id>[/<recipient id>]/<security hash>`
+/// link. Links sent by email have a recipient id, public links don't.
+#[derive(Debug, PartialEq)]
+struct TransferLink {
+    transfer_id: String,
+    recipient_id: Option<String>,
+    security_hash: String,
+}
+
+impl TransferLink {
+    fn parse(url: &str) -> anyhow::Result<Self> {
+        let parsed = Url::parse(url)?;
+        let segments: Vec<&str> = parsed
+            .path_segments()
+            .map(|val| val.filter(|segment| !segment.is_empty()).collect())
+            .unwrap_or_default();
+
+        match segments.as_slice() {
+            ["downloads", transfer_id, security_hash] => Ok(Self {
+                transfer_id: transfer_id.to_string(),
+                recipient_id: None,
+                security_hash: security_hash.to_string(),
+            }),
+            ["downloads", transfer_id, recipient_id, security_hash] => Ok(Self {
+                transfer_id: transfer_id.to_string(),
+                recipient_id: Some(recipient_id.to_string()),
+                security_hash: security_hash.to_string(),
+            }),
+            _ => anyhow::bail!("{} is not a WeTransfer download link", url),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct DownloadRequest<'a> {
+    security_hash: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recipient_id: Option<&'a str>,
+    intent: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+struct DownloadResponse {
+    direct_link: Option<String>,
+    message: Option<String>,
+}
+
+/// The transfer page carries the CSRF token the download API wants back.
+fn parse_csrf_token(html: &str) -> Option<String> {
+    let soup = Soup::new(html);
+
+    soup.tag("meta")
+        .attr("name", "csrf-token")
+        .find()
+        .and_then(|val| val.get("content"))
+}
+
+fn parse_direct_link(body: &str) -> anyhow::Result<String> {
+    let response: DownloadResponse = serde_json::from_str(body)?;
+
+    match response.direct_link {
+        Some(val) => Ok(val),
+        // expired and deleted transfers
+        None => Err(DownloadError::NotFound(format!(
+            "WeTransfer returned no download link: {}",
+            response.message.as_deref().unwrap_or(body)
+        ))
+        .into()),
+    }
+}
+
+/// Last path segment of the direct link, which is the transfer's file name
+/// (or a zip named after the transfer when it has several files).
+fn file_name_from_link(direct_link: &str) -> Option<String> {
+    let parsed = Url::parse(direct_link).ok()?;
+    let name = parsed.path_segments()?.next_back()?;
+
+    percent_encoding::percent_decode_str(name)
+        .decode_utf8()
+        .ok()
+        .map(|val| val.to_string())
+        .filter(|val| !val.is_empty())
+}
+
+#[derive(Debug)]
+pub struct WeTransferMetadata {
+    url: String,
+    file_path: String,
+    out_path: Option<String>,
+}
+
+impl WeTransferMetadata {
+    pub fn new(url: String, file_path: String) -> Self {
+        Self {
+            url,
+            file_path,
+            out_path: None,
+        }
+    }
+}
+
+impl DownloadFiles<String> for WeTransferMetadata {
+    fn metadata_to_sql(self, store: &dyn MetadataStore, post: &RedditPost) -> anyhow::Result<()> {
+        store.create_file_row(&post.new_file_source(
+            &self.url,
+            &self.out_path.unwrap(),
+            PROVIDER,
+        ))?;
+
+        Ok(())
+    }
+
+    #[tokio::main]
+    async fn download(
+        mut self,
+        client: &HttpClient,
+        _hub: Option<&String>,
+        store: &dyn MetadataStore,
+        post: &RedditPost,
+    ) -> anyhow::Result<Option<String>> {
+        // we.tl short links redirect to the transfer page, which also sets the
+        // session cookie that goes with the CSRF token
+        let page = client
+            .send(client.get(&self.url))
+            .await?
+            .error_for_status()?;
+        let link = TransferLink::parse(page.url().as_str())?;
+        let csrf_token = parse_csrf_token(&client.text(page).await?);
+
+        let mut request = client
+            .post(format!(
+                "https://wetransfer.com/api/v4/transfers/{}/download",
+                &link.transfer_id
+            ))
+            .header("x-requested-with", "XMLHttpRequest")
+            .json(&DownloadRequest {
+                security_hash: &link.security_hash,
+                recipient_id: link.recipient_id.as_deref(),
+                intent: "entire_transfer",
+            });
+        if let Some(token) = &csrf_token {
+            request = request.header("x-csrf-token", token);
+        }
+        let direct_link = parse_direct_link(&client.text(client.send(request).await?).await?)?;
+
+        info!(
+            "Downloading WeTransfer transfer {} from url: {}",
+            &link.transfer_id, &self.url
+        );
+        let response = client
+            .send(client.get(&direct_link))
+            .await?
+            .error_for_status()?;
+        // transfers of several files are all named after the transfer, which
+        // is often just `WeTransfer.zip`
+        let file_name = response
+            .headers()
+            .get(CONTENT_DISPOSITION)
+            .and_then(|val| val.to_str().ok())
+            .and_then(download_utils::content_disposition_file_name)
+            .or_else(|| file_name_from_link(&direct_link))
+            .map(|val| download_utils::keyed_file_name(&val, &link.transfer_id))
+            .unwrap_or_else(|| format!("{}.zip", &link.transfer_id));
+        let content = client.bytes(response).await?;
+
+        self.out_path = Some(download_utils::save_download(
+            &self.file_path,
+            &file_name,
+            &content,
+        )?);
+
+        self.metadata_to_sql(store, post)?;
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_link() {
+        assert_eq!(
+            TransferLink {
+                transfer_id: "4b3c1d2e5f6a7b8c9d0e1f2a3b4c5d6e20221105101010".to_string(),
+                recipient_id: None,
+                security_hash: "7e6d5c".to_string(),
+            },
+            TransferLink::parse(
+                "https://wetransfer.com/downloads/4b3c1d2e5f6a7b8c9d0e1f2a3b4c5d6e20221105101010/7e6d5c"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            Some("a1b2c3".to_string()),
+            TransferLink::parse("https://wetransfer.com/downloads/4b3c1d/a1b2c3/7e6d5c?utm=x")
+                .unwrap()
+                .recipient_id
+        );
+        assert!(TransferLink::parse("https://we.tl/t-k2lQ0bM5l8").is_err());
+    }
+
+    #[test]
+    fn test_parse_transfer_page() {
+        let html = include_str!("../../test_samples/fixtures/wetransfer/transfer_page.html");
+
+        assert_eq!(
+            Some("k3Yb0pM0p7rQ1nVtD0bS9rL2Jm8u7w5yVY4dQ1vXq2sW3eR4tY5uI6oP7aS8dF9g==".to_string()),
+            parse_csrf_token(html)
+        );
+        assert_eq!(None, parse_csrf_token("<html></html>"));
+    }
+
+    #[test]
+    fn test_parse_direct_link() {
+        let direct_link = parse_direct_link(include_str!(
+            "../../test_samples/fixtures/wetransfer/download.json"
+        ))
+        .unwrap();
+        let error = parse_direct_link(include_str!(
+            "../../test_samples/fixtures/wetransfer/expired.json"
+        ))
+        .unwrap_err();
+
+        assert!(direct_link.starts_with("https://download.wetransfer.com/eugv/"));
+        assert_eq!(
+            Some("WeTransfer_Drum_Kit.zip".to_string()),
+            file_name_from_link(&direct_link)
+        );
+        assert_eq!(
+            "WeTransfer returned no download link: This transfer has expired and is no longer available",
+            error.to_string()
+        );
+    }
+}